@@ -30,13 +30,19 @@ pub fn wasm_new(tree_height: usize, zkey: Uint8Array, vk: Uint8Array) -> *mut RL
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
 #[wasm_bindgen(js_name = getSerializedRLNWitness)]
-pub fn wasm_get_serialized_rln_witness(ctx: *mut RLNWrapper, input: Uint8Array) -> Uint8Array {
+pub fn wasm_get_serialized_rln_witness(
+    ctx: *mut RLNWrapper,
+    input: Uint8Array,
+) -> Result<Uint8Array, String> {
     let wrapper = unsafe { &mut *ctx };
-    let rln_witness = wrapper
+    if let Ok(rln_witness) = wrapper
         .instance
-        .get_serialized_rln_witness(&input.to_vec()[..]);
-
-    Uint8Array::from(&rln_witness[..])
+        .get_serialized_rln_witness(&input.to_vec()[..])
+    {
+        Ok(Uint8Array::from(&rln_witness[..]))
+    } else {
+        Err("could not get serialized rln witness".into())
+    }
 }
 
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
@@ -305,7 +311,7 @@ mod tests {
         let serialized_message = Uint8Array::from(&serialized_vec[..]);
 
         let serialized_rln_witness =
-            wasm_get_serialized_rln_witness(rln_instance, serialized_message);
+            wasm_get_serialized_rln_witness(rln_instance, serialized_message).unwrap();
 
         // Obtaining inputs that should be sent to circom witness calculator
         let json_inputs = rln_witness_to_json(rln_instance, serialized_rln_witness.clone());
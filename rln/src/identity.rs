@@ -0,0 +1,261 @@
+// This crate defines the RLN identity, i.e. the (identity_trapdoor, identity_nullifier) pair
+// from which the identity secret hash and the identity commitment are derived
+
+// Timing characteristics: secret_hash and commitment (and their Semaphore variants) hash the secret trapdoor and nullifier
+// with the crate poseidon_hash, i.e. the zerokit_utils Poseidon implementation over arkworks field elements.
+// Its control flow and memory accesses only depend on the number of inputs and the round constants, never on the hashed values.
+// The underlying arkworks field additions and Montgomery multiplications end with a conditional subtraction of the modulus,
+// which is a data-dependent branch: derivations are not strictly constant time, but their timing variations are limited
+// to these final subtractions and don't depend on the Hamming weight of the secrets (see test_commitment_timing).
+// Serialization of identities with to_bytes is constant time
+
+use crate::circuit::Fr;
+use crate::poseidon_hash::poseidon_hash;
+use crate::protocol::{
+    extended_keygen, extended_seeded_keygen, id_commitment_from_secret, SerializationError,
+    SERIALIZED_FR_LEN,
+};
+use crate::utils::{bytes_be_to_fr, fr_to_bytes_be, fr_to_bytes_le_ct};
+
+// The length in bytes of a serialized identity, i.e. [ identity_trapdoor<32> | identity_nullifier<32> ]
+pub const SERIALIZED_IDENTITY_LEN: usize = 2 * SERIALIZED_FR_LEN;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub trapdoor: Fr,
+    pub nullifier: Fr,
+}
+
+impl Identity {
+    // Generates a random identity, as extended_keygen does
+    pub fn random() -> Self {
+        let (trapdoor, nullifier, _, _) = extended_keygen();
+        Identity {
+            trapdoor,
+            nullifier,
+        }
+    }
+
+    // Derives an identity from a seed as extended_seeded_keygen does,
+    // i.e. identity_trapdoor and identity_nullifier are sampled from 20 rounds of ChaCha seeded with the Keccak256 hash of the input.
+    // The same seed gives the same credentials through both APIs
+    pub fn from_rln_seed(signal: &[u8]) -> Self {
+        let (trapdoor, nullifier, _, _) = extended_seeded_keygen(signal);
+        Identity {
+            trapdoor,
+            nullifier,
+        }
+    }
+
+    // Serializes the identity as [ identity_trapdoor<32> | identity_nullifier<32> ], both values being big-endian.
+    // Since the trapdoor and the nullifier are secret, they are serialized in constant time
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut serialized = Vec::with_capacity(SERIALIZED_IDENTITY_LEN);
+        for value in [&self.trapdoor, &self.nullifier] {
+            let mut value_be = fr_to_bytes_le_ct(value);
+            value_be.reverse();
+            serialized.append(&mut value_be);
+        }
+        serialized
+    }
+
+    // Deserializes an identity serialized with to_bytes.
+    // Returns an error if the input is not SERIALIZED_IDENTITY_LEN bytes long or a value is not smaller than the field modulus
+    pub fn from_bytes(serialized: &[u8]) -> Result<Identity, SerializationError> {
+        if serialized.len() != SERIALIZED_IDENTITY_LEN {
+            return Err(SerializationError::InvalidLength {
+                expected: SERIALIZED_IDENTITY_LEN,
+                actual: serialized.len(),
+            });
+        }
+
+        let read_fr = |bytes: &[u8]| {
+            let (value, _) = bytes_be_to_fr(bytes);
+            // Values not smaller than the modulus are reduced by bytes_be_to_fr, so they don't serialize back to the input
+            if fr_to_bytes_be(&value) != bytes {
+                return Err(SerializationError::NonCanonicalFieldElement);
+            }
+            Ok(value)
+        };
+
+        let (trapdoor, nullifier) = serialized.split_at(SERIALIZED_FR_LEN);
+        Ok(Identity {
+            trapdoor: read_fr(trapdoor)?,
+            nullifier: read_fr(nullifier)?,
+        })
+    }
+
+    // Returns identity_secret_hash = PoseidonHash(identity_trapdoor, identity_nullifier).
+    // The trapdoor-first ordering is the one used by extended_keygen and extended_seeded_keygen,
+    // hence the one of credentials registered through the RLN public API
+    pub fn secret_hash(&self) -> Fr {
+        poseidon_hash(&[self.trapdoor, self.nullifier])
+    }
+
+    // Returns id_commitment = PoseidonHash(identity_secret_hash)
+    pub fn commitment(&self) -> Fr {
+        id_commitment_from_secret(&self.secret_hash())
+    }
+
+    // Returns PoseidonHash(identity_nullifier, identity_trapdoor), i.e. the secret hash with the nullifier-first
+    // ordering used by the Semaphore identity in semaphore-rs. Note that this differs from secret_hash:
+    // use it only to interoperate with identities created by Semaphore tooling
+    pub fn semaphore_secret_hash(&self) -> Fr {
+        poseidon_hash(&[self.nullifier, self.trapdoor])
+    }
+
+    // Returns PoseidonHash(semaphore_secret_hash), i.e. the commitment of the Semaphore identity in semaphore-rs
+    pub fn semaphore_commitment(&self) -> Fr {
+        id_commitment_from_secret(&self.semaphore_secret_hash())
+    }
+}
+
+////////////////////////////////////////////////////////////
+/// Tests
+////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_identity_from_rln_seed() {
+        let seed = b"this is a seed";
+
+        let identity = Identity::from_rln_seed(seed);
+        let (identity_trapdoor, identity_nullifier, identity_secret_hash, id_commitment) =
+            extended_seeded_keygen(seed);
+
+        assert_eq!(identity.trapdoor, identity_trapdoor);
+        assert_eq!(identity.nullifier, identity_nullifier);
+        assert_eq!(identity.secret_hash(), identity_secret_hash);
+        assert_eq!(identity.commitment(), id_commitment);
+
+        // A different seed gives a different identity
+        assert_ne!(identity, Identity::from_rln_seed(b"this is another seed"));
+    }
+
+    #[test]
+    #[ignore]
+    // We flag regressions where the commitment derivation time depends on the Hamming weight of the identity secrets,
+    // e.g. if a square-and-multiply exponentiation branching on secret bits were introduced.
+    // Samples of low and high weight identities are interleaved and compared by their median to limit the noise.
+    // Wall-clock timings are unreliable on shared runners and in debug builds, so this test is run on demand only,
+    // i.e. with cargo test --release -- --ignored
+    fn test_commitment_timing() {
+        use std::time::{Duration, Instant};
+
+        let low_weight = Identity {
+            trapdoor: Fr::from(1),
+            nullifier: Fr::from(1),
+        };
+        // 2^253 - 1, i.e. the largest value with all bits set smaller than the field modulus
+        let mut all_ones = [0xff; 32];
+        all_ones[0] = 0x1f;
+        let (all_ones, _) = bytes_be_to_fr(&all_ones);
+        let high_weight = Identity {
+            trapdoor: all_ones,
+            nullifier: all_ones,
+        };
+
+        let samples = 31;
+        let iterations = 50;
+        let measure = |identity: &Identity| {
+            let now = Instant::now();
+            for _ in 0..iterations {
+                std::hint::black_box(std::hint::black_box(identity).commitment());
+            }
+            now.elapsed()
+        };
+
+        let mut low_weight_times: Vec<Duration> = Vec::with_capacity(samples);
+        let mut high_weight_times: Vec<Duration> = Vec::with_capacity(samples);
+        for _ in 0..samples {
+            low_weight_times.push(measure(&low_weight));
+            high_weight_times.push(measure(&high_weight));
+        }
+        low_weight_times.sort();
+        high_weight_times.sort();
+        let low_weight_median = low_weight_times[samples / 2].as_secs_f64();
+        let high_weight_median = high_weight_times[samples / 2].as_secs_f64();
+
+        let ratio = high_weight_median / low_weight_median;
+        assert!(
+            (0.67..1.5).contains(&ratio),
+            "commitment derivation time depends on the secret Hamming weight: ratio {ratio}"
+        );
+    }
+
+    #[test]
+    fn test_identity_serialization() {
+        let identity = Identity::random();
+
+        let serialized = identity.to_bytes();
+        assert_eq!(serialized.len(), SERIALIZED_IDENTITY_LEN);
+        assert_eq!(
+            &serialized[..SERIALIZED_FR_LEN],
+            fr_to_bytes_be(&identity.trapdoor)
+        );
+        assert_eq!(
+            &serialized[SERIALIZED_FR_LEN..],
+            fr_to_bytes_be(&identity.nullifier)
+        );
+        assert_eq!(Identity::from_bytes(&serialized), Ok(identity));
+
+        // Inputs of the wrong length are rejected
+        assert_eq!(
+            Identity::from_bytes(&serialized[1..]),
+            Err(SerializationError::InvalidLength {
+                expected: SERIALIZED_IDENTITY_LEN,
+                actual: SERIALIZED_IDENTITY_LEN - 1
+            })
+        );
+
+        // Values not smaller than the field modulus are rejected rather than reduced
+        let mut non_canonical = serialized;
+        non_canonical[..SERIALIZED_FR_LEN].fill(0xff);
+        assert_eq!(
+            Identity::from_bytes(&non_canonical),
+            Err(SerializationError::NonCanonicalFieldElement)
+        );
+    }
+
+    #[test]
+    fn test_identity_secret_hash_ordering() {
+        let identity = Identity::random();
+
+        // secret_hash uses the same ordering as the protocol keygens
+        assert_eq!(
+            identity.secret_hash(),
+            poseidon_hash(&[identity.trapdoor, identity.nullifier])
+        );
+        let (identity_trapdoor, identity_nullifier, identity_secret_hash, id_commitment) =
+            extended_seeded_keygen(b"this is a seed");
+        let identity = Identity {
+            trapdoor: identity_trapdoor,
+            nullifier: identity_nullifier,
+        };
+        assert_eq!(identity.secret_hash(), identity_secret_hash);
+        assert_eq!(identity.commitment(), id_commitment);
+
+        // The Semaphore ordering swaps the trapdoor and the nullifier
+        assert_eq!(
+            identity.semaphore_secret_hash(),
+            poseidon_hash(&[identity_nullifier, identity_trapdoor])
+        );
+        assert_ne!(identity.semaphore_secret_hash(), identity.secret_hash());
+        assert_eq!(
+            identity.semaphore_commitment(),
+            id_commitment_from_secret(&identity.semaphore_secret_hash())
+        );
+
+        let swapped_identity = Identity {
+            trapdoor: identity_nullifier,
+            nullifier: identity_trapdoor,
+        };
+        assert_eq!(
+            swapped_identity.semaphore_secret_hash(),
+            identity.secret_hash()
+        );
+    }
+}
@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 pub mod circuit;
+pub mod identity;
 pub mod poseidon_hash;
 pub mod poseidon_tree;
 pub mod protocol;
@@ -20,7 +21,7 @@ mod test {
     use crate::poseidon_hash::poseidon_hash;
     use crate::poseidon_tree::PoseidonTree;
     use crate::protocol::*;
-    use crate::utils::str_to_fr;
+    use crate::utils::*;
 
     // Input generated with https://github.com/oskarth/zk-kit/commit/b6a872f7160c7c14e10a0ea40acab99cbb23c9a8
     const WITNESS_JSON_15: &str = r#"
@@ -345,12 +346,12 @@ mod test {
             witness_json = WITNESS_JSON_20;
         }
 
-        let rln_witness = rln_witness_from_json(witness_json);
+        let rln_witness = rln_witness_from_json(witness_json).unwrap();
 
         // Let's generate a zkSNARK proof
         let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
 
-        let proof_values = proof_values_from_witness(&rln_witness);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
 
         // Let's verify the proof
         let verified = verify_proof(&verification_key, &proof, &proof_values);
@@ -375,7 +376,7 @@ mod test {
         let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
 
         let signal = b"hey hey";
-        let x = hash_to_field(signal);
+        let x = signal_to_field(signal);
 
         // We set the remaining values to random ones
         let epoch = hash_to_field(b"test-epoch");
@@ -396,7 +397,7 @@ mod test {
         // Let's generate a zkSNARK proof
         let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
 
-        let proof_values = proof_values_from_witness(&rln_witness);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
 
         // Let's verify the proof
         let success = verify_proof(&verification_key, &proof, &proof_values).unwrap();
@@ -404,30 +405,3224 @@ mod test {
         assert!(success);
     }
 
+    #[test]
+    // We test that proving through a software SecretProvider matches the direct-secret path
+    fn test_secret_provider() {
+        let tree_height = TEST_TREE_HEIGHT;
+        let leaf_index = 3;
+
+        // Generate identity pair
+        let (identity_secret_hash, id_commitment) = keygen();
+        let secret_provider = SoftwareSecretProvider::new(identity_secret_hash);
+        assert_eq!(secret_provider.id_commitment(), id_commitment);
+
+        // generate merkle tree
+        let default_leaf = Fr::from(0);
+        let mut tree = PoseidonTree::new(tree_height, default_leaf);
+        tree.set(leaf_index, id_commitment.into()).unwrap();
+
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let x = signal_to_field(b"hey hey");
+        let epoch = hash_to_field(b"test-epoch");
+
+        let rln_identifier = hash_to_field(crate::public::RLN_IDENTIFIER);
+
+        // We check that proof values match the ones computed from the secret directly
+        let rln_witness = rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        let proof_values_provider = proof_values_from_secret_provider(
+            &secret_provider,
+            &merkle_proof,
+            x,
+            epoch,
+            rln_identifier,
+        )
+        .unwrap();
+        assert_eq!(proof_values, proof_values_provider);
+        assert_eq!(proof_values_provider.root, tree.root());
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let mut builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        // Let's generate a zkSNARK proof with the provider, which holds the secret, and verify it against its proof values
+        let proof = secret_provider
+            .generate_proof(
+                &mut builder,
+                &proving_key,
+                &merkle_proof,
+                x,
+                epoch,
+                rln_identifier,
+            )
+            .unwrap();
+        let success = verify_proof(&verification_key, &proof, &proof_values_provider).unwrap();
+        assert!(success);
+
+        // Proof values of the provider for another application are consistent too
+        let app_rln_identifier = rln_identifier_from_app(b"my-chat-app/v1");
+        let app_proof_values = proof_values_from_secret_provider(
+            &secret_provider,
+            &merkle_proof,
+            x,
+            epoch,
+            app_rln_identifier,
+        )
+        .unwrap();
+        assert_eq!(
+            app_proof_values,
+            proof_values_from_witness(&rln_witness_from_values_with_identifier(
+                identity_secret_hash,
+                &merkle_proof,
+                x,
+                epoch,
+                app_rln_identifier
+            ))
+            .unwrap()
+        );
+
+        // A zero x is rejected
+        assert_eq!(
+            proof_values_from_secret_provider(
+                &secret_provider,
+                &merkle_proof,
+                Fr::from(0),
+                epoch,
+                rln_identifier
+            ),
+            Err(WitnessValidationError::ZeroX)
+        );
+    }
+
+    #[test]
+    // We test that witnesses whose Merkle proof doesn't match the circuit tree height are rejected
+    fn test_validate_tree_height() {
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        assert!(validate_tree_height(&rln_witness, TEST_TREE_HEIGHT).is_ok());
+
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT - 1);
+        match validate_tree_height(&rln_witness, TEST_TREE_HEIGHT) {
+            Err(ProofError::TreeHeightMismatch { expected, actual }) => {
+                assert_eq!(expected, TEST_TREE_HEIGHT);
+                assert_eq!(actual, TEST_TREE_HEIGHT - 1);
+            }
+            _ => panic!("expected a tree height mismatch error"),
+        }
+    }
+
+    #[test]
+    // We test the field element constructors on edge values
+    fn test_fr_constructors() {
+        assert_eq!(fr_modulus_minus_one() + fr_one(), fr_zero());
+        assert_eq!(fr_from_u64(0), fr_zero());
+        assert_eq!(fr_from_u64(1), fr_one());
+        assert_eq!(fr_from_u64(2) * fr_modulus_minus_one(), -fr_from_u64(2));
+    }
+
+    #[test]
+    // We test that witnesses differing only in the RLN identifier give different nullifiers
+    fn test_witness_with_identifier() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let x = signal_to_field(b"hey hey");
+        let epoch = hash_to_field(b"test-epoch");
+
+        let rln_witness_1 = rln_witness_from_values_with_identifier(
+            identity_secret_hash,
+            &merkle_proof,
+            x,
+            epoch,
+            hash_to_field(b"test-rln-identifier-1"),
+        );
+        let rln_witness_2 = rln_witness_from_values_with_identifier(
+            identity_secret_hash,
+            &merkle_proof,
+            x,
+            epoch,
+            hash_to_field(b"test-rln-identifier-2"),
+        );
+
+        let proof_values_1 = proof_values_from_witness(&rln_witness_1).unwrap();
+        let proof_values_2 = proof_values_from_witness(&rln_witness_2).unwrap();
+
+        let external_nullifier_1 =
+            poseidon_hash(&[proof_values_1.epoch, proof_values_1.rln_identifier]);
+        let external_nullifier_2 =
+            poseidon_hash(&[proof_values_2.epoch, proof_values_2.rln_identifier]);
+
+        assert_ne!(external_nullifier_1, external_nullifier_2);
+        assert_ne!(proof_values_1.nullifier, proof_values_2.nullifier);
+        assert_eq!(proof_values_1.root, proof_values_2.root);
+
+        // The default identifier is used when none is passed explicitly
+        assert_eq!(
+            rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch),
+            rln_witness_from_values_with_identifier(
+                identity_secret_hash,
+                &merkle_proof,
+                x,
+                epoch,
+                hash_to_field(crate::public::RLN_IDENTIFIER),
+            )
+        );
+    }
+
+    #[test]
+    // We test verification of a proof when only the hash of the signal is known
+    fn test_verify_with_prehashed_x() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let x = signal_to_field(b"hey hey");
+        let epoch = hash_to_field(b"test-epoch");
+
+        let rln_witness = rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch);
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        // The pre-hashed x matches the one the proof was generated for
+        let verified = verify_with_prehashed_x(&verification_key, &proof, &proof_values, x);
+        assert!(verified.unwrap());
+
+        // The pre-hashed x corresponds to a different signal
+        let other_x = signal_to_field(b"ho ho");
+        let verified = verify_with_prehashed_x(&verification_key, &proof, &proof_values, other_x);
+        assert!(!verified.unwrap());
+    }
+
+    #[test]
+    // We test that proofs are verified only for the signal they were generated for
+    fn test_verify_proof_for_signal() {
+        let leaf_index = 3;
+        let signal = b"hey hey";
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let rln_witness = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            signal_to_field(signal),
+            hash_to_field(b"test-epoch"),
+        );
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        let verified = verify_proof_for_signal(&verification_key, &proof, &proof_values, signal);
+        assert!(verified.unwrap());
+
+        // The proof is valid, but for a different message
+        let verified = verify_proof_for_signal(&verification_key, &proof, &proof_values, b"ho ho");
+        assert!(!verified.unwrap());
+    }
+
+    #[test]
+    fn test_proof_bundle() {
+        let leaf_index = 3;
+        let signal = b"hey hey";
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let rln_witness = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            signal_to_field(signal),
+            hash_to_field(b"test-epoch"),
+        );
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let bundle = RLNProofBundle {
+            proof: generate_proof(builder, &proving_key, &rln_witness).unwrap(),
+            values: proof_values_from_witness(&rln_witness).unwrap(),
+            signal: signal.to_vec(),
+        };
+        assert!(bundle.verify(&verification_key).unwrap());
+
+        // The serialized bundle round-trips and still verifies
+        let serialized = bundle.serialize().unwrap();
+        assert_eq!(
+            serialized.len(),
+            SERIALIZED_PROOF_LEN + SERIALIZED_PROOF_VALUES_LEN + 8 + signal.len()
+        );
+        let deserialized = RLNProofBundle::deserialize(&serialized).unwrap();
+        assert_eq!(deserialized, bundle);
+        assert!(deserialized.verify(&verification_key).unwrap());
+
+        // Truncated or extended buffers are rejected
+        assert!(RLNProofBundle::deserialize(&serialized[..serialized.len() - 1]).is_err());
+        assert!(RLNProofBundle::deserialize(&serialized[..SERIALIZED_PROOF_LEN]).is_err());
+        let mut extended = serialized.clone();
+        extended.push(0);
+        assert!(RLNProofBundle::deserialize(&extended).is_err());
+
+        // The proof doesn't verify against a different signal
+        let tampered = RLNProofBundle {
+            signal: b"ho ho".to_vec(),
+            ..deserialized
+        };
+        assert!(!tampered.verify(&verification_key).unwrap());
+    }
+
+    #[test]
+    fn test_verify_serialized() {
+        use ark_serialize::CanonicalSerialize;
+
+        let leaf_index = 3;
+        let signal = b"hey hey";
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let rln_witness = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            signal_to_field(signal),
+            hash_to_field(b"test-epoch"),
+        );
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        let mut proof_and_values: Vec<u8> = Vec::new();
+        proof.serialize(&mut proof_and_values).unwrap();
+        proof_and_values.append(&mut serialize_proof_values(
+            &proof_values_from_witness(&rln_witness).unwrap(),
+        ));
+
+        assert!(verify_serialized(&verification_key, &proof_and_values, signal).unwrap());
+        assert!(!verify_serialized(&verification_key, &proof_and_values, b"ho ho").unwrap());
+
+        // Malformed inputs give a serialization error
+        assert!(matches!(
+            verify_serialized(
+                &verification_key,
+                &proof_and_values[..proof_and_values.len() - 1],
+                signal
+            ),
+            Err(ProofError::SerializationError(
+                SerializationError::InvalidLength { .. }
+            ))
+        ));
+        let mut invalid_proof = proof_and_values.clone();
+        invalid_proof[..SERIALIZED_PROOF_LEN].fill(0xff);
+        assert!(matches!(
+            verify_serialized(&verification_key, &invalid_proof, signal),
+            Err(ProofError::SerializationError(
+                SerializationError::InvalidProof(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_generate_proof_deterministic() {
+        use crate::circuit::Curve;
+        use ark_groth16::Proof as ArkProof;
+        use ark_serialize::CanonicalSerialize;
+
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let r = hash_to_field(b"test-blinding-r");
+        let s = hash_to_field(b"test-blinding-s");
+
+        let serialize = |proof: &ArkProof<Curve>| {
+            let mut serialized: Vec<u8> = Vec::new();
+            proof.serialize(&mut serialized).unwrap();
+            serialized
+        };
+
+        // Pinned blinding values give the same proof bytes
+        let proof_1 =
+            generate_proof_deterministic(builder, &proving_key, &rln_witness, r, s).unwrap();
+        let proof_2 =
+            generate_proof_deterministic(builder, &proving_key, &rln_witness, r, s).unwrap();
+        assert_eq!(serialize(&proof_1), serialize(&proof_2));
+        assert!(verify_proof(&verification_key, &proof_1, &proof_values).unwrap());
+
+        // Different blinding values give a different, still valid, proof
+        let proof_3 =
+            generate_proof_deterministic(builder, &proving_key, &rln_witness, s, r).unwrap();
+        assert_ne!(serialize(&proof_1), serialize(&proof_3));
+        assert!(verify_proof(&verification_key, &proof_3, &proof_values).unwrap());
+    }
+
+    #[test]
+    fn test_custom_witness_generator() {
+        use ark_circom::WitnessCalculator;
+        use std::sync::Mutex;
+
+        // A witness generator delegating to the circom one, counting the witnesses it computes
+        struct CountingWitnessGenerator {
+            inner: &'static Mutex<WitnessCalculator>,
+            calls: usize,
+        }
+
+        impl WitnessGenerator for CountingWitnessGenerator {
+            fn calculate(&mut self, rln_witness: &RLNWitnessInput) -> Result<Vec<Fr>, ProofError> {
+                self.calls += 1;
+                self.inner.calculate(rln_witness)
+            }
+        }
+
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let mut witness_generator = CountingWitnessGenerator {
+            inner: circom_from_folder(TEST_RESOURCES_FOLDER),
+            calls: 0,
+        };
+
+        let proof = generate_proof(&mut witness_generator, &proving_key, &rln_witness).unwrap();
+        assert_eq!(witness_generator.calls, 1);
+        assert!(verify_proof(&verification_key, &proof, &proof_values).unwrap());
+    }
+
+    #[test]
+    fn test_generate_and_verify_proof_serialized() {
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values =
+            serialize_proof_values(&proof_values_from_witness(&rln_witness).unwrap());
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let proof = generate_proof_serialized(builder, &proving_key, &rln_witness).unwrap();
+        assert_eq!(proof.len(), SERIALIZED_PROOF_LEN);
+        assert!(verify_proof_serialized(&verification_key, &proof, &proof_values).unwrap());
+        assert_eq!(
+            serialize_proof(&deserialize_proof(&proof).unwrap()).unwrap(),
+            proof
+        );
+
+        // Proof values of another witness don't verify
+        let other_proof_values = serialize_proof_values(
+            &proof_values_from_witness(&random_rln_witness(TEST_TREE_HEIGHT)).unwrap(),
+        );
+        assert!(!verify_proof_serialized(&verification_key, &proof, &other_proof_values).unwrap());
+
+        // Malformed buffers give serialization errors
+        assert!(matches!(
+            verify_proof_serialized(&verification_key, &proof[1..], &proof_values),
+            Err(ProofError::SerializationError(
+                SerializationError::InvalidLength { .. }
+            ))
+        ));
+        assert!(matches!(
+            verify_proof_serialized(
+                &verification_key,
+                &proof,
+                &proof_values[..SERIALIZED_PROOF_VALUES_LEN - 1]
+            ),
+            Err(ProofError::SerializationError(
+                SerializationError::InvalidLength { .. }
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_is_double_signal() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let epoch = hash_to_field(b"test-epoch");
+        let proof_values = |signal: &[u8], epoch: Fr| {
+            proof_values_from_witness(&rln_witness_from_values(
+                identity_secret_hash,
+                &merkle_proof,
+                hash_to_field(signal),
+                epoch,
+            ))
+            .unwrap()
+        };
+
+        // Two distinct messages sent by the same identity in the same epoch
+        let proof_values_1 = proof_values(b"hey hey", epoch);
+        let proof_values_2 = proof_values(b"ho ho", epoch);
+        assert!(is_double_signal(&proof_values_1, &proof_values_2));
+        assert!(is_double_signal(&proof_values_2, &proof_values_1));
+        assert_eq!(
+            recover_identity_from_proof_values(&proof_values_1, &proof_values_2),
+            Ok(identity_secret_hash)
+        );
+
+        // The same message sent twice
+        assert!(!is_double_signal(&proof_values_1, &proof_values_1.clone()));
+
+        // Messages sent in different epochs
+        let proof_values_3 = proof_values(b"ho ho", hash_to_field(b"another-epoch"));
+        assert!(!is_double_signal(&proof_values_1, &proof_values_3));
+
+        // Messages sent for different applications
+        let mut proof_values_4 = proof_values_2.clone();
+        proof_values_4.rln_identifier = hash_to_field(b"another-app");
+        assert!(!is_double_signal(&proof_values_1, &proof_values_4));
+
+        // Messages sent by different identities
+        let mut proof_values_5 = proof_values_2.clone();
+        proof_values_5.nullifier = proof_values_3.nullifier;
+        assert!(!is_double_signal(&proof_values_1, &proof_values_5));
+    }
+
+    #[test]
+    fn test_recover_identity_from_proof_values() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let epoch = hash_to_field(b"test-epoch");
+
+        // Two messages sent by the same identity in the same epoch
+        let rln_witness_1 = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            hash_to_field(b"hey hey"),
+            epoch,
+        );
+        let rln_witness_2 = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            hash_to_field(b"ho ho"),
+            epoch,
+        );
+        let proof_values_1 = proof_values_from_witness(&rln_witness_1).unwrap();
+        let proof_values_2 = proof_values_from_witness(&rln_witness_2).unwrap();
+
+        let recovered = recover_identity_from_proof_values(&proof_values_1, &proof_values_2);
+        assert_eq!(recovered, Ok(identity_secret_hash));
+
+        // A message sent in a different epoch
+        let rln_witness_3 = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            hash_to_field(b"hey hey"),
+            hash_to_field(b"another-epoch"),
+        );
+        let proof_values_3 = proof_values_from_witness(&rln_witness_3).unwrap();
+
+        let recovered = recover_identity_from_proof_values(&proof_values_1, &proof_values_3);
+        assert_eq!(recovered, Err(RecoveryError::EpochMismatch));
+
+        // A message sent by a different identity in the same epoch
+        let (identity_secret_hash_4, _) = keygen();
+        let rln_witness_4 = rln_witness_from_values(
+            identity_secret_hash_4,
+            &merkle_proof,
+            hash_to_field(b"ho ho"),
+            epoch,
+        );
+        let proof_values_4 = proof_values_from_witness(&rln_witness_4).unwrap();
+
+        let recovered = recover_identity_from_proof_values(&proof_values_1, &proof_values_4);
+        assert_eq!(recovered, Err(RecoveryError::NullifierMismatch));
+    }
+
+    #[test]
+    fn test_eval_share() {
+        let (identity_secret_hash, id_commitment) = keygen();
+        let epoch = hash_to_field(b"test-epoch");
+        let external_nullifier =
+            poseidon_hash(&[epoch, hash_to_field(crate::public::RLN_IDENTIFIER)]);
+
+        // Shares match the ones computed in proof values
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(0, id_commitment).unwrap();
+        let merkle_proof = tree.proof(0).expect("proof should exist");
+        let x = signal_to_field(b"hey hey");
+        let rln_witness = rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        assert_eq!(
+            eval_share(identity_secret_hash, external_nullifier, x),
+            (proof_values.x, proof_values.y)
+        );
+
+        // Any two shares at different x recover the identity secret
+        let shares: Vec<(Fr, Fr)> = (1..5u64)
+            .map(|i| eval_share(identity_secret_hash, external_nullifier, Fr::from(i)))
+            .collect();
+        for i in 0..shares.len() {
+            for j in (i + 1)..shares.len() {
+                assert_eq!(
+                    compute_id_secret(shares[i], shares[j], external_nullifier),
+                    Ok(identity_secret_hash)
+                );
+            }
+        }
+
+        // Shares of different secrets don't
+        let (other_identity_secret_hash, _) = keygen();
+        let other_share = eval_share(other_identity_secret_hash, external_nullifier, Fr::from(5));
+        assert!(compute_id_secret(shares[0], other_share, external_nullifier).is_err());
+    }
+
+    #[test]
+    fn test_recover_from_shares() {
+        let (identity_secret_hash, _) = keygen();
+        let external_nullifier =
+            compute_external_nullifier(hash_to_field(b"test-epoch"), hash_to_field(b"app"));
+
+        let mut shares: Vec<(Fr, Fr)> = (1..4u64)
+            .map(|i| eval_share(identity_secret_hash, external_nullifier, Fr::from(i)))
+            .collect();
+        assert_eq!(
+            recover_from_shares(&shares, external_nullifier),
+            Ok(identity_secret_hash)
+        );
+
+        // A corrupt share, even first, and a replayed share don't poison recovery
+        shares.insert(0, (Fr::from(5), Fr::from(42)));
+        shares.insert(1, shares[1]);
+        assert_eq!(
+            recover_from_shares(&shares, external_nullifier),
+            Ok(identity_secret_hash)
+        );
+
+        // Recovery fails without two consistent shares
+        let corrupt_shares = [shares[0], shares[1], (Fr::from(6), Fr::from(43))];
+        assert!(matches!(
+            recover_from_shares(&corrupt_shares, external_nullifier),
+            Err(RecoveryError::InvalidShares(_))
+        ));
+        assert!(recover_from_shares(&shares[1..3], external_nullifier).is_err());
+        assert!(recover_from_shares(&[], external_nullifier).is_err());
+    }
+
+    #[test]
+    // Regression test: shares with the same x used to cause a division by zero
+    fn test_compute_id_secret_identical_shares() {
+        let (identity_secret_hash, _) = keygen();
+        let external_nullifier = poseidon_hash(&[
+            hash_to_field(b"test-epoch"),
+            hash_to_field(crate::public::RLN_IDENTIFIER),
+        ]);
+
+        let a_1 = poseidon_hash(&[identity_secret_hash, external_nullifier]);
+        let x = signal_to_field(b"hey hey");
+        let share = (x, identity_secret_hash + x * a_1);
+
+        // A replayed share
+        assert_eq!(
+            compute_id_secret(share, share, external_nullifier),
+            Err(RecoveryError::IdenticalShares)
+        );
+
+        // Same x but different y
+        let forged_share = (x, share.1 + Fr::from(1));
+        assert_eq!(
+            compute_id_secret(share, forged_share, external_nullifier),
+            Err(RecoveryError::IdenticalShares)
+        );
+
+        // Shares at different x still recover the secret
+        let x2 = signal_to_field(b"ho ho");
+        let share2 = (x2, identity_secret_hash + x2 * a_1);
+        assert_eq!(
+            compute_id_secret(share, share2, external_nullifier),
+            Ok(identity_secret_hash)
+        );
+    }
+
+    #[test]
+    fn test_proof_to_eth_abi() {
+        use ark_bn254::{G1Affine, G2Affine};
+        use ark_ec::AffineCurve;
+        use ark_groth16::Proof as ArkProof;
+
+        // Known answer test: the curve generators have the EIP-197 coordinates
+        // G1 = (1, 2)
+        // G2 = (11559732032986387107991004021392285783925812861821192530917403151452391805634 * i + 10857046999023057135944570762232829481370756359578518086990519993285655852781,
+        //       4082367875863433681332203403145435568316851327593401208105741076214120093531 * i + 8495653923123431417604973247489272438418190587263600148770280649306958101930)
+        let proof = ArkProof {
+            a: G1Affine::prime_subgroup_generator(),
+            b: G2Affine::prime_subgroup_generator(),
+            c: G1Affine::prime_subgroup_generator(),
+        };
+
+        let expected = [
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "198e9393920d483a7260bfb731fb5d25f1aa493335a9e71297e485b7aef312c2",
+            "1800deef121f1e76426a00665e5c4479674322d4f75edadd46debd5cd992f6ed",
+            "090689d0585ff075ec9e99ad690c3395bc4b313370b38ef355acdadcd122975b",
+            "12c85ea5db8c6deb4aab71808dcb408fe3d1e7690c43d37b4ce6cc0166fa7daa",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+        ];
+
+        let encoded = proof_to_eth_abi(&proof);
+        assert_eq!(encoded.len(), 256);
+
+        for (word, expected_word) in encoded.chunks(32).zip(expected.iter()) {
+            let expected_word: Vec<u8> = (0..expected_word.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&expected_word[i..i + 2], 16).unwrap())
+                .collect();
+            assert_eq!(word, expected_word.as_slice());
+        }
+
+        // Known answer test with distinct a, b and c, so that swapping a and c, negating b or swapping
+        // the coefficients of b coordinates changes the encoding. The proof is given as a snarkjs proof.json,
+        // with a = 2 * G1, b = 2 * G2 and c = 3 * G1:
+        // {"pi_a":["1368015179489954701390400359078579693043519447331113978918064868415326638035","9918110051302171585080402603319702774565515993150576347155970296011118125764","1"],
+        //  "pi_b":[["18029695676650738226693292988307914797657423701064905010927197838374790804409","14583779054894525174450323658765874724019480979794335525732096752006891875705"],
+        //          ["2140229616977736810657479771656733941598412651537078903776637920509952744750","11474861747383700316476719153975578001603231366361248090558603872215261634898"],["1","0"]],
+        //  "pi_c":["3353031288059533942658390886683067124040920775575537747144343083137631628272","19321533766552368860946552437480515441416830039777911637913418824951667761761","1"],
+        //  "protocol":"groth16","curve":"bn128"}
+        // and the expected words are the ones of its snarkjs Solidity calldata, i.e. [a.x, a.y], [[b.x.c1, b.x.c0], [b.y.c1, b.y.c0]], [c.x, c.y]
+        use ark_bn254::{Fq, Fq2};
+        use ark_ec::ProjectiveCurve;
+        use std::str::FromStr;
+
+        let fq = |value: &str| Fq::from_str(value).unwrap();
+        let proof = ArkProof {
+            a: G1Affine::new(
+                fq("1368015179489954701390400359078579693043519447331113978918064868415326638035"),
+                fq("9918110051302171585080402603319702774565515993150576347155970296011118125764"),
+                false,
+            ),
+            b: G2Affine::new(
+                Fq2::new(
+                    fq("18029695676650738226693292988307914797657423701064905010927197838374790804409"),
+                    fq("14583779054894525174450323658765874724019480979794335525732096752006891875705"),
+                ),
+                Fq2::new(
+                    fq("2140229616977736810657479771656733941598412651537078903776637920509952744750"),
+                    fq("11474861747383700316476719153975578001603231366361248090558603872215261634898"),
+                ),
+                false,
+            ),
+            c: G1Affine::new(
+                fq("3353031288059533942658390886683067124040920775575537747144343083137631628272"),
+                fq("19321533766552368860946552437480515441416830039777911637913418824951667761761"),
+                false,
+            ),
+        };
+        assert!(proof.a.is_on_curve() && proof.b.is_on_curve() && proof.c.is_on_curve());
+        let generator_1 = G1Affine::prime_subgroup_generator().into_projective();
+        assert_eq!(proof.a, (generator_1 + generator_1).into_affine());
+        assert_eq!(
+            proof.c,
+            (generator_1 + generator_1 + generator_1).into_affine()
+        );
+
+        let calldata = [
+            "0x030644e72e131a029b85045b68181585d97816a916871ca8d3c208c16d87cfd3",
+            "0x15ed738c0e0a7c92e7845f96b2ae9c0a68a6a449e3538fc7ff3ebf7a5a18a2c4",
+            "0x203e205db4f19b37b60121b83a7333706db86431c6d835849957ed8c3928ad79",
+            "0x27dc7234fd11d3e8c36c59277c3e6f149d5cd3cfa9a62aee49f8130962b4b3b9",
+            "0x195e8aa5b7827463722b8c153931579d3505566b4edf48d498e185f0509de152",
+            "0x04bb53b8977e5f92a0bc372742c4830944a59b4fe6b1c0466e2a6dad122b5d2e",
+            "0x0769bf9ac56bea3ff40232bcb1b6bd159315d84715b8e679f2d355961915abf0",
+            "0x2ab799bee0489429554fdb7c8d086475319e63b40b9c5b57cdf1ff3dd9fe2261",
+        ];
+
+        let encoded = proof_to_eth_abi(&proof);
+        assert_eq!(encoded.len(), 32 * calldata.len());
+        for (word, expected_word) in encoded.chunks(32).zip(calldata.iter()) {
+            let word: String = word.iter().map(|byte| format!("{byte:02x}")).collect();
+            assert_eq!(format!("0x{word}"), *expected_word);
+        }
+    }
+
+    #[test]
+    fn test_commitment_visual_seed() {
+        let (_, id_commitment_1) = keygen();
+        let (_, id_commitment_2) = keygen();
+
+        // The same commitment always yields the same seed
+        assert_eq!(
+            commitment_visual_seed(id_commitment_1),
+            commitment_visual_seed(id_commitment_1)
+        );
+
+        // Different commitments yield different seeds
+        assert_ne!(
+            commitment_visual_seed(id_commitment_1),
+            commitment_visual_seed(id_commitment_2)
+        );
+    }
+
+    #[test]
+    fn test_multi_message_proof_values() {
+        let leaf_index = 3;
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).unwrap();
+
+        let epoch = hash_to_field(b"test-epoch");
+        let messages = [
+            (signal_to_field(b"hey hey"), Fr::from(0)),
+            (signal_to_field(b"ho ho"), Fr::from(1)),
+            (signal_to_field(b"let's go"), Fr::from(1)),
+        ];
+        let rln_witness = rln_multi_message_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            &messages,
+            epoch,
+        );
+        let proof_values = proof_values_from_multi_message_witness(&rln_witness);
+
+        // All messages share the membership proof
+        assert_eq!(proof_values.root, tree.root());
+        assert_eq!(proof_values.epoch, epoch);
+        assert_eq!(
+            proof_values.xs,
+            messages.iter().map(|(x, _)| *x).collect::<Vec<Fr>>()
+        );
+        assert_eq!(proof_values.ys.len(), messages.len());
+
+        // Each share lies on the line of its message_id
+        let external_nullifier = compute_external_nullifier(epoch, proof_values.rln_identifier);
+        for (i, (x, message_id)) in messages.iter().enumerate() {
+            let a_1 = poseidon_hash(&[identity_secret_hash, external_nullifier, *message_id]);
+            assert_eq!(proof_values.ys[i], identity_secret_hash + *x * a_1);
+            assert_eq!(proof_values.nullifiers[i], poseidon_hash(&[a_1]));
+        }
+
+        // Only messages reusing a message_id share their nullifier
+        assert_ne!(proof_values.nullifiers[0], proof_values.nullifiers[1]);
+        assert_eq!(proof_values.nullifiers[1], proof_values.nullifiers[2]);
+    }
+
+    #[test]
+    fn test_proof_values_batch() {
+        let rln_witnesses: Vec<RLNWitnessInput> = (0..8)
+            .map(|_| random_rln_witness(TEST_TREE_HEIGHT))
+            .collect();
+
+        let expected: Vec<Result<RLNProofValues, WitnessValidationError>> = rln_witnesses
+            .iter()
+            .map(proof_values_from_witness)
+            .collect();
+        assert_eq!(proof_values_batch(&rln_witnesses), expected);
+    }
+
+    #[test]
+    #[ignore]
+    // We compare serial and batch proof values computation times.
+    // Timings depend on the machine, so this benchmark is run on demand only, i.e. with cargo test --release -- --ignored
+    fn test_proof_values_batch_performances() {
+        use std::time::Instant;
+
+        let sample_size = 1024;
+
+        let rln_witnesses: Vec<RLNWitnessInput> = (0..sample_size)
+            .map(|_| random_rln_witness(TEST_TREE_HEIGHT))
+            .collect();
+
+        let now = Instant::now();
+        let expected: Vec<Result<RLNProofValues, WitnessValidationError>> = rln_witnesses
+            .iter()
+            .map(proof_values_from_witness)
+            .collect();
+        let serial_time = now.elapsed();
+
+        let now = Instant::now();
+        let proof_values = proof_values_batch(&rln_witnesses);
+        let batch_time = now.elapsed();
+
+        assert_eq!(proof_values, expected);
+
+        println!(
+            " Proof values for {} witnesses: serial {:?}, batch {:?}",
+            sample_size, serial_time, batch_time
+        );
+    }
+
+    #[test]
+    fn test_signal_to_field() {
+        let signal = b"hey hey";
+        assert_eq!(signal_to_field(signal), hash_to_field(signal));
+
+        // Witnesses built from serialized prove inputs use the same mapping
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        let (identity_secret_hash, id_commitment) = keygen();
+        tree.set(0, id_commitment).unwrap();
+
+        let epoch = hash_to_field(b"test-epoch");
+        let serialized = prepare_prove_input(identity_secret_hash, 0, epoch, signal).unwrap();
+        let (rln_witness, _) = proof_inputs_to_rln_witness(&mut tree, &serialized).unwrap();
+        assert_eq!(
+            proof_values_from_witness(&rln_witness).unwrap().x,
+            signal_to_field(signal)
+        );
+    }
+
+    #[test]
+    fn test_hash_to_field_with_domain() {
+        let signal = b"hey hey";
+
+        // The empty domain gives the bare hash
+        assert_eq!(
+            hash_to_field_with_domain(b"", signal),
+            hash_to_field(signal)
+        );
+
+        // Different domains give independent hashes
+        let x = hash_to_field_with_domain(b"rln/x", signal);
+        let rln_identifier = hash_to_field_with_domain(b"rln/rln_identifier", signal);
+        assert_ne!(x, rln_identifier);
+        assert_ne!(x, hash_to_field(signal));
+
+        // Domain and signal boundaries are unambiguous
+        assert_ne!(
+            hash_to_field_with_domain(b"rln/", b"xhey hey"),
+            hash_to_field_with_domain(b"rln/x", signal)
+        );
+    }
+
+    #[test]
+    // We test that x is never 0, as the share y would then be equal to the identity secret
+    fn test_zero_x_rejected() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        // A witness with x = 0 would leak the identity secret in y, hence its proof values are not computed
+        let rln_witness = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            Fr::from(0),
+            hash_to_field(b"test-epoch"),
+        );
+        assert_eq!(
+            proof_values_from_witness(&rln_witness),
+            Err(WitnessValidationError::ZeroX)
+        );
+
+        // Such witness is rejected before proving too
+        assert_eq!(
+            validate_witness(&rln_witness),
+            Err(WitnessValidationError::ZeroX)
+        );
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        assert!(matches!(
+            generate_proof(builder, &proving_key, &rln_witness),
+            Err(ProofError::InvalidWitness(WitnessValidationError::ZeroX))
+        ));
+
+        // A signal hashing to 0 (which we can't find) would be remapped to the first non-zero counter rehash
+        let signal = b"hey hey";
+        let remapped_x = remap_zero_x(signal);
+        assert_ne!(remapped_x, Fr::from(0));
+        assert_eq!(
+            remapped_x,
+            hash_to_field(&[&signal[..], &1u64.to_le_bytes()].concat())
+        );
+        assert_eq!(remapped_x, remap_zero_x(signal));
+        assert_ne!(signal_to_field(signal), Fr::from(0));
+    }
+
+    #[test]
+    fn test_verify_nullifier() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let epoch = hash_to_field(b"test-epoch");
+        let rln_witness = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            hash_to_field(b"hey hey"),
+            epoch,
+        );
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        let external_nullifier = poseidon_hash(&[proof_values.epoch, proof_values.rln_identifier]);
+
+        assert!(verify_nullifier(
+            &identity_secret_hash,
+            &external_nullifier,
+            &proof_values.nullifier
+        ));
+
+        // A different secret doesn't yield the same nullifier
+        let (other_identity_secret_hash, _) = keygen();
+        assert!(!verify_nullifier(
+            &other_identity_secret_hash,
+            &external_nullifier,
+            &proof_values.nullifier
+        ));
+
+        // Nor does a different external nullifier
+        let other_external_nullifier =
+            poseidon_hash(&[hash_to_field(b"another-epoch"), proof_values.rln_identifier]);
+        assert!(!verify_nullifier(
+            &identity_secret_hash,
+            &other_external_nullifier,
+            &proof_values.nullifier
+        ));
+    }
+
+    #[test]
+    fn test_verify_slashing_claim() {
+        use ark_serialize::CanonicalSerialize;
+
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let epoch = hash_to_field(b"test-epoch");
+        let signal1: &[u8] = b"hey hey";
+        let signal2: &[u8] = b"ho ho";
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+
+        // We generate two proofs for the same epoch and serialize them as [ proof<128> | proof_values ]
+        let mut serialized_proofs = Vec::new();
+        for signal in [signal1, signal2] {
+            let rln_witness = rln_witness_from_values(
+                identity_secret_hash,
+                &merkle_proof,
+                hash_to_field(signal),
+                epoch,
+            );
+            let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+            let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+            let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+            let mut serialized: Vec<u8> = Vec::new();
+            proof.serialize(&mut serialized).unwrap();
+            serialized.append(&mut serialize_proof_values(&proof_values));
+            serialized_proofs.push(serialized);
+        }
+
+        // A valid claim
+        let claim = SlashingReport {
+            identity_secret: identity_secret_hash,
+            id_commitment,
+        };
+        let verified = verify_slashing_claim(
+            &claim,
+            &serialized_proofs[0],
+            signal1,
+            &serialized_proofs[1],
+            signal2,
+        );
+        assert!(verified.unwrap());
+
+        // A fabricated claim
+        let (fake_identity_secret_hash, fake_id_commitment) = keygen();
+        let fabricated_claim = SlashingReport {
+            identity_secret: fake_identity_secret_hash,
+            id_commitment: fake_id_commitment,
+        };
+        let verified = verify_slashing_claim(
+            &fabricated_claim,
+            &serialized_proofs[0],
+            signal1,
+            &serialized_proofs[1],
+            signal2,
+        );
+        assert!(!verified.unwrap());
+
+        // Proofs claimed for the wrong signals
+        let verified = verify_slashing_claim(
+            &claim,
+            &serialized_proofs[0],
+            signal2,
+            &serialized_proofs[1],
+            signal1,
+        );
+        assert!(!verified.unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "test-vectors")]
+    fn test_deterministic_test_vector() {
+        let seed = [42u8; 32];
+
+        // The same seed always gives the same witness
+        let rln_witness = deterministic_rln_witness(seed, TEST_TREE_HEIGHT);
+        assert_eq!(
+            rln_witness,
+            deterministic_rln_witness(seed, TEST_TREE_HEIGHT)
+        );
+        assert_ne!(
+            rln_witness,
+            deterministic_rln_witness([43u8; 32], TEST_TREE_HEIGHT)
+        );
+
+        // Expected outputs correspond to the deterministic witness
+        let (proof_values, json_inputs) = deterministic_test_vector(seed, TEST_TREE_HEIGHT);
+        assert_eq!(
+            proof_values,
+            proof_values_from_witness(&rln_witness).unwrap()
+        );
+        assert_eq!(json_inputs, get_json_inputs(&rln_witness));
+    }
+
+    #[test]
+    fn test_compute_tree_root_length_mismatch() {
+        let leaf = Fr::from(42);
+        let path_elements = vec![Fr::from(1), Fr::from(2)];
+        let identity_path_index = vec![0, 1, 0];
+
+        let root = compute_tree_root(&leaf, &path_elements, &identity_path_index, true);
+        assert_eq!(
+            root,
+            Err("Inconsistent Merkle proof: path_elements has length 2, identity_path_index has length 3".to_string())
+        );
+    }
+
+    #[test]
+    fn test_compute_tree_root_with_mode() {
+        let leaf_index = 3;
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).unwrap();
+        let path_elements = merkle_proof.get_path_elements();
+        let identity_path_index = merkle_proof.get_path_index();
+
+        // The identity secret is hashed into the leaf, while the id_commitment is the leaf
+        for (leaf, leaf_hash_mode) in [
+            (identity_secret_hash, LeafHashMode::HashLeaf),
+            (id_commitment, LeafHashMode::Prehashed),
+        ] {
+            let root = compute_tree_root_with_mode(
+                &leaf,
+                &path_elements,
+                &identity_path_index,
+                leaf_hash_mode,
+            );
+            assert_eq!(root, Ok(tree.root()));
+            assert_eq!(
+                root,
+                compute_tree_root(
+                    &leaf,
+                    &path_elements,
+                    &identity_path_index,
+                    leaf_hash_mode == LeafHashMode::HashLeaf
+                )
+            );
+        }
+
+        // The wrong mode yields a different root
+        assert_ne!(
+            compute_tree_root_with_mode(
+                &id_commitment,
+                &path_elements,
+                &identity_path_index,
+                LeafHashMode::HashLeaf
+            ),
+            Ok(tree.root())
+        );
+        assert_eq!(LeafHashMode::from(true), LeafHashMode::HashLeaf);
+        assert_eq!(LeafHashMode::from(false), LeafHashMode::Prehashed);
+    }
+
+    #[test]
+    fn test_compute_tree_roots_batch() {
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        let leaves: Vec<Fr> = (0..10).map(|i| hash_to_field(&[i])).collect();
+        tree.set_range(0, leaves.clone()).unwrap();
+
+        let mut inputs: Vec<(Fr, Vec<Fr>, Vec<u8>)> = leaves
+            .iter()
+            .enumerate()
+            .map(|(i, leaf)| {
+                let merkle_proof = tree.proof(i).unwrap();
+                (
+                    *leaf,
+                    merkle_proof.get_path_elements(),
+                    merkle_proof.get_path_index(),
+                )
+            })
+            .collect();
+        // An inconsistent Merkle proof only fails its own root computation
+        inputs[4].2.pop();
+
+        let roots = compute_tree_roots_batch(&inputs, false);
+        assert_eq!(roots.len(), inputs.len());
+        for (i, root) in roots.iter().enumerate() {
+            if i == 4 {
+                assert!(root.is_err());
+            } else {
+                assert_eq!(root, &Ok(tree.root()));
+            }
+        }
+
+        assert!(compute_tree_roots_batch(&[], false).is_empty());
+    }
+
+    #[test]
+    // We check compute_tree_root_with_arity against a quaternary tree built level by level
+    fn test_compute_tree_root_with_arity() {
+        let arity = 4;
+        let depth = 2;
+        let leaves: Vec<Fr> = (0..16).map(|i| Fr::from(i as u64)).collect();
+
+        let mut levels = vec![leaves.clone()];
+        for _ in 0..depth {
+            let level: Vec<Fr> = levels
+                .last()
+                .unwrap()
+                .chunks(arity)
+                .map(poseidon_hash)
+                .collect();
+            levels.push(level);
+        }
+        let root = levels[depth][0];
+
+        for leaf_index in [0, 6, 15] {
+            let mut path_elements = Vec::new();
+            let mut identity_path_index = Vec::new();
+            let mut index = leaf_index;
+            for level in levels.iter().take(depth) {
+                let position = index % arity;
+                let first = index - position;
+                for (i, node) in level[first..first + arity].iter().enumerate() {
+                    if i != position {
+                        path_elements.push(*node);
+                    }
+                }
+                identity_path_index.push(position as u8);
+                index /= arity;
+            }
+
+            assert_eq!(
+                compute_tree_root_with_arity(
+                    &leaves[leaf_index],
+                    &path_elements,
+                    &identity_path_index,
+                    arity,
+                    false
+                )
+                .unwrap(),
+                root
+            );
+        }
+
+        // Arity 2 is the binary tree used by compute_tree_root
+        let path_elements = vec![Fr::from(1), Fr::from(2)];
+        let identity_path_index = vec![1, 0];
+        assert_eq!(
+            compute_tree_root_with_arity(
+                &Fr::from(42),
+                &path_elements,
+                &identity_path_index,
+                2,
+                true
+            ),
+            compute_tree_root(&Fr::from(42), &path_elements, &identity_path_index, true)
+        );
+
+        // Unsupported arities, inconsistent lengths and out of range digits are rejected
+        assert!(compute_tree_root_with_arity(&Fr::from(42), &[], &[], 1, false).is_err());
+        assert!(
+            compute_tree_root_with_arity(&Fr::from(42), &[], &[], MAX_TREE_ARITY + 1, false)
+                .is_err()
+        );
+        assert!(
+            compute_tree_root_with_arity(&Fr::from(42), &path_elements, &[0, 1], 4, false).is_err()
+        );
+        assert!(compute_tree_root_with_arity(
+            &Fr::from(42),
+            &[Fr::from(1), Fr::from(2), Fr::from(3)],
+            &[4],
+            4,
+            false
+        )
+        .is_err());
+    }
+
+    #[test]
+    // We check that the cached tree root is the one computed from any member's Merkle proof
+    fn test_tree_root_matches_member_paths() {
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        let identity_secret_hashes: Vec<Fr> = (0..5).map(|_| keygen().0).collect();
+        for identity_secret_hash in identity_secret_hashes.iter() {
+            tree.update_next(id_commitment_from_secret(identity_secret_hash))
+                .unwrap();
+        }
+
+        let root = tree.root();
+        for (leaf_index, identity_secret_hash) in identity_secret_hashes.iter().enumerate() {
+            let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+            assert_eq!(
+                compute_tree_root(
+                    identity_secret_hash,
+                    &merkle_proof.get_path_elements(),
+                    &merkle_proof.get_path_index(),
+                    true
+                )
+                .unwrap(),
+                root
+            );
+        }
+        assert_eq!(tree.recompute_root(), root);
+    }
+
+    #[test]
+    // We check that the atomic Merkle operations match the ones used by the tree
+    fn test_merkle_atomic_operations() {
+        let leaf_index = 5;
+        let identity_secret_hash = hash_to_field(b"test-merkle-atomic");
+        let id_commitment = hash_leaf(&identity_secret_hash);
+        assert_eq!(id_commitment, poseidon_hash(&[identity_secret_hash]));
+
+        let mut tree = PoseidonTree::new(TEST_TREE_HEIGHT, Fr::from(0));
+        tree.set(leaf_index, id_commitment).unwrap();
+
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+        let path_elements = merkle_proof.get_path_elements();
+        let identity_path_index = merkle_proof.get_path_index();
+
+        // We recompute the root level by level using only merkle_parent
+        let mut root = id_commitment;
+        for (sibling, bit) in path_elements.iter().zip(identity_path_index.iter()) {
+            root = if *bit == 0 {
+                merkle_parent(&root, sibling)
+            } else {
+                merkle_parent(sibling, &root)
+            };
+        }
+
+        assert_eq!(root, tree.root());
+        assert_eq!(
+            compute_tree_root(
+                &identity_secret_hash,
+                &path_elements,
+                &identity_path_index,
+                true
+            )
+            .unwrap(),
+            tree.root()
+        );
+    }
+
+    #[test]
+    fn test_prove_input_id_index_encoding() {
+        let identity_secret = hash_to_field(b"test-id-index");
+        let epoch = hash_to_field(b"test-epoch");
+        let signal = b"hey hey";
+
+        // The index field is always 8 bytes, whatever the platform pointer width
+        for id_index in [0, 5, usize::from(u16::MAX)] {
+            let serialized = prepare_prove_input(identity_secret, id_index, epoch, signal).unwrap();
+            assert_eq!(serialized.len(), 32 + 8 + 32 + 8 + signal.len());
+            assert_eq!(
+                &serialized[32..40],
+                &u64::try_from(id_index).unwrap().to_le_bytes()
+            );
+        }
+
+        // The serialized index is read back by proof_inputs_to_rln_witness
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        let serialized = prepare_prove_input(identity_secret, 5, epoch, signal).unwrap();
+        let (rln_witness, read) = proof_inputs_to_rln_witness(&mut tree, &serialized).unwrap();
+        assert_eq!(read, 32 + 8 + 32 + 8);
+        assert_eq!(
+            rln_witness,
+            rln_witness_from_values(
+                identity_secret,
+                &tree.proof(5).unwrap(),
+                signal_to_field(signal),
+                epoch
+            )
+        );
+    }
+
+    #[test]
+    fn test_signal_length_prefix() {
+        let proof_data = vec![1u8; 320];
+        let signal = b"hey hey";
+
+        let serialized_u64 = prepare_verify_input_with_prefix(
+            proof_data.clone(),
+            signal,
+            LengthPrefix::U64,
+            MAX_SIGNAL_LEN,
+        )
+        .unwrap();
+        assert_eq!(
+            serialized_u64,
+            prepare_verify_input(proof_data.clone(), signal).unwrap()
+        );
+
+        let serialized_u16 = prepare_verify_input_with_prefix(
+            proof_data.clone(),
+            signal,
+            LengthPrefix::U16,
+            MAX_SIGNAL_LEN,
+        )
+        .unwrap();
+
+        // We save 6 bytes using a u16 prefix
+        assert_eq!(serialized_u64.len() - serialized_u16.len(), 6);
+
+        // We read back the signal
+        let signal_len = LengthPrefix::U16.decode(&serialized_u16[320..]).unwrap();
+        assert_eq!(signal_len, signal.len());
+        assert_eq!(&serialized_u16[322..322 + signal_len], signal);
+
+        // A signal too long for the chosen prefix is rejected
+        let long_signal = vec![0u8; u16::MAX as usize + 1];
+        assert!(prepare_verify_input_with_prefix(
+            proof_data,
+            &long_signal,
+            LengthPrefix::U16,
+            MAX_SIGNAL_LEN
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_max_signal_len() {
+        let identity_secret_hash = hash_to_field(b"secret");
+        let epoch = hash_to_field(b"test-epoch");
+
+        let signal = vec![0u8; MAX_SIGNAL_LEN];
+        assert!(prepare_prove_input(identity_secret_hash, 0, epoch, &signal).is_ok());
+        assert!(prepare_verify_input(vec![1u8; 320], &signal).is_ok());
+
+        let oversized_signal = vec![0u8; MAX_SIGNAL_LEN + 1];
+        assert_eq!(
+            prepare_prove_input(identity_secret_hash, 0, epoch, &oversized_signal),
+            Err(SerializationError::SignalTooLong {
+                len: MAX_SIGNAL_LEN + 1,
+                max: MAX_SIGNAL_LEN
+            })
+        );
+        assert!(prepare_verify_input(vec![1u8; 320], &oversized_signal).is_err());
+
+        // The limit is configurable
+        assert!(
+            prepare_verify_input_with_prefix(vec![1u8; 320], b"hey hey", LengthPrefix::U64, 4)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_witness_from_tree() {
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        let (index, identity_secret_hash, _) = register(&mut tree).unwrap();
+
+        let signal = b"hey hey";
+        let epoch = hash_to_field(b"test-epoch");
+        let rln_witness =
+            witness_from_tree(&tree, index, identity_secret_hash, signal, epoch).unwrap();
+
+        let merkle_proof = tree.proof(index).unwrap();
+        assert_eq!(
+            rln_witness,
+            rln_witness_from_values(
+                identity_secret_hash,
+                &merkle_proof,
+                signal_to_field(signal),
+                epoch
+            )
+        );
+        assert_eq!(
+            proof_values_from_witness(&rln_witness).unwrap().root,
+            tree.root()
+        );
+
+        // Indexes out of the tree bounds are rejected
+        let capacity = tree.capacity();
+        assert!(matches!(
+            witness_from_tree(&tree, capacity, identity_secret_hash, signal, epoch),
+            Err(ProofError::TreeIndexOutOfBounds { index, capacity: c }) if index == capacity as u64 && c == capacity
+        ));
+    }
+
+    #[test]
+    fn test_dummy_member_witness() {
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(0, hash_to_field(b"existing member")).unwrap();
+
+        let signal = b"hey hey";
+        let epoch = hash_to_field(b"test-epoch");
+        let (identity_secret, rln_witness) =
+            dummy_member_witness(&mut tree, epoch, signal).unwrap();
+
+        // The dummy member was registered at the next available leaf
+        assert_eq!(tree.leaves_set(), 2);
+        assert!(tree
+            .verify(&poseidon_hash(&[identity_secret]), &tree.proof(1).unwrap())
+            .unwrap());
+
+        // The witness is consistent with the tree, so that its proof verifies against the tree root
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        assert_eq!(proof_values.root, tree.root());
+        assert_eq!(proof_values.x, signal_to_field(signal));
+        assert_eq!(proof_values.epoch, epoch);
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        assert!(
+            verify_proof_against_root(&verification_key, &proof, &proof_values, &tree.root())
+                .unwrap()
+        );
+
+        // No dummy member can be registered in a full tree
+        let mut full_tree = PoseidonTree::default(1);
+        full_tree.update_next(fr_one()).unwrap();
+        full_tree.update_next(fr_one()).unwrap();
+        assert!(matches!(
+            dummy_member_witness(&mut full_tree, epoch, signal),
+            Err(ProofError::TreeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_proof_for_insertion() {
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(0, hash_to_field(b"existing member")).unwrap();
+        tree.set(5, hash_to_field(b"another member")).unwrap();
+        let root = tree.root();
+
+        let index = 3;
+        let (_, id_commitment) = keygen();
+        let (merkle_proof, anticipated_root) =
+            proof_for_insertion(&tree, index, id_commitment).unwrap();
+
+        // The tree is not mutated
+        assert_eq!(tree.root(), root);
+        assert!(!tree.is_occupied(index).unwrap());
+        assert!(!tree.verify(&id_commitment, &merkle_proof).unwrap());
+
+        // The proof and root become valid once the value is inserted
+        tree.set(index, id_commitment).unwrap();
+        assert_eq!(tree.root(), anticipated_root);
+        assert!(tree.verify(&id_commitment, &merkle_proof).unwrap());
+        assert_eq!(merkle_proof.leaf_index(), index);
+
+        // Indexes out of the tree bounds are rejected
+        assert!(proof_for_insertion(&tree, tree.capacity(), id_commitment).is_err());
+    }
+
+    #[test]
+    fn test_register() {
+        let mut tree = PoseidonTree::default(2);
+        tree.set(0, hash_to_field(b"existing member")).unwrap();
+
+        // Members are registered at the next available leaf
+        for expected_index in 1..tree.capacity() {
+            let (index, identity_secret_hash, id_commitment) = register(&mut tree).unwrap();
+            assert_eq!(index, expected_index);
+            assert_eq!(id_commitment, poseidon_hash(&[identity_secret_hash]));
+
+            let merkle_proof = tree.proof(index).unwrap();
+            assert_eq!(merkle_proof.leaf_index(), index);
+            assert!(tree.verify(&id_commitment, &merkle_proof).unwrap());
+        }
+
+        // Registration fails once the tree is full
+        assert!(register(&mut tree).is_err());
+    }
+
+    #[test]
+    fn test_tree_integrity_check() {
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        assert!(tree_integrity_check(&tree).is_ok());
+
+        for i in 0..10 {
+            let (_, id_commitment) = keygen();
+            tree.set(i, id_commitment).unwrap();
+        }
+        tree.delete(3).unwrap();
+        assert!(tree_integrity_check(&tree).is_ok());
+
+        // A corrupted stored root is detected
+        tree.corrupt_root(hash_to_field(b"corrupted-root"));
+        assert!(matches!(
+            tree_integrity_check(&tree),
+            Err(RLNError::TreeCorrupted)
+        ));
+    }
+
+    #[test]
+    fn test_generate_proof_with_external_witness() {
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        // We compute the witness outside of the proving function
+        let inputs = inputs_for_witness_calculation(&rln_witness)
+            .into_iter()
+            .map(|(name, values)| (name.to_string(), values));
+        let calculated_witness = builder
+            .lock()
+            .unwrap()
+            .calculate_witness(inputs, false)
+            .unwrap();
+
+        let proof = generate_proof_with_witness(calculated_witness, &proving_key).unwrap();
+
+        let verified = verify_proof(&verification_key, &proof, &proof_values);
+        assert!(verified.unwrap());
+    }
+
+    #[test]
+    fn test_deserialize_proof_and_values() {
+        use ark_serialize::CanonicalSerialize;
+
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+
+        let mut serialized: Vec<u8> = Vec::new();
+        proof.serialize(&mut serialized).unwrap();
+        assert_eq!(serialized.len(), SERIALIZED_PROOF_LEN);
+        serialized.append(&mut serialize_proof_values(&proof_values));
+
+        let (deser_proof, deser_proof_values) = deserialize_proof_and_values(&serialized).unwrap();
+        assert_eq!(deser_proof, proof);
+        assert_eq!(deser_proof_values, proof_values);
+
+        // Buffers with a wrong length are rejected
+        let expected = SERIALIZED_PROOF_LEN + SERIALIZED_PROOF_VALUES_LEN;
+        assert_eq!(
+            deserialize_proof_and_values(&serialized[..expected - 1]).unwrap_err(),
+            SerializationError::InvalidLength {
+                expected,
+                actual: expected - 1
+            }
+        );
+        serialized.push(0);
+        assert!(deserialize_proof_and_values(&serialized).is_err());
+    }
+
+    #[test]
+    fn test_verify_proof_stream() {
+        use ark_serialize::CanonicalSerialize;
+        use std::io::Read;
+
+        // A synthetic stream repeating the same record, generated on the fly so that the stream is never held in memory.
+        // Memory usage of the verifier is checked in the proof_stream_memory integration test
+        struct RepeatedRecords {
+            record: Vec<u8>,
+            records_left: usize,
+            position: usize,
+        }
+
+        impl Read for RepeatedRecords {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                if self.records_left == 0 {
+                    return Ok(0);
+                }
+                let read = buf.len().min(self.record.len() - self.position);
+                buf[..read].copy_from_slice(&self.record[self.position..self.position + read]);
+                self.position += read;
+                if self.position == self.record.len() {
+                    self.position = 0;
+                    self.records_left -= 1;
+                }
+                Ok(read)
+            }
+        }
+
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+
+        let mut record: Vec<u8> = Vec::new();
+        proof.serialize(&mut record).unwrap();
+        record.append(&mut serialize_proof_values(&proof_values));
+
+        let stream_len = 100;
+        let stream = RepeatedRecords {
+            record: record.clone(),
+            records_left: stream_len,
+            position: 0,
+        };
+
+        let mut verified_count = 0;
+        for verified in verify_proof_stream(&verification_key, stream) {
+            assert!(verified.unwrap());
+            verified_count += 1;
+        }
+        assert_eq!(verified_count, stream_len);
+
+        // A record with tampered proof values doesn't verify
+        let mut tampered_proof_values =
+            proof_values_from_witness(&random_rln_witness(TEST_TREE_HEIGHT)).unwrap();
+        tampered_proof_values.x = proof_values.x;
+        let mut tampered_record = record[..SERIALIZED_PROOF_LEN].to_vec();
+        tampered_record.append(&mut serialize_proof_values(&tampered_proof_values));
+
+        // A truncated record ends the stream with an error
+        let mut serialized_stream = record.clone();
+        serialized_stream.append(&mut tampered_record);
+        serialized_stream.extend_from_slice(&record[..10]);
+
+        let results: Vec<Result<bool, ProofError>> =
+            verify_proof_stream(&verification_key, serialized_stream.as_slice()).collect();
+        assert_eq!(results.len(), 3);
+        assert!(matches!(results[0], Ok(true)));
+        assert!(matches!(results[1], Ok(false)));
+        assert!(results[2].is_err());
+    }
+
+    #[test]
+    fn test_fr_solidity_uint() {
+        let elements = [
+            fr_zero(),
+            fr_one(),
+            fr_modulus_minus_one(),
+            hash_to_field(b"hey hey"),
+        ];
+
+        for element in elements {
+            let encoded = fr_to_solidity_uint(&element);
+            assert_eq!(encoded.len(), 66);
+
+            // We cross-check against the hex encoding of the element
+            assert_eq!(
+                encoded,
+                format!("0x{:0>64}", to_bigint(&element).to_str_radix(16))
+            );
+
+            assert_eq!(fr_from_solidity_uint(&encoded).unwrap(), element);
+        }
+
+        // Non 0x-prefixed, wrongly sized, non hex and non reduced inputs are rejected
+        assert!(fr_from_solidity_uint(&"0".repeat(64)).is_err());
+        assert!(fr_from_solidity_uint("0x01").is_err());
+        assert!(fr_from_solidity_uint(&format!("0x{}", "g".repeat(64))).is_err());
+        assert!(fr_from_solidity_uint(&format!("0x{}", "f".repeat(64))).is_err());
+
+        // The field modulus itself is the smallest rejected value
+        let modulus = to_bigint(&fr_modulus_minus_one()) + 1;
+        assert!(fr_from_solidity_uint(&format!("0x{:064x}", modulus)).is_err());
+    }
+
+    #[test]
+    fn test_poseidon_hash_signal() {
+        let signal = b"hey hey";
+        assert_eq!(poseidon_hash_signal(signal), poseidon_hash_signal(signal));
+        assert_ne!(poseidon_hash_signal(signal), poseidon_hash_signal(b"ho ho"));
+
+        // Trailing zero bytes change the hash
+        assert_ne!(poseidon_hash_signal(b""), poseidon_hash_signal(&[0u8]));
+        assert_ne!(
+            poseidon_hash_signal(&[1u8]),
+            poseidon_hash_signal(&[1u8, 0u8])
+        );
+
+        // Signals spanning several absorptions
+        let long_signal: Vec<u8> = (0..1000).map(|i| (i % 256) as u8).collect();
+        let mut other_long_signal = long_signal.clone();
+        other_long_signal[999] ^= 1;
+        assert_ne!(
+            poseidon_hash_signal(&long_signal),
+            poseidon_hash_signal(&other_long_signal)
+        );
+    }
+
+    #[test]
+    fn test_signal_hash_function() {
+        let signal = b"hey hey";
+
+        assert_eq!(SignalHashFunction::default(), SignalHashFunction::Keccak256);
+        assert_eq!(
+            hash_to_field_with(SignalHashFunction::Keccak256, signal),
+            hash_to_field(signal)
+        );
+        assert_eq!(
+            signal_to_field_with(SignalHashFunction::Keccak256, signal),
+            signal_to_field(signal)
+        );
+        assert_eq!(
+            hash_to_field_with(SignalHashFunction::Poseidon, signal),
+            poseidon_hash_signal(signal)
+        );
+        assert_eq!(
+            signal_to_field_with(SignalHashFunction::Poseidon, signal),
+            poseidon_hash_signal(signal)
+        );
+
+        // The two hash functions are not interchangeable
+        assert_ne!(
+            signal_to_field_with(SignalHashFunction::Keccak256, signal),
+            signal_to_field_with(SignalHashFunction::Poseidon, signal)
+        );
+    }
+
+    #[test]
+    fn test_generate_proof_from_serialized_witness() {
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        let serialized_witness = serialize_witness(&rln_witness);
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        // Groth16 proofs are randomized: we check that both proofs verify against the same proof values
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        let proof_from_serialized =
+            generate_proof_from_serialized_witness(&serialized_witness, builder, &proving_key)
+                .unwrap();
+
+        assert!(verify_proof(&verification_key, &proof, &proof_values).unwrap());
+        assert!(verify_proof(&verification_key, &proof_from_serialized, &proof_values).unwrap());
+    }
+
+    #[test]
+    fn test_full_lifecycle_check() {
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let verified = full_lifecycle_check(
+            builder,
+            &proving_key,
+            &verification_key,
+            TEST_TREE_HEIGHT,
+            b"this is a seed",
+            b"hey hey",
+            hash_to_field(b"test-epoch"),
+        );
+        assert!(verified.unwrap());
+    }
+
+    #[test]
+    fn test_generate_proof_with_poisoned_witness_calculator() {
+        use ark_circom::WitnessCalculator;
+        use std::sync::Mutex;
+        use wasmer::{Module, Store};
+
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+
+        // We instantiate a dedicated witness calculator, so that the shared one used by other tests is left untouched
+        let wasm_buffer = std::fs::read(format!("{TEST_RESOURCES_FOLDER}rln.wasm")).unwrap();
+        let store = Store::default();
+        let module = Module::new(&store, wasm_buffer).unwrap();
+        let witness_calculator = Mutex::new(WitnessCalculator::from_module(module).unwrap());
+
+        // We poison the witness calculator mutex by panicking while holding its lock
+        let _ = std::panic::catch_unwind(|| {
+            let _guard = witness_calculator.lock().unwrap();
+            panic!("proof attempt failed");
+        });
+        assert!(witness_calculator.is_poisoned());
+
+        // Proof generation still succeeds
+        let proof = generate_proof(&witness_calculator, &proving_key, &rln_witness).unwrap();
+        assert!(verify_proof(&verification_key, &proof, &proof_values).unwrap());
+    }
+
+    #[test]
+    fn test_verify_proof_with_roots() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+        let proof_root = tree.root();
+
+        let x = signal_to_field(b"hey hey");
+        let epoch = hash_to_field(b"test-epoch");
+
+        let rln_witness = rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        // We generate all relevant keys
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+
+        // The tree is updated after the proof was generated
+        let (_, other_id_commitment) = keygen();
+        tree.set(leaf_index + 1, other_id_commitment).unwrap();
+        let current_root = tree.root();
+
+        // The proof root is still acceptable
+        let verified = verify_proof_with_roots(
+            &verification_key,
+            &proof,
+            &proof_values,
+            &[proof_root, current_root],
+        );
+        assert!(verified.unwrap());
+
+        // The proof root is no longer acceptable
+        let verified =
+            verify_proof_with_roots(&verification_key, &proof, &proof_values, &[current_root]);
+        assert!(!verified.unwrap());
+    }
+
+    #[test]
+    fn test_proof_inputs_index_out_of_bounds() {
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        let capacity = tree.capacity();
+
+        let (identity_secret_hash, id_commitment) = keygen();
+        tree.set(0, id_commitment).unwrap();
+
+        let epoch = hash_to_field(b"test-epoch");
+
+        // A valid index
+        let serialized = prepare_prove_input(identity_secret_hash, 0, epoch, b"hey hey").unwrap();
+        assert!(proof_inputs_to_rln_witness(&mut tree, &serialized).is_ok());
+
+        // An index beyond the tree capacity
+        let serialized =
+            prepare_prove_input(identity_secret_hash, capacity, epoch, b"hey hey").unwrap();
+        match proof_inputs_to_rln_witness(&mut tree, &serialized) {
+            Err(ProofError::TreeIndexOutOfBounds {
+                index,
+                capacity: tree_capacity,
+            }) => {
+                assert_eq!(index, capacity as u64);
+                assert_eq!(tree_capacity, capacity);
+            }
+            _ => panic!("expected a TreeIndexOutOfBounds error"),
+        }
+
+        // An index above u32::MAX, which would be truncated to the occupied leaf 0 on 32 bits targets
+        let mut serialized =
+            prepare_prove_input(identity_secret_hash, 0, epoch, b"hey hey").unwrap();
+        let id_index = u32::MAX as u64 + 1;
+        serialized[fr_byte_size()..fr_byte_size() + 8].copy_from_slice(&id_index.to_le_bytes());
+        match proof_inputs_to_rln_witness(&mut tree, &serialized) {
+            Err(ProofError::TreeIndexOutOfBounds {
+                index,
+                capacity: tree_capacity,
+            }) => {
+                assert_eq!(index, id_index);
+                assert_eq!(tree_capacity, capacity);
+            }
+            _ => panic!("expected a TreeIndexOutOfBounds error"),
+        }
+
+        // Truncated inputs are rejected instead of panicking, whether the header or the signal is truncated
+        let serialized = prepare_prove_input(identity_secret_hash, 0, epoch, b"hey hey").unwrap();
+        for len in [0, 40, serialized.len() - 1] {
+            assert!(matches!(
+                proof_inputs_to_rln_witness(&mut tree, &serialized[..len]),
+                Err(ProofError::SerializationError(
+                    SerializationError::InvalidLength { .. }
+                ))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_id_commitment_from_secret() {
+        let (identity_secret_hash, id_commitment) = keygen();
+        assert_eq!(
+            id_commitment_from_secret(&identity_secret_hash),
+            id_commitment
+        );
+
+        let (_, _, identity_secret_hash, id_commitment) = extended_seeded_keygen(b"this is a seed");
+        assert_eq!(
+            id_commitment_from_secret(&identity_secret_hash),
+            id_commitment
+        );
+    }
+
+    #[test]
+    fn test_merkle_proof_serialization() {
+        let leaf_index = 3;
+
+        let (_, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let serialized = serialize_merkle_proof(&merkle_proof);
+        let deserialized = deserialize_merkle_proof(&serialized).unwrap();
+
+        assert_eq!(
+            deserialized.get_path_elements(),
+            merkle_proof.get_path_elements()
+        );
+        assert_eq!(deserialized.get_path_index(), merkle_proof.get_path_index());
+        assert_eq!(deserialized.compute_root_from(&id_commitment), tree.root());
+
+        // Truncated inputs and trailing bytes are rejected
+        assert!(deserialize_merkle_proof(&serialized[..serialized.len() - 1]).is_err());
+        assert!(deserialize_merkle_proof(&serialized[..4]).is_err());
+        let mut extended = serialized.clone();
+        extended.push(0);
+        assert!(deserialize_merkle_proof(&extended).is_err());
+    }
+
     #[test]
     fn test_witness_serialization() {
         // We test witness serialization
         let mut witness_json: &str = "";
 
-        if TEST_TREE_HEIGHT == 15 {
-            witness_json = WITNESS_JSON_15;
-        } else if TEST_TREE_HEIGHT == 19 {
-            witness_json = WITNESS_JSON_19;
-        } else if TEST_TREE_HEIGHT == 20 {
-            witness_json = WITNESS_JSON_20;
+        if TEST_TREE_HEIGHT == 15 {
+            witness_json = WITNESS_JSON_15;
+        } else if TEST_TREE_HEIGHT == 19 {
+            witness_json = WITNESS_JSON_19;
+        } else if TEST_TREE_HEIGHT == 20 {
+            witness_json = WITNESS_JSON_20;
+        }
+
+        let rln_witness = rln_witness_from_json(witness_json).unwrap();
+
+        let ser = serialize_witness(&rln_witness);
+        let (deser, _) = deserialize_witness(&ser).unwrap();
+        assert_eq!(rln_witness, deser);
+
+        // We test Proof values serialization
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        let ser = serialize_proof_values(&proof_values);
+        let (deser, _) = deserialize_proof_values(&ser);
+        assert_eq!(proof_values, deser);
+    }
+
+    #[test]
+    fn test_load_circuit_keys() {
+        use ark_serialize::CanonicalSerialize;
+
+        let zkey_bytes = std::fs::read(format!("{TEST_RESOURCES_FOLDER}rln_final.zkey")).unwrap();
+        let proving_key = load_proving_key(&zkey_bytes).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+
+        // The verifying key can be derived from the proving key
+        assert_eq!(extract_verifying_key(&proving_key.0), verification_key);
+        assert_eq!(
+            verifying_key_from_proving_key(&proving_key),
+            verification_key
+        );
+
+        // The verifying key can be loaded from its arkworks serialization
+        let mut vk_bytes = Vec::new();
+        verification_key.serialize(&mut vk_bytes).unwrap();
+        assert_eq!(load_verifying_key(&vk_bytes).unwrap(), verification_key);
+
+        // Invalid keys are rejected
+        assert!(matches!(
+            load_proving_key(&[]),
+            Err(ProofError::CircuitKeyError(_))
+        ));
+        assert!(matches!(
+            load_verifying_key(&vk_bytes[..vk_bytes.len() - 1]),
+            Err(ProofError::CircuitKeyError(_))
+        ));
+    }
+
+    #[test]
+    fn test_proving_key_fingerprint() {
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let fingerprint = proving_key_fingerprint(&proving_key);
+
+        // The fingerprint is stable across loads and formats
+        let zkey_bytes = std::fs::read(format!("{TEST_RESOURCES_FOLDER}rln_final.zkey")).unwrap();
+        let mut reloaded_key = load_proving_key(&zkey_bytes).unwrap();
+        assert_eq!(proving_key_fingerprint(&reloaded_key), fingerprint);
+        let arkzkey = load_arkzkey(&zkey_to_arkzkey(&zkey_bytes).unwrap()).unwrap();
+        assert_eq!(proving_key_fingerprint(&arkzkey), fingerprint);
+
+        // A key from another ceremony for the same circuit has a different fingerprint
+        reloaded_key.0.vk.delta_g2 = -reloaded_key.0.vk.delta_g2;
+        assert_ne!(proving_key_fingerprint(&reloaded_key), fingerprint);
+        reloaded_key.0.vk.delta_g2 = -reloaded_key.0.vk.delta_g2;
+        assert_eq!(proving_key_fingerprint(&reloaded_key), fingerprint);
+
+        // A key for another circuit has a different fingerprint
+        let (coefficient, _) = reloaded_key.1.a.iter_mut().flatten().next().unwrap();
+        *coefficient += Fr::from(1);
+        assert_ne!(proving_key_fingerprint(&reloaded_key), fingerprint);
+    }
+
+    #[test]
+    fn test_load_arkzkey() {
+        let zkey_bytes = std::fs::read(format!("{TEST_RESOURCES_FOLDER}rln_final.zkey")).unwrap();
+        let proving_key = load_proving_key(&zkey_bytes).unwrap();
+
+        // Converted keys load to the same proving key and constraint matrices
+        let arkzkey_bytes = zkey_to_arkzkey(&zkey_bytes).unwrap();
+        assert_eq!(arkzkey_bytes, serialize_arkzkey(&proving_key).unwrap());
+        let (arkzkey_proving_key, arkzkey_matrices) = load_arkzkey(&arkzkey_bytes).unwrap();
+        let (zkey_proving_key, zkey_matrices) = &proving_key;
+        assert_eq!(&arkzkey_proving_key, zkey_proving_key);
+        assert_eq!(
+            arkzkey_matrices.num_instance_variables,
+            zkey_matrices.num_instance_variables
+        );
+        assert_eq!(
+            arkzkey_matrices.num_witness_variables,
+            zkey_matrices.num_witness_variables
+        );
+        assert_eq!(
+            arkzkey_matrices.num_constraints,
+            zkey_matrices.num_constraints
+        );
+        assert_eq!(
+            arkzkey_matrices.a_num_non_zero,
+            zkey_matrices.a_num_non_zero
+        );
+        assert_eq!(
+            arkzkey_matrices.b_num_non_zero,
+            zkey_matrices.b_num_non_zero
+        );
+        assert_eq!(
+            arkzkey_matrices.c_num_non_zero,
+            zkey_matrices.c_num_non_zero
+        );
+        assert_eq!(arkzkey_matrices.a, zkey_matrices.a);
+        assert_eq!(arkzkey_matrices.b, zkey_matrices.b);
+        assert_eq!(arkzkey_matrices.c, zkey_matrices.c);
+
+        // Proofs generated with the loaded key verify
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        let proof = generate_proof(
+            builder,
+            &(arkzkey_proving_key, arkzkey_matrices),
+            &rln_witness,
+        )
+        .unwrap();
+        assert!(verify_proof(
+            &verifying_key_from_proving_key(&proving_key),
+            &proof,
+            &proof_values_from_witness(&rln_witness).unwrap()
+        )
+        .unwrap());
+
+        // Invalid or truncated arkzkeys are rejected
+        assert!(matches!(
+            load_arkzkey(&zkey_bytes),
+            Err(ProofError::CircuitKeyError(_))
+        ));
+        assert!(matches!(
+            load_arkzkey(&arkzkey_bytes[..arkzkey_bytes.len() - 1]),
+            Err(ProofError::CircuitKeyError(_))
+        ));
+        assert!(matches!(
+            load_arkzkey(&arkzkey_bytes[..100]),
+            Err(ProofError::CircuitKeyError(_))
+        ));
+    }
+
+    #[test]
+    // Threat model: serialized secrets (e.g. identity secrets in witnesses) may be processed on devices where an attacker
+    // can measure timings. fr_to_bytes_le_ct must then be free of branches and memory accesses depending on the input value:
+    // do not replace it with fr_to_bytes_le or arkworks into_repr, which branch on the value, even if faster.
+    // Here we only check that it outputs the same bytes as fr_to_bytes_le
+    fn test_fr_to_bytes_le_ct() {
+        let mut values = vec![
+            Fr::from(0),
+            Fr::from(1),
+            Fr::from(u64::MAX),
+            fr_modulus_minus_one(),
+            fr_modulus_minus_one() - Fr::from(1),
+        ];
+        for i in 0..32u8 {
+            values.push(hash_to_field(&[i]));
+        }
+
+        for value in values {
+            assert_eq!(fr_to_bytes_le_ct(&value), fr_to_bytes_le(&value));
+        }
+
+        // Witness serialization is unchanged
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+        let (deser, _) = deserialize_witness(&serialize_witness(&rln_witness)).unwrap();
+        assert_eq!(deser, rln_witness);
+    }
+
+    #[test]
+    fn test_epoch_json_round_trip() {
+        // json -> witness -> json keeps the epoch unchanged
+        for witness_json in [WITNESS_JSON_15, WITNESS_JSON_19, WITNESS_JSON_20] {
+            let input_json: serde_json::Value = serde_json::from_str(witness_json).unwrap();
+            let rln_witness = rln_witness_from_json(witness_json).unwrap();
+            let output_json = get_json_inputs(&rln_witness);
+            assert_eq!(output_json["epoch"], input_json["epoch"]);
+            assert_eq!(
+                epoch_from_json_string(output_json["epoch"].as_str().unwrap()),
+                proof_values_from_witness(&rln_witness).unwrap().epoch
+            );
+        }
+
+        // Epochs with leading zeros and decimal-looking digits use the canonical encoding and round-trip
+        for epoch in [
+            Fr::from(0),
+            Fr::from(10),
+            Fr::from(u64::MAX),
+            hash_to_field(b"epoch"),
+        ] {
+            let encoded = epoch_to_json_string(&epoch);
+            assert!(encoded.starts_with("0x"));
+            assert_eq!(encoded.len(), 66);
+            assert_eq!(epoch_from_json_string(&encoded), epoch);
+
+            // Decimal epochs, as emitted by get_json_inputs_decimal, are not misread as hexadecimal
+            assert_eq!(
+                epoch_from_json_string(&to_bigint(&epoch).to_str_radix(10)),
+                epoch
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_json_inputs_decimal() {
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+
+        let json_inputs = get_json_inputs(&rln_witness);
+        let json_inputs_decimal = get_json_inputs_decimal(&rln_witness);
+
+        // The default encoding keeps epoch in hexadecimal, as in the input witness JSON
+        let input_json: serde_json::Value = serde_json::from_str(WITNESS_JSON_20).unwrap();
+        assert_eq!(json_inputs["epoch"], input_json["epoch"]);
+
+        // Both encodings correspond to the same epoch
+        assert_eq!(
+            str_to_fr(json_inputs["epoch"].as_str().unwrap(), 16),
+            str_to_fr(json_inputs_decimal["epoch"].as_str().unwrap(), 10)
+        );
+
+        // All other inputs are the same
+        for key in [
+            "identity_secret",
+            "path_elements",
+            "identity_path_index",
+            "x",
+            "rln_identifier",
+        ] {
+            assert_eq!(json_inputs[key], json_inputs_decimal[key]);
+        }
+    }
+
+    #[test]
+    fn test_validate_witness_with_height() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let x = signal_to_field(b"hey hey");
+        let epoch = hash_to_field(b"test-epoch");
+
+        let rln_witness = rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch);
+        assert!(validate_witness_with_height(&rln_witness, TEST_TREE_HEIGHT).is_ok());
+        assert!(matches!(
+            validate_witness_with_height(&rln_witness, TEST_TREE_HEIGHT + 1),
+            Err(ProofError::TreeHeightMismatch {
+                expected,
+                actual: TEST_TREE_HEIGHT
+            }) if expected == TEST_TREE_HEIGHT + 1
+        ));
+
+        // A path index equal to 2 is rejected rather than silently taken as 1
+        let mut serialized = serialize_witness(&rln_witness);
+        let path_index_offset = 1 + 32 + 8 + 32 * TEST_TREE_HEIGHT + 8;
+        serialized[path_index_offset] = 2;
+        let (invalid_witness, _) = deserialize_witness(&serialized).unwrap();
+        assert!(matches!(
+            validate_witness_with_height(&invalid_witness, TEST_TREE_HEIGHT),
+            Err(ProofError::InvalidWitness(
+                WitnessValidationError::InvalidPathIndex { level: 0, value: 2 }
+            ))
+        ));
+
+        // So is the corresponding root computation, which doesn't produce a wrong root
+        let mut identity_path_index = merkle_proof.get_path_index();
+        identity_path_index[0] = 2;
+        assert!(compute_tree_root(
+            &identity_secret_hash,
+            &merkle_proof.get_path_elements(),
+            &identity_path_index,
+            true
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_validate_witness() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let x = signal_to_field(b"hey hey");
+        let epoch = hash_to_field(b"test-epoch");
+
+        let rln_witness = rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch);
+        assert_eq!(validate_witness(&rln_witness), Ok(()));
+
+        // A path index different from 0 or 1
+        let mut serialized = serialize_witness(&rln_witness);
+        let path_index_offset = 1 + 32 + 8 + 32 * TEST_TREE_HEIGHT + 8;
+        serialized[path_index_offset + 2] = 2;
+        let (invalid_witness, _) = deserialize_witness(&serialized).unwrap();
+        assert_eq!(
+            validate_witness(&invalid_witness),
+            Err(WitnessValidationError::InvalidPathIndex { level: 2, value: 2 })
+        );
+
+        // Path elements and indexes with different lengths
+        let mut serialized = vec![WITNESS_FORMAT_VERSION];
+        serialized.append(&mut fr_to_bytes_le(&identity_secret_hash));
+        serialized.append(&mut vec_fr_to_bytes_le(&merkle_proof.get_path_elements()));
+        serialized.append(&mut vec_u8_to_bytes_le(&merkle_proof.get_path_index()[1..]));
+        serialized.append(&mut fr_to_bytes_le(&x));
+        serialized.append(&mut fr_to_bytes_le(&epoch));
+        serialized.append(&mut fr_to_bytes_le(&hash_to_field(
+            crate::public::RLN_IDENTIFIER,
+        )));
+        let (invalid_witness, _) = deserialize_witness(&serialized).unwrap();
+        assert_eq!(
+            validate_witness(&invalid_witness),
+            Err(WitnessValidationError::PathLengthMismatch {
+                path_elements: TEST_TREE_HEIGHT,
+                identity_path_index: TEST_TREE_HEIGHT - 1
+            })
+        );
+
+        // Proof generation fails early on invalid witnesses
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        assert!(matches!(
+            generate_proof(builder, &proving_key, &invalid_witness),
+            Err(ProofError::InvalidWitness(
+                WitnessValidationError::PathLengthMismatch { .. }
+            ))
+        ));
+
+        // A RLN identifier different from the expected application one is rejected only by validate_witness_identifier
+        let other_app_witness = rln_witness_from_values_with_identifier(
+            identity_secret_hash,
+            &merkle_proof,
+            x,
+            epoch,
+            hash_to_field(b"test-rln-identifier"),
+        );
+        assert_eq!(validate_witness(&other_app_witness), Ok(()));
+        assert_eq!(
+            validate_witness_identifier(
+                &other_app_witness,
+                &hash_to_field(crate::public::RLN_IDENTIFIER)
+            ),
+            Err(WitnessValidationError::RlnIdentifierMismatch)
+        );
+        assert_eq!(
+            validate_witness_identifier(
+                &rln_witness,
+                &hash_to_field(crate::public::RLN_IDENTIFIER)
+            ),
+            Ok(())
+        );
+    }
+
+    #[test]
+    // We test proving and verifying with an application RLN identifier other than the default one
+    fn test_proof_for_app_rln_identifier() {
+        let leaf_index = 3;
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let rln_identifier = rln_identifier_from_app(b"my-chat-app/v1");
+        let rln_witness = rln_witness_from_values_with_identifier(
+            identity_secret_hash,
+            &merkle_proof,
+            signal_to_field(b"hey hey"),
+            hash_to_field(b"test-epoch"),
+            rln_identifier,
+        );
+        assert_eq!(
+            validate_witness_identifier(&rln_witness, &rln_identifier),
+            Ok(())
+        );
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        assert_eq!(proof_values.rln_identifier, rln_identifier);
+        assert_eq!(proof_values.root, tree.root());
+
+        assert!(verify_proof(&verification_key, &proof, &proof_values).unwrap());
+    }
+
+    #[test]
+    fn test_witness_diff() {
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+        assert!(witness_diff(&rln_witness, &rln_witness.clone()).is_empty());
+
+        // We change the epoch and a path element
+        let mut serialized = serialize_witness(&rln_witness);
+        let path_element_offset = 1 + 32 + 8 + 32 * 3;
+        serialized[path_element_offset] ^= 1;
+        let epoch_offset = serialized.len() - 2 * 32;
+        serialized[epoch_offset] ^= 1;
+        let (other_witness, _) = deserialize_witness(&serialized).unwrap();
+
+        let diff = witness_diff(&rln_witness, &other_witness);
+        assert_eq!(diff.len(), 2);
+        assert!(diff[0].starts_with("epoch: 0x"));
+        assert!(diff[1].starts_with("path_elements[3]: 0x"));
+
+        // We compare with a witness for a different tree height
+        let other_witness = random_rln_witness(TEST_TREE_HEIGHT - 1);
+        let diff = witness_diff(&rln_witness, &other_witness);
+        assert!(diff.contains(&format!(
+            "path_elements.len(): {} != {}",
+            TEST_TREE_HEIGHT,
+            TEST_TREE_HEIGHT - 1
+        )));
+        assert!(diff.contains(&format!(
+            "identity_path_index.len(): {} != {}",
+            TEST_TREE_HEIGHT,
+            TEST_TREE_HEIGHT - 1
+        )));
+    }
+
+    #[test]
+    fn test_generate_proofs_batch() {
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let witnesses: Vec<RLNWitnessInput> = (0..3)
+            .map(|_| random_rln_witness(TEST_TREE_HEIGHT))
+            .collect();
+
+        let proofs = generate_proofs_batch(builder, &proving_key, &witnesses).unwrap();
+        assert_eq!(proofs.len(), witnesses.len());
+        for (proof, rln_witness) in proofs.iter().zip(&witnesses) {
+            let proof_values = proof_values_from_witness(rln_witness).unwrap();
+            assert!(verify_proof(&verification_key, proof, &proof_values).unwrap());
+        }
+
+        // The index of the failing witness is reported
+        let mut witnesses = witnesses;
+        let mut serialized = serialize_witness(&witnesses[2]);
+        let path_index_offset = 1 + 32 + 8 + 32 * TEST_TREE_HEIGHT + 8;
+        serialized[path_index_offset] = 2;
+        witnesses[2] = deserialize_witness(&serialized).unwrap().0;
+
+        match generate_proofs_batch(builder, &proving_key, &witnesses) {
+            Err(ProofError::BatchItemFailed { index, source }) => {
+                assert_eq!(index, 2);
+                assert!(matches!(*source, ProofError::InvalidWitness(_)));
+            }
+            _ => panic!("batch proof generation should fail"),
+        }
+
+        // When several witnesses fail, the lowest failing index is reported
+        witnesses[1] = witnesses[2].clone();
+        match generate_proofs_batch(builder, &proving_key, &witnesses) {
+            Err(ProofError::BatchItemFailed { index, .. }) => assert_eq!(index, 1),
+            _ => panic!("batch proof generation should fail"),
+        }
+    }
+
+    #[test]
+    fn test_rln_witness_to_kilic_inputs() {
+        let leaf_index = 3;
+        let signal = b"hey hey";
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let epoch = hash_to_field(b"test-epoch");
+        let rln_witness = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            signal_to_field(signal),
+            epoch,
+        );
+
+        // We round-trip through the kilic format
+        let serialized = rln_witness_to_kilic_inputs(&rln_witness, leaf_index, signal).unwrap();
+        assert_eq!(
+            serialized,
+            prepare_prove_input(identity_secret_hash, leaf_index, epoch, signal).unwrap()
+        );
+        let (deser, read) = proof_inputs_to_rln_witness(&mut tree, &serialized).unwrap();
+        assert_eq!(deser, rln_witness);
+        assert_eq!(read, serialized.len());
+    }
+
+    #[test]
+    // We test that a slashed member removed from the tree can no longer prove membership
+    fn test_proof_after_leaf_deletion() {
+        let leaf_index = 3;
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+
+        let (identity_secret_hash, id_commitment) = keygen();
+
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(leaf_index, id_commitment).unwrap();
+        assert!(tree.is_occupied(leaf_index).unwrap());
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+
+        let rln_witness = rln_witness_from_values(
+            identity_secret_hash,
+            &merkle_proof,
+            signal_to_field(b"hey hey"),
+            hash_to_field(b"test-epoch"),
+        );
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        assert!(
+            verify_proof_with_roots(&verification_key, &proof, &proof_values, &[tree.root()])
+                .unwrap()
+        );
+
+        // The member is removed from the tree
+        tree.delete(leaf_index).unwrap();
+        assert!(!tree.is_occupied(leaf_index).unwrap());
+
+        // Proofs against the old root are rejected, and the old Merkle proof doesn't match the updated root
+        assert!(
+            !verify_proof_with_roots(&verification_key, &proof, &proof_values, &[tree.root()])
+                .unwrap()
+        );
+        assert!(!tree.verify(&id_commitment, &merkle_proof).unwrap());
+        let merkle_proof = tree.proof(leaf_index).expect("proof should exist");
+        assert_ne!(merkle_proof.compute_root_from(&id_commitment), tree.root());
+    }
+
+    #[test]
+    fn test_rln_identifier_from_app() {
+        let rln_identifier = rln_identifier_from_app(b"my-chat-app/v1");
+
+        // The identifier is reproducible and domain-separated
+        assert_eq!(rln_identifier, rln_identifier_from_app(b"my-chat-app/v1"));
+        assert_eq!(
+            rln_identifier,
+            hash_to_field_with_domain(RLN_IDENTIFIER_DOMAIN, b"my-chat-app/v1")
+        );
+        assert_ne!(rln_identifier, hash_to_field(b"my-chat-app/v1"));
+
+        // Different apps get different identifiers, hence different external nullifiers for the same epoch
+        let other_rln_identifier = rln_identifier_from_app(b"my-chat-app/v2");
+        assert_ne!(rln_identifier, other_rln_identifier);
+        let epoch = hash_to_field(b"test-epoch");
+        assert_ne!(
+            poseidon_hash(&[epoch, rln_identifier]),
+            poseidon_hash(&[epoch, other_rln_identifier])
+        );
+    }
+
+    #[test]
+    fn test_epoch_in_window() {
+        let current_epoch = Fr::from(100);
+
+        assert!(epoch_in_window(Fr::from(100), current_epoch, 0));
+        assert!(epoch_in_window(Fr::from(99), current_epoch, 1));
+        assert!(epoch_in_window(Fr::from(101), current_epoch, 1));
+        assert!(!epoch_in_window(Fr::from(98), current_epoch, 1));
+        assert!(!epoch_in_window(Fr::from(99), current_epoch, 0));
+
+        // Window bounds don't overflow
+        assert!(epoch_in_window(Fr::from(0), Fr::from(u64::MAX), u64::MAX));
+        assert!(epoch_in_window(Fr::from(u64::MAX), Fr::from(0), u64::MAX));
+
+        // Epochs not fitting in a u64 (e.g. -1 or hashed epochs) never wrap into the window
+        assert!(!epoch_in_window(-Fr::from(1), Fr::from(0), 1));
+        assert!(!epoch_in_window(
+            Fr::from(u64::MAX) + Fr::from(1),
+            Fr::from(u64::MAX),
+            u64::MAX
+        ));
+        assert!(!epoch_in_window(
+            current_epoch,
+            hash_to_field(b"test-epoch"),
+            u64::MAX
+        ));
+    }
+
+    #[test]
+    fn test_external_nullifiers_for_epochs() {
+        let rln_identifier = hash_to_field(crate::public::RLN_IDENTIFIER);
+        let epochs: Vec<Fr> = (0..5u64).map(Fr::from).collect();
+
+        let external_nullifiers = external_nullifiers_for_epochs(rln_identifier, &epochs);
+        assert_eq!(external_nullifiers.len(), epochs.len());
+        for (epoch, external_nullifier) in epochs.iter().zip(&external_nullifiers) {
+            assert_eq!(
+                *external_nullifier,
+                compute_external_nullifier(*epoch, rln_identifier)
+            );
+            assert_eq!(
+                *external_nullifier,
+                poseidon_hash(&[*epoch, rln_identifier])
+            );
         }
+        assert!(external_nullifiers_for_epochs(rln_identifier, &[]).is_empty());
 
-        let rln_witness = rln_witness_from_json(witness_json);
+        // The cached external nullifiers give the nullifiers of a member for the upcoming epochs
+        let identity_secret = hash_to_field(b"identity secret");
+        // At x = 1, the share is y = identity_secret + a_1 and the nullifier is PoseidonHash(a_1)
+        let (_, y) = eval_share(identity_secret, external_nullifiers[1], Fr::from(1));
+        assert_eq!(
+            poseidon_hash(&[y - identity_secret]),
+            nullifier_from_secret(identity_secret, epochs[1], rln_identifier)
+        );
+    }
 
-        let ser = serialize_witness(&rln_witness);
-        let (deser, _) = deserialize_witness(&ser);
-        assert_eq!(rln_witness, deser);
+    #[test]
+    fn test_nullifier_epoch_consistent() {
+        let identity_secret = hash_to_field(b"identity secret");
+        let rln_identifier = hash_to_field(b"rln identifier");
+        let proof_values = |epoch: &[u8], x: &[u8]| {
+            let epoch = hash_to_field(epoch);
+            let x = hash_to_field(x);
+            let (_, y) = eval_share(identity_secret, poseidon_hash(&[epoch, rln_identifier]), x);
+            RLNProofValues {
+                y,
+                nullifier: nullifier_from_secret(identity_secret, epoch, rln_identifier),
+                root: hash_to_field(b"root"),
+                x,
+                epoch,
+                rln_identifier,
+            }
+        };
 
-        // We test Proof values serialization
-        let proof_values = proof_values_from_witness(&rln_witness);
-        let ser = serialize_proof_values(&proof_values);
-        let (deser, _) = deserialize_proof_values(&ser);
-        assert_eq!(proof_values, deser);
+        // Nullifiers differ across epochs, and repeat within the same epoch
+        let mut values = vec![
+            proof_values(b"epoch 1", b"hey hey"),
+            proof_values(b"epoch 1", b"ho ho"),
+            proof_values(b"epoch 2", b"hey hey"),
+        ];
+        assert_eq!(nullifier_epoch_consistent(&values), Ok(()));
+        assert_eq!(nullifier_epoch_consistent(&[]), Ok(()));
+
+        // The same nullifier in another epoch is flagged
+        let mut malformed = proof_values(b"epoch 3", b"hey hey");
+        malformed.nullifier = values[0].nullifier;
+        values.push(malformed);
+        assert!(nullifier_epoch_consistent(&values).is_err());
+    }
+
+    #[test]
+    fn test_nullifier_log() {
+        let (identity_secret_hash, id_commitment) = keygen();
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(0, id_commitment).unwrap();
+        let merkle_proof = tree.proof(0).expect("proof should exist");
+
+        let proof_values_for = |signal: &[u8], epoch: Fr| {
+            proof_values_from_witness(&rln_witness_from_values(
+                identity_secret_hash,
+                &merkle_proof,
+                signal_to_field(signal),
+                epoch,
+            ))
+            .unwrap()
+        };
+
+        let mut nullifier_log = NullifierLog::new();
+        assert!(nullifier_log.is_empty());
+
+        // A first message, and its replay, don't reveal the secret
+        let proof_values_1 = proof_values_for(b"hey hey", Fr::from(1));
+        assert_eq!(nullifier_log.observe(&proof_values_1), None);
+        assert_eq!(nullifier_log.observe(&proof_values_1), None);
+        assert!(nullifier_log.contains(&proof_values_1.epoch, &proof_values_1.nullifier));
+        assert_eq!(nullifier_log.len(), 1);
+
+        // A message in another epoch is fine
+        let proof_values_2 = proof_values_for(b"ho ho", Fr::from(2));
+        assert_eq!(nullifier_log.observe(&proof_values_2), None);
+        assert_eq!(nullifier_log.len(), 2);
+
+        // A second message in the same epoch reveals the secret
+        let proof_values_3 = proof_values_for(b"ho ho", Fr::from(1));
+        assert_eq!(
+            nullifier_log.observe(&proof_values_3),
+            Some(identity_secret_hash)
+        );
+        assert_eq!(nullifier_log.len(), 2);
+
+        // Epochs out of the window are pruned
+        nullifier_log.prune(Fr::from(3), 1);
+        assert!(!nullifier_log.contains(&proof_values_1.epoch, &proof_values_1.nullifier));
+        assert!(nullifier_log.contains(&proof_values_2.epoch, &proof_values_2.nullifier));
+        nullifier_log.remove_epoch(&Fr::from(2));
+        assert!(nullifier_log.is_empty());
+
+        // Hashed epochs are pruned according to the order they were observed in
+        let epochs: Vec<Fr> = [b"epoch-1", b"epoch-2", b"epoch-3"]
+            .iter()
+            .map(|epoch| hash_to_field(*epoch))
+            .collect();
+        let hashed_proof_values: Vec<RLNProofValues> = epochs
+            .iter()
+            .map(|epoch| proof_values_for(b"hey hey", *epoch))
+            .collect();
+        for proof_values in hashed_proof_values.iter() {
+            assert_eq!(nullifier_log.observe(proof_values), None);
+        }
+
+        nullifier_log.prune(epochs[2], 1);
+        assert!(!nullifier_log.contains(&epochs[0], &hashed_proof_values[0].nullifier));
+        assert!(nullifier_log.contains(&epochs[1], &hashed_proof_values[1].nullifier));
+        assert!(nullifier_log.contains(&epochs[2], &hashed_proof_values[2].nullifier));
+
+        // The current epoch is never pruned
+        nullifier_log.prune(epochs[2], 0);
+        assert_eq!(nullifier_log.len(), 1);
+        assert!(nullifier_log.contains(&epochs[2], &hashed_proof_values[2].nullifier));
+    }
+
+    #[test]
+    fn test_signal_to_field_epoch_bound() {
+        let signal = b"hey hey";
+        let epoch_1 = hash_to_field(b"test-epoch-1");
+        let epoch_2 = hash_to_field(b"test-epoch-2");
+
+        let x_1 = signal_to_field_epoch_bound(signal, epoch_1);
+        assert_eq!(x_1, signal_to_field_epoch_bound(signal, epoch_1));
+        assert_eq!(
+            x_1,
+            hash_to_field_with_domain(
+                EPOCH_BOUND_SIGNAL_DOMAIN,
+                &[fr_to_bytes_le(&epoch_1), signal.to_vec()].concat()
+            )
+        );
+
+        // The same signal maps to different values in different epochs, none being the unbound one
+        let x_2 = signal_to_field_epoch_bound(signal, epoch_2);
+        assert_ne!(x_1, x_2);
+        assert_ne!(x_1, signal_to_field(signal));
+        assert_ne!(x_2, signal_to_field(signal));
+        assert_ne!(x_1, signal_to_field_epoch_bound(b"ho ho", epoch_1));
+    }
+
+    #[test]
+    fn test_signal_hasher() {
+        let mut hasher = SignalHasher::new(2);
+        assert!(hasher.is_empty());
+
+        let signals: [&[u8]; 3] = [b"pinned", b"other", b"another"];
+        for signal in signals {
+            assert_eq!(hasher.hash(signal), signal_to_field(signal));
+        }
+        assert_eq!(hasher.len(), hasher.capacity());
+        assert!(!hasher.contains(b"pinned"));
+
+        // Hits return the same value and refresh the entry, so that the least recently used signal is evicted
+        assert_eq!(hasher.hash(b"other"), signal_to_field(b"other"));
+        assert_eq!(hasher.hash(b"pinned"), signal_to_field(b"pinned"));
+        assert!(hasher.contains(b"other"));
+        assert!(hasher.contains(b"pinned"));
+        assert!(!hasher.contains(b"another"));
+        assert_eq!(hasher.len(), 2);
+
+        // A zero capacity disables caching
+        let mut hasher = SignalHasher::new(0);
+        assert_eq!(hasher.hash(b"pinned"), signal_to_field(b"pinned"));
+        assert!(hasher.is_empty());
+    }
+
+    #[test]
+    fn test_proof_error_kind() {
+        assert_eq!(
+            ProofError::TreeIndexOutOfBounds {
+                index: 4,
+                capacity: 4
+            }
+            .kind(),
+            "TreeIndexOutOfBounds"
+        );
+        assert_eq!(
+            ProofError::from(SerializationError::UnknownInputKind(8)).kind(),
+            "SerializationError"
+        );
+        assert_eq!(
+            ProofError::from(WitnessValidationError::ZeroX).kind(),
+            "InvalidWitness"
+        );
+        assert_eq!(
+            ProofError::from(std::io::Error::from(std::io::ErrorKind::NotFound)).kind(),
+            "CircuitKeyError"
+        );
+        assert_eq!(
+            ProofError::TreeError(std::io::Error::from(std::io::ErrorKind::InvalidInput)).kind(),
+            "TreeError"
+        );
+    }
+
+    #[test]
+    fn test_public_input_count_mismatch() {
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+        assert_eq!(
+            verification_key.gamma_abc_g1.len() - 1,
+            public_inputs(&proof_values).len()
+        );
+
+        // A verifying key for a circuit with a different number of public inputs is reported
+        let mut mismatched_key = verification_key.clone();
+        mismatched_key.gamma_abc_g1.pop();
+        assert!(matches!(
+            verify_proof(&mismatched_key, &proof, &proof_values),
+            Err(ProofError::PublicInputCountMismatch {
+                expected: 5,
+                got: 6
+            })
+        ));
+    }
+
+    #[test]
+    fn test_verify_proof_against_root() {
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+        let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+
+        // The proof verifies against the root it was generated for
+        let trusted_root = proof_values.root;
+        assert!(
+            verify_proof_against_root(&verification_key, &proof, &proof_values, &trusted_root)
+                .unwrap()
+        );
+
+        // A proof generated against another root is rejected, even if its proof values assert the trusted root
+        let other_root = hash_to_field(b"another root");
+        assert!(
+            !verify_proof_against_root(&verification_key, &proof, &proof_values, &other_root)
+                .unwrap()
+        );
+        let mut asserted_proof_values = proof_values.clone();
+        asserted_proof_values.root = other_root;
+        assert!(!verify_proof(&verification_key, &proof, &asserted_proof_values).unwrap());
+        assert!(!verify_proof_against_root(
+            &verification_key,
+            &proof,
+            &asserted_proof_values,
+            &other_root
+        )
+        .unwrap());
+        assert!(verify_proof_against_root(
+            &verification_key,
+            &proof,
+            &asserted_proof_values,
+            &trusted_root
+        )
+        .unwrap());
+    }
+
+    #[test]
+    // We check public_inputs against the public signals of the circuit, which follow the constant one in the full assignment
+    fn test_public_inputs() {
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+        let inputs = public_inputs(&proof_values);
+        assert_eq!(
+            inputs,
+            vec![
+                proof_values.y,
+                proof_values.root,
+                proof_values.nullifier,
+                proof_values.x,
+                proof_values.epoch,
+                proof_values.rln_identifier,
+            ]
+        );
+
+        let mut builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+        let full_assignment = builder.calculate(&rln_witness).unwrap();
+        assert_eq!(&full_assignment[1..=inputs.len()], &inputs[..]);
+    }
+
+    #[test]
+    fn test_proof_values_hash_set() {
+        use std::collections::HashSet;
+
+        let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        let other_proof_values =
+            proof_values_from_witness(&random_rln_witness(TEST_TREE_HEIGHT)).unwrap();
+
+        let mut seen = HashSet::new();
+        assert!(seen.insert(proof_values.clone()));
+        assert!(seen.insert(other_proof_values));
+
+        // Replayed proof values, e.g. deserialized again from the same message, are detected
+        let (replayed, _) = deserialize_proof_values(&serialize_proof_values(&proof_values));
+        assert!(!seen.insert(replayed));
+        assert_eq!(seen.len(), 2);
+        assert!(seen.contains(&proof_values));
+    }
+
+    #[test]
+    fn test_commitment_from_identity() {
+        let (identity_trapdoor, identity_nullifier, identity_secret_hash, id_commitment) =
+            extended_keygen();
+
+        // The tree leaf is restored from the trapdoor and nullifier only
+        assert_eq!(
+            commitment_from_identity(identity_trapdoor, identity_nullifier),
+            (identity_secret_hash, id_commitment)
+        );
+
+        // Trapdoor and nullifier are not interchangeable
+        assert_ne!(
+            commitment_from_identity(identity_nullifier, identity_trapdoor),
+            (identity_secret_hash, id_commitment)
+        );
+    }
+
+    #[test]
+    fn test_nullifier_from_secret() {
+        let (identity_secret_hash, id_commitment) = keygen();
+        let mut tree = PoseidonTree::default(TEST_TREE_HEIGHT);
+        tree.set(0, id_commitment).unwrap();
+
+        let epoch = hash_to_field(b"test-epoch");
+        let rln_identifier = hash_to_field(crate::public::RLN_IDENTIFIER);
+        let nullifier = nullifier_from_secret(identity_secret_hash, epoch, rln_identifier);
+
+        // The derivation matches the one of proof values
+        let proof_values = proof_values_from_witness(&rln_witness_from_values(
+            identity_secret_hash,
+            &tree.proof(0).unwrap(),
+            signal_to_field(b"hey hey"),
+            epoch,
+        ))
+        .unwrap();
+        assert_eq!(nullifier, proof_values.nullifier);
+
+        // And the one checked by verify_nullifier
+        assert!(verify_nullifier(
+            &identity_secret_hash,
+            &poseidon_hash(&[epoch, rln_identifier]),
+            &nullifier
+        ));
+
+        // Each epoch gives a different nullifier
+        assert_ne!(
+            nullifier,
+            nullifier_from_secret(
+                identity_secret_hash,
+                hash_to_field(b"test-epoch-2"),
+                rln_identifier
+            )
+        );
+    }
+
+    #[test]
+    fn test_witness_format_version() {
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+        let serialized = serialize_witness(&rln_witness);
+        assert_eq!(serialized[0], WITNESS_FORMAT_VERSION);
+        assert_eq!(
+            deserialize_witness(&serialized),
+            Ok((rln_witness.clone(), serialized.len()))
+        );
+
+        // Unknown versions are rejected rather than misparsed
+        let mut unsupported = serialized.clone();
+        unsupported[0] = WITNESS_FORMAT_VERSION + 1;
+        assert_eq!(
+            deserialize_witness(&unsupported),
+            Err(SerializationError::UnsupportedVersion(
+                WITNESS_FORMAT_VERSION + 1
+            ))
+        );
+        assert_eq!(
+            deserialize_witness(&[]),
+            Err(SerializationError::InvalidLength {
+                expected: 1,
+                actual: 0
+            })
+        );
+
+        // Legacy witnesses are the versioned ones without the version byte
+        #[cfg(feature = "legacy-witness")]
+        {
+            let legacy = &serialized[1..];
+            assert_eq!(
+                deserialize_legacy_witness(legacy),
+                Ok((rln_witness, legacy.len()))
+            );
+            assert_eq!(migrate_legacy_witness(legacy), Ok(serialized));
+        }
+    }
+
+    #[test]
+    fn test_deserialize_witness_prefix() {
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+        let serialized = serialize_witness(&rln_witness);
+
+        // A witness followed by other data is read from the front of the buffer
+        let trailing = b"trailing data";
+        let buffer = [serialized.clone(), trailing.to_vec()].concat();
+        let (deser, read) = deserialize_witness_prefix(&buffer).unwrap();
+        assert_eq!(deser, rln_witness);
+        assert_eq!(read, serialized.len());
+        assert_eq!(&buffer[read..], trailing);
+
+        // deserialize_witness rejects trailing data with an error rather than a panic
+        assert_eq!(
+            deserialize_witness(&buffer),
+            Err(SerializationError::InvalidLength {
+                expected: serialized.len(),
+                actual: buffer.len()
+            })
+        );
+
+        // Truncated witnesses are rejected
+        assert!(matches!(
+            deserialize_witness_prefix(&serialized[..serialized.len() - 1]),
+            Err(SerializationError::InvalidLength { .. })
+        ));
+    }
+
+    #[test]
+    fn test_witness_framed_serialization() {
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+        let serialized = serialize_witness(&rln_witness);
+        let framed = serialize_witness_framed(&rln_witness);
+
+        assert_eq!(framed.len(), 4 + serialized.len());
+        assert_eq!(&framed[4..], &serialized[..]);
+
+        // Framed witnesses can be concatenated with other data
+        let mut message = framed.clone();
+        message.extend_from_slice(b"trailing data");
+        let (deser, read) = deserialize_witness_framed(&message).unwrap();
+        assert_eq!(deser, rln_witness);
+        assert_eq!(read, framed.len());
+        assert_eq!(&message[read..], b"trailing data");
+
+        // A declared length exceeding the buffer is rejected
+        let truncated = &framed[..framed.len() - 1];
+        assert_eq!(
+            deserialize_witness_framed(truncated),
+            Err(SerializationError::InvalidLength {
+                expected: framed.len(),
+                actual: framed.len() - 1
+            })
+        );
+        assert!(deserialize_witness_framed(&framed[..3]).is_err());
+    }
+
+    #[test]
+    fn test_try_parse_any() {
+        use rand::{RngCore, SeedableRng};
+        use rand_chacha::ChaCha20Rng;
+
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+        let mut witness_input = vec![3u8];
+        witness_input.append(&mut serialize_witness(&rln_witness));
+        assert_eq!(
+            try_parse_any(&witness_input),
+            Ok(ParsedInput::Witness(rln_witness.clone()))
+        );
+
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+        let mut proof_values_input = vec![4u8];
+        proof_values_input.append(&mut serialize_proof_values(&proof_values));
+        assert_eq!(
+            try_parse_any(&proof_values_input),
+            Ok(ParsedInput::ProofValues(proof_values))
+        );
+
+        let identity_secret = hash_to_field(b"test-try-parse-any");
+        let epoch = hash_to_field(b"test-epoch");
+        let mut prove_input = vec![7u8];
+        prove_input
+            .append(&mut prepare_prove_input(identity_secret, 5, epoch, b"hey hey").unwrap());
+        assert_eq!(
+            try_parse_any(&prove_input),
+            Ok(ParsedInput::ProveInput {
+                identity_secret,
+                id_index: 5,
+                epoch,
+                signal: b"hey hey".to_vec()
+            })
+        );
+
+        // Empty inputs, unknown kinds and trailing bytes are rejected
+        assert!(try_parse_any(&[]).is_err());
+        assert_eq!(
+            try_parse_any(&[8u8]),
+            Err(SerializationError::UnknownInputKind(8))
+        );
+        let mut extended = witness_input.clone();
+        extended.push(0);
+        assert!(try_parse_any(&extended).is_err());
+
+        // Truncated inputs and huge length prefixes return errors instead of panicking
+        for input in [&witness_input, &proof_values_input, &prove_input] {
+            for len in 0..input.len() {
+                assert!(try_parse_any(&input[..len]).is_err());
+            }
+        }
+        let mut huge_len = vec![3u8, WITNESS_FORMAT_VERSION];
+        huge_len.append(&mut fr_to_bytes_le(&identity_secret));
+        huge_len.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert!(matches!(
+            try_parse_any(&huge_len),
+            Err(SerializationError::InvalidLength { .. })
+        ));
+
+        // Random inputs of any kind never panic
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        for i in 0..1000 {
+            let mut input = vec![0u8; 1 + i % 400];
+            rng.fill_bytes(&mut input);
+            input[0] %= 9;
+            let _ = try_parse_any(&input);
+        }
+    }
+
+    #[test]
+    fn test_witness_from_json_reader() {
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+        let rln_witness_from_reader =
+            rln_witness_from_json_reader(WITNESS_JSON_20.as_bytes()).unwrap();
+        assert_eq!(rln_witness_from_reader, rln_witness);
+
+        // Truncated JSON gives a parse error
+        let truncated = &WITNESS_JSON_20[..WITNESS_JSON_20.len() / 2];
+        assert!(rln_witness_from_json(truncated).is_err());
+        assert!(rln_witness_from_json_reader(truncated.as_bytes()).is_err());
+
+        // So do missing fields and fields with the wrong type
+        assert!(rln_witness_from_json(r#"{ "x": "1" }"#).is_err());
+        let mut input_json: serde_json::Value = serde_json::from_str(WITNESS_JSON_20).unwrap();
+        input_json["identity_path_index"] = serde_json::json!("0");
+        assert!(rln_witness_from_json(&input_json.to_string()).is_err());
+    }
+
+    #[test]
+    fn test_witness_clone() {
+        let rln_witness = rln_witness_from_json(WITNESS_JSON_20).unwrap();
+
+        // A cloned witness can be consumed independently from the original one
+        let cloned_witness = rln_witness.clone();
+        let (deser, _) = deserialize_witness(&serialize_witness(&cloned_witness)).unwrap();
+        drop(cloned_witness);
+
+        assert_eq!(rln_witness, deser);
+        assert_eq!(
+            proof_values_from_witness(&rln_witness).unwrap(),
+            proof_values_from_witness(&deser).unwrap()
+        );
     }
 
     #[test]
@@ -483,4 +3678,40 @@ mod test {
         );
         assert_eq!(id_commitment, expected_id_commitment_seed_phrase);
     }
+
+    #[test]
+    // Tests derivation of identity families from a single seed
+    fn test_seeded_keygen_with_index() {
+        use ark_std::UniformRand;
+        use rand::SeedableRng;
+        use rand_chacha::ChaCha20Rng;
+
+        let seed_phrase = b"A seed phrase example";
+
+        // The seed used by seeded_keygen is exposed
+        let mut rng = ChaCha20Rng::from_seed(derive_seed(seed_phrase));
+        assert_eq!(seeded_keygen(seed_phrase).0, Fr::rand(&mut rng));
+
+        // Identities of the same family are deterministic and pairwise different
+        let identities: Vec<(Fr, Fr)> = (0..4)
+            .map(|index| seeded_keygen_with_index(seed_phrase, index))
+            .collect();
+        for (index, identity) in identities.iter().enumerate() {
+            assert_eq!(
+                *identity,
+                seeded_keygen_with_index(seed_phrase, index as u64)
+            );
+            assert_eq!(identity.1, id_commitment_from_secret(&identity.0));
+            assert_ne!(*identity, seeded_keygen(seed_phrase));
+            for other in identities.iter().skip(index + 1) {
+                assert_ne!(identity, other);
+            }
+        }
+
+        // Different master seeds yield different families
+        assert_ne!(
+            seeded_keygen_with_index(seed_phrase, 0),
+            seeded_keygen_with_index(b"Another seed phrase", 0)
+        );
+    }
 }
@@ -4,11 +4,18 @@
 
 use crate::circuit::Fr;
 use crate::poseidon_hash::poseidon_hash;
+use crate::protocol::SerializationError;
+use crate::utils::{bytes_le_to_vec_fr, fr_byte_size, vec_fr_to_bytes_le};
 use cfg_if::cfg_if;
+use std::collections::VecDeque;
+use std::io;
 use utils::merkle_tree::*;
 
 // The zerokit RLN default Merkle tree implementation is the OptimalMerkleTree.
 // To switch to FullMerkleTree implementation, it is enough to enable the fullmerkletree feature
+// Trees created with PoseidonTree::default have zero as empty leaf (see PoseidonHash::default_leaf), while
+// PoseidonTree::new(depth, empty_leaf) allows to use a custom empty leaf value, e.g. a nothing-up-my-sleeve value
+// distinguishing empty leaves from leaves holding a zero commitment
 
 cfg_if! {
     if #[cfg(feature = "fullmerkletree")] {
@@ -37,6 +44,138 @@ impl utils::merkle_tree::Hasher for PoseidonHash {
     }
 }
 
+// A PoseidonTree wrapper recording the last roots taken by the tree.
+// Proofs generated against a slightly stale tree can then be accepted by checking
+// their root against the history before running the (expensive) zkSNARK verification
+pub struct RootHistory {
+    tree: PoseidonTree,
+    roots: VecDeque<Fr>,
+    max_roots: usize,
+}
+
+impl RootHistory {
+    // Wraps the tree, recording up to max_roots roots. The current tree root is the first recorded root
+    pub fn new(tree: PoseidonTree, max_roots: usize) -> Self {
+        assert!(max_roots > 0, "at least one root must be recorded");
+
+        let mut roots = VecDeque::with_capacity(max_roots);
+        roots.push_back(tree.root());
+
+        RootHistory {
+            tree,
+            roots,
+            max_roots,
+        }
+    }
+
+    // Returns the wrapped tree
+    pub fn tree(&self) -> &PoseidonTree {
+        &self.tree
+    }
+
+    // Returns the current tree root
+    pub fn root(&self) -> Fr {
+        self.tree.root()
+    }
+
+    // Returns the maximum number of recorded roots
+    pub fn max_roots(&self) -> usize {
+        self.max_roots
+    }
+
+    // Returns the recorded roots, from the oldest to the current one
+    pub fn roots(&self) -> impl Iterator<Item = &Fr> {
+        self.roots.iter()
+    }
+
+    // Returns true if root is one of the recorded roots
+    pub fn contains_root(&self, root: &Fr) -> bool {
+        self.roots.contains(root)
+    }
+
+    // Serializes the recorded roots, from the oldest to the current one, as [ roots_count<8> | root<32> * roots_count ],
+    // e.g. to pass the acceptable roots across the FFI boundary. The roots can be parsed back with deserialize_roots
+    pub fn serialize_roots(&self) -> Vec<u8> {
+        let roots: Vec<Fr> = self.roots.iter().copied().collect();
+        vec_fr_to_bytes_le(&roots)
+    }
+
+    // Parses roots serialized with serialize_roots.
+    // Returns an error if the input length doesn't match the roots count prefix
+    pub fn deserialize_roots(serialized: &[u8]) -> Result<Vec<Fr>, SerializationError> {
+        if serialized.len() < 8 {
+            return Err(SerializationError::InvalidLength {
+                expected: 8,
+                actual: serialized.len(),
+            });
+        }
+
+        let roots_count = u64::from_le_bytes(serialized[..8].try_into().unwrap());
+        let expected = usize::try_from(roots_count)
+            .ok()
+            .and_then(|count| count.checked_mul(fr_byte_size()))
+            .and_then(|len| len.checked_add(8))
+            .unwrap_or(usize::MAX);
+        if serialized.len() != expected {
+            return Err(SerializationError::InvalidLength {
+                expected,
+                actual: serialized.len(),
+            });
+        }
+
+        let (roots, _) = bytes_le_to_vec_fr(serialized);
+        Ok(roots)
+    }
+
+    // Sets a leaf at the specified tree index and records the new root
+    pub fn set(&mut self, index: usize, leaf: Fr) -> io::Result<()> {
+        self.tree.set(index, leaf)?;
+        self.record_root();
+        Ok(())
+    }
+
+    // Sets a leaf at the next available index and records the new root
+    pub fn update_next(&mut self, leaf: Fr) -> io::Result<()> {
+        self.tree.update_next(leaf)?;
+        self.record_root();
+        Ok(())
+    }
+
+    // Sets multiple leaves from the specified tree index and records the root after each insertion (see set_leaves_tracked).
+    // Returns the recorded roots, which may be more than max_roots: only the last max_roots of them are kept in the history
+    pub fn set_leaves_tracked<I: IntoIterator<Item = Fr>>(
+        &mut self,
+        start: usize,
+        leaves: I,
+    ) -> io::Result<Vec<Fr>> {
+        let roots = self.tree.set_leaves_tracked(start, leaves)?;
+        for root in roots.iter() {
+            self.push_root(*root);
+        }
+        Ok(roots)
+    }
+
+    // Deletes the leaf at the specified tree index and records the new root
+    pub fn delete(&mut self, index: usize) -> io::Result<()> {
+        self.tree.delete(index)?;
+        self.record_root();
+        Ok(())
+    }
+
+    // Records the current tree root, evicting the oldest one if max_roots roots are already recorded
+    fn record_root(&mut self) {
+        self.push_root(self.tree.root());
+    }
+
+    // Records root, evicting the oldest recorded root if max_roots roots are already recorded
+    fn push_root(&mut self, root: Fr) {
+        if self.roots.len() == self.max_roots {
+            self.roots.pop_front();
+        }
+        self.roots.push_back(root);
+    }
+}
+
 ////////////////////////////////////////////////////////////
 /// Tests
 ////////////////////////////////////////////////////////////
@@ -45,6 +184,154 @@ impl utils::merkle_tree::Hasher for PoseidonHash {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_root_history() {
+        let tree_height = 10;
+        let max_roots = 3;
+
+        let mut root_history = RootHistory::new(PoseidonTree::default(tree_height), max_roots);
+        let initial_root = root_history.root();
+        assert!(root_history.contains_root(&initial_root));
+
+        let mut roots = vec![initial_root];
+        for i in 0..max_roots {
+            root_history.update_next(Fr::from(i as u64 + 1)).unwrap();
+            roots.push(root_history.root());
+        }
+
+        // Only the last max_roots roots are recorded: the initial one was evicted
+        assert!(!root_history.contains_root(&roots[0]));
+        for root in roots.iter().skip(1) {
+            assert!(root_history.contains_root(root));
+        }
+        assert_eq!(
+            root_history.roots().cloned().collect::<Vec<Fr>>(),
+            roots[1..].to_vec()
+        );
+
+        // Deleting a leaf records the new root and evicts the oldest one
+        root_history.delete(0).unwrap();
+        assert!(root_history.contains_root(&root_history.root()));
+        assert!(!root_history.contains_root(&roots[1]));
+    }
+
+    #[test]
+    fn test_root_history_set_leaves_tracked() {
+        let tree_height = 10;
+        let max_roots = 3;
+        let leaves: Vec<Fr> = (1..=5).map(Fr::from).collect();
+
+        let mut root_history = RootHistory::new(PoseidonTree::default(tree_height), max_roots);
+        let roots = root_history.set_leaves_tracked(2, leaves.clone()).unwrap();
+
+        // Roots match the ones taken by a tree where leaves are set one at a time
+        let mut expected_tree = PoseidonTree::default(tree_height);
+        let mut expected_roots = Vec::new();
+        for (i, leaf) in leaves.iter().enumerate() {
+            expected_tree.set(2 + i, *leaf).unwrap();
+            expected_roots.push(expected_tree.root());
+        }
+        assert_eq!(roots, expected_roots);
+        assert_eq!(root_history.root(), expected_tree.root());
+
+        // Only the last max_roots roots are recorded
+        assert_eq!(
+            root_history.roots().cloned().collect::<Vec<Fr>>(),
+            roots[roots.len() - max_roots..].to_vec()
+        );
+
+        // Out of range insertions leave the history untouched
+        assert!(root_history.set_leaves_tracked(usize::MAX, leaves).is_err());
+        assert_eq!(root_history.root(), expected_tree.root());
+    }
+
+    #[test]
+    fn test_custom_empty_leaf() {
+        let tree_height = 10;
+        let empty_leaf = Fr::from(42);
+
+        let mut tree = PoseidonTree::new(tree_height, empty_leaf);
+        assert_eq!(tree.default_leaf(), empty_leaf);
+
+        // The empty subtree hashes are computed from the empty leaf
+        let mut empty_root = empty_leaf;
+        for _ in 0..tree_height {
+            empty_root = poseidon_hash(&[empty_root, empty_root]);
+        }
+        assert_eq!(tree.root(), empty_root);
+        assert_ne!(tree.root(), PoseidonTree::default(tree_height).root());
+
+        // A zero commitment is distinguishable from an empty leaf
+        assert!(!tree.is_occupied(0).unwrap());
+        tree.set(0, Fr::from(0)).unwrap();
+        assert!(tree.is_occupied(0).unwrap());
+
+        // Proofs of other leaves use the empty leaf for unset siblings
+        let leaf = Fr::from(1);
+        tree.set(3, leaf).unwrap();
+        let proof = tree.proof(3).unwrap();
+        assert!(tree.verify(&leaf, &proof).unwrap());
+        assert_eq!(proof.compute_root_from(&leaf), tree.root());
+        assert_eq!(proof.get_path_elements()[0], empty_leaf);
+
+        // Deleted leaves are reset to the empty leaf
+        tree.delete(0).unwrap();
+        tree.delete(3).unwrap();
+        assert_eq!(tree.root(), empty_root);
+    }
+
+    #[test]
+    fn test_serialize_roots() {
+        let tree_height = 10;
+        let max_roots = 3;
+
+        let mut root_history = RootHistory::new(PoseidonTree::default(tree_height), max_roots);
+        for i in 0..5 {
+            root_history.update_next(Fr::from(i as u64 + 1)).unwrap();
+        }
+
+        let serialized = root_history.serialize_roots();
+        assert_eq!(serialized.len(), 8 + max_roots * fr_byte_size());
+        assert_eq!(&serialized[..8], &(max_roots as u64).to_le_bytes());
+
+        let roots = RootHistory::deserialize_roots(&serialized).unwrap();
+        assert_eq!(roots, root_history.roots().cloned().collect::<Vec<Fr>>());
+        assert!(roots.iter().all(|root| root_history.contains_root(root)));
+
+        // Inputs whose length doesn't match the roots count are rejected
+        assert_eq!(
+            RootHistory::deserialize_roots(&serialized[..serialized.len() - 1]),
+            Err(SerializationError::InvalidLength {
+                expected: serialized.len(),
+                actual: serialized.len() - 1
+            })
+        );
+        assert!(RootHistory::deserialize_roots(&serialized[..4]).is_err());
+        let mut huge_count = serialized.clone();
+        huge_count[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(RootHistory::deserialize_roots(&huge_count).is_err());
+    }
+
+    #[test]
+    fn test_from_leaves() {
+        let tree_height = 10;
+        let leaves: Vec<Fr> = (0..5).map(|i| Fr::from(i as u64 + 1)).collect();
+
+        let tree = PoseidonTree::from_leaves(tree_height, &leaves).unwrap();
+        let mut expected_tree = PoseidonTree::default(tree_height);
+        for (i, leaf) in leaves.iter().enumerate() {
+            expected_tree.set(i, *leaf).unwrap();
+        }
+        assert_eq!(tree.root(), expected_tree.root());
+
+        let leaves = vec![Fr::from(1); 1 << 3];
+        assert!(PoseidonTree::from_leaves(3, &leaves).is_ok());
+        assert!(matches!(
+            PoseidonTree::from_leaves(2, &leaves),
+            Err(TreeError::TooManyLeaves { .. })
+        ));
+    }
+
     #[test]
     /// A basic performance comparison between the two supported Merkle Tree implementations
     fn test_zerokit_merkle_implementations_performances() {
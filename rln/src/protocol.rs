@@ -1,26 +1,38 @@
 // This crate collects all the underlying primitives used to implement RLN
 
-use ark_circom::{CircomReduction, WitnessCalculator};
+use ark_bn254::Fq;
+use ark_circom::{read_zkey, CircomReduction, WitnessCalculator};
+use ark_ec::PairingEngine;
+use ark_ff::{BigInteger, PrimeField, Zero};
 use ark_groth16::{
     create_proof_with_reduction_and_matrices, prepare_verifying_key,
-    verify_proof as ark_verify_proof, Proof as ArkProof, ProvingKey, VerifyingKey,
+    verify_proof as ark_verify_proof, PreparedVerifyingKey, Proof as ArkProof, ProvingKey,
+    VerifyingKey,
 };
 use ark_relations::r1cs::ConstraintMatrices;
 use ark_relations::r1cs::SynthesisError;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
 use ark_std::{rand::thread_rng, UniformRand};
 use color_eyre::Result;
-use num_bigint::BigInt;
-use rand::{Rng, SeedableRng};
+use num_bigint::{BigInt, BigUint};
+use num_traits::Num;
+use rand::{CryptoRng, Rng, SeedableRng};
 use rand_chacha::ChaCha20Rng;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Cursor, Read};
 #[cfg(not(target_arch = "wasm32"))]
-use std::sync::Mutex;
-#[cfg(debug_assertions)]
+use std::sync::{Mutex, PoisonError};
+// std::time::Instant::now panics on wasm32-unknown-unknown, so timings are recorded only on other targets
+#[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
 use std::time::Instant;
 use thiserror::Error;
 use tiny_keccak::{Hasher as _, Keccak};
 
 use crate::circuit::{Curve, Fr};
-use crate::poseidon_hash::poseidon_hash;
+use crate::poseidon_hash::{poseidon_hash, ROUND_PARAMS};
 use crate::poseidon_tree::*;
 use crate::public::RLN_IDENTIFIER;
 use crate::utils::*;
@@ -30,26 +42,41 @@ use cfg_if::cfg_if;
 // RLN Witness data structure and utility functions
 ///////////////////////////////////////////////////////
 
-#[derive(Debug, PartialEq)]
-pub struct RLNWitnessInput {
-    identity_secret: Fr,
-    path_elements: Vec<Fr>,
+// Witness and proof values are generic over the pairing engine used for proving, and default to Curve (i.e. BN254).
+// All utility functions operating on them, as Poseidon hashing and serialization, are implemented for the default Curve only
+#[derive(Debug, PartialEq, Clone)]
+pub struct RLNWitnessInput<E: PairingEngine = Curve> {
+    identity_secret: E::Fr,
+    path_elements: Vec<E::Fr>,
     identity_path_index: Vec<u8>,
-    x: Fr,
-    epoch: Fr,
-    rln_identifier: Fr,
+    x: E::Fr,
+    epoch: E::Fr,
+    rln_identifier: E::Fr,
 }
 
-#[derive(Debug, PartialEq)]
-pub struct RLNProofValues {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RLNProofValues<E: PairingEngine = Curve> {
     // Public outputs:
-    pub y: Fr,
-    pub nullifier: Fr,
-    pub root: Fr,
+    pub y: E::Fr,
+    pub nullifier: E::Fr,
+    pub root: E::Fr,
     // Public Inputs:
-    pub x: Fr,
-    pub epoch: Fr,
-    pub rln_identifier: Fr,
+    pub x: E::Fr,
+    pub epoch: E::Fr,
+    pub rln_identifier: E::Fr,
+}
+
+// Hashes all the proof values, so that proof values can be deduplicated with a HashSet (e.g. for replay detection).
+// Field elements have a unique representation, hence this is consistent with equality and with serialize_proof_values
+impl<E: PairingEngine> Hash for RLNProofValues<E> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.y.hash(state);
+        self.nullifier.hash(state);
+        self.root.hash(state);
+        self.x.hash(state);
+        self.epoch.hash(state);
+        self.rln_identifier.hash(state);
+    }
 }
 
 pub fn serialize_field_element(element: Fr) -> Vec<u8> {
@@ -91,10 +118,17 @@ pub fn deserialize_identity_tuple(serialized: Vec<u8>) -> (Fr, Fr, Fr, Fr) {
     );
 }
 
+// The version of the witness serialization format, written as first byte by serialize_witness.
+// Version 0 denotes the legacy unversioned format, i.e. the current format without the version byte
+pub const WITNESS_FORMAT_VERSION: u8 = 1;
+
+// Serializes a witness as [ version<1> | identity_secret<32> | path_elements<var> | identity_path_index<var> | x<32> | epoch<32> | rln_identifier<32> ],
+// where version is WITNESS_FORMAT_VERSION
 pub fn serialize_witness(rln_witness: &RLNWitnessInput) -> Vec<u8> {
-    let mut serialized: Vec<u8> = Vec::new();
+    let mut serialized: Vec<u8> = vec![WITNESS_FORMAT_VERSION];
 
-    serialized.append(&mut fr_to_bytes_le(&rln_witness.identity_secret));
+    // The identity secret is serialized in constant time
+    serialized.append(&mut fr_to_bytes_le_ct(&rln_witness.identity_secret));
     serialized.append(&mut vec_fr_to_bytes_le(&rln_witness.path_elements));
     serialized.append(&mut vec_u8_to_bytes_le(&rln_witness.identity_path_index));
     serialized.append(&mut fr_to_bytes_le(&rln_witness.x));
@@ -104,51 +138,192 @@ pub fn serialize_witness(rln_witness: &RLNWitnessInput) -> Vec<u8> {
     serialized
 }
 
-pub fn deserialize_witness(serialized: &[u8]) -> (RLNWitnessInput, usize) {
-    let mut all_read: usize = 0;
+// Deserializes a witness serialized with serialize_witness, returning the witness and the number of bytes read.
+// Returns an error if the format version is not WITNESS_FORMAT_VERSION or the buffer is not exactly a serialized witness:
+// use deserialize_witness_prefix to read a witness followed by other data
+pub fn deserialize_witness(
+    serialized: &[u8],
+) -> Result<(RLNWitnessInput, usize), SerializationError> {
+    let (rln_witness, all_read) = deserialize_witness_prefix(serialized)?;
+    if all_read != serialized.len() {
+        return Err(SerializationError::InvalidLength {
+            expected: all_read,
+            actual: serialized.len(),
+        });
+    }
 
-    let (identity_secret, read) = bytes_le_to_fr(&serialized[all_read..].to_vec());
-    all_read += read;
+    Ok((rln_witness, all_read))
+}
 
-    let (path_elements, read) = bytes_le_to_vec_fr(&serialized[all_read..].to_vec());
-    all_read += read;
+// Deserializes a witness serialized with serialize_witness from the front of serialized, which may hold other data after it.
+// Returns the witness and the number of bytes read, so that callers can continue parsing after the witness,
+// or an error if the format version is not WITNESS_FORMAT_VERSION or the buffer is too short
+pub fn deserialize_witness_prefix(
+    serialized: &[u8],
+) -> Result<(RLNWitnessInput, usize), SerializationError> {
+    let mut reader = CheckedReader::new(serialized);
+    let rln_witness = reader.read_witness()?;
+    Ok((rln_witness, reader.all_read))
+}
 
-    let (identity_path_index, read) = bytes_le_to_vec_u8(&serialized[all_read..].to_vec());
-    all_read += read;
+// Deserializes a witness serialized in the legacy unversioned format (version 0), i.e. without the leading version byte.
+// Since the first byte of legacy witnesses is part of the identity secret, the version of a serialized witness can't be detected:
+// callers must know their witnesses are legacy ones, and can migrate them with migrate_legacy_witness
+#[cfg(feature = "legacy-witness")]
+pub fn deserialize_legacy_witness(
+    serialized: &[u8],
+) -> Result<(RLNWitnessInput, usize), SerializationError> {
+    let mut reader = CheckedReader::new(serialized);
+    let rln_witness = reader.read_unversioned_witness()?;
+    Ok((rln_witness, reader.all_read))
+}
 
-    let (x, read) = bytes_le_to_fr(&serialized[all_read..].to_vec());
-    all_read += read;
+// Converts a witness serialized in the legacy unversioned format (version 0) to the current serialize_witness format
+#[cfg(feature = "legacy-witness")]
+pub fn migrate_legacy_witness(serialized: &[u8]) -> Result<Vec<u8>, SerializationError> {
+    let (rln_witness, _) = deserialize_legacy_witness(serialized)?;
+    Ok(serialize_witness(&rln_witness))
+}
 
-    let (epoch, read) = bytes_le_to_fr(&serialized[all_read..].to_vec());
-    all_read += read;
+// Returns a human-readable description of each field differing between two witnesses, e.g.
+// "path_elements[3]: 0x.. != 0x..". An empty vector means the witnesses are equal
+pub fn witness_diff(a: &RLNWitnessInput, b: &RLNWitnessInput) -> Vec<String> {
+    fn fr_to_hex(el: &Fr) -> String {
+        format!("0x{:064x}", to_bigint(el))
+    }
 
-    let (rln_identifier, read) = bytes_le_to_fr(&serialized[all_read..].to_vec());
-    all_read += read;
+    let mut diff = Vec::new();
+
+    for (name, value_a, value_b) in [
+        ("identity_secret", &a.identity_secret, &b.identity_secret),
+        ("x", &a.x, &b.x),
+        ("epoch", &a.epoch, &b.epoch),
+        ("rln_identifier", &a.rln_identifier, &b.rln_identifier),
+    ] {
+        if value_a != value_b {
+            diff.push(format!(
+                "{name}: {} != {}",
+                fr_to_hex(value_a),
+                fr_to_hex(value_b)
+            ));
+        }
+    }
 
-    // TODO: check rln_identifier against public::RLN_IDENTIFIER
-    assert_eq!(serialized.len(), all_read);
+    if a.path_elements.len() != b.path_elements.len() {
+        diff.push(format!(
+            "path_elements.len(): {} != {}",
+            a.path_elements.len(),
+            b.path_elements.len()
+        ));
+    }
+    for (i, (value_a, value_b)) in a.path_elements.iter().zip(&b.path_elements).enumerate() {
+        if value_a != value_b {
+            diff.push(format!(
+                "path_elements[{i}]: {} != {}",
+                fr_to_hex(value_a),
+                fr_to_hex(value_b)
+            ));
+        }
+    }
 
-    (
-        RLNWitnessInput {
-            identity_secret,
-            path_elements,
-            identity_path_index,
-            x,
-            epoch,
-            rln_identifier,
-        },
-        all_read,
+    if a.identity_path_index.len() != b.identity_path_index.len() {
+        diff.push(format!(
+            "identity_path_index.len(): {} != {}",
+            a.identity_path_index.len(),
+            b.identity_path_index.len()
+        ));
+    }
+    for (i, (value_a, value_b)) in a
+        .identity_path_index
+        .iter()
+        .zip(&b.identity_path_index)
+        .enumerate()
+    {
+        if value_a != value_b {
+            diff.push(format!("identity_path_index[{i}]: {value_a} != {value_b}"));
+        }
+    }
+
+    diff
+}
+
+// This function serializes a rln witness as input for kilic's rln generate_proof public API, i.e. it is the inverse of proof_inputs_to_rln_witness.
+// The kilic format doesn't carry the Merkle proof: it is derived from the tree using id_index,
+// so id_index and signal must be the ones used to build the witness, i.e. the witness path must be the one of leaf id_index
+// and its x must be signal_to_field(signal).
+// Returns an error if the signal is longer than MAX_SIGNAL_LEN
+pub fn rln_witness_to_kilic_inputs(
+    rln_witness: &RLNWitnessInput,
+    id_index: usize,
+    signal: &[u8],
+) -> Result<Vec<u8>, SerializationError> {
+    prepare_prove_input(
+        rln_witness.identity_secret,
+        id_index,
+        rln_witness.epoch,
+        signal,
     )
 }
 
+// Serializes a witness as [ witness_len<4> | witness<var> ], where witness_len is the little-endian length of
+// serialize_witness(rln_witness). The framing makes the witness self-delimiting when embedded in larger messages
+pub fn serialize_witness_framed(rln_witness: &RLNWitnessInput) -> Vec<u8> {
+    let mut witness = serialize_witness(rln_witness);
+    let witness_len = u32::try_from(witness.len()).expect("serialized witness fits in 4 GiB");
+
+    let mut serialized: Vec<u8> = Vec::with_capacity(4 + witness.len());
+    serialized.extend_from_slice(&witness_len.to_le_bytes());
+    serialized.append(&mut witness);
+
+    serialized
+}
+
+// Deserializes a witness serialized with serialize_witness_framed, reading exactly the declared number of bytes.
+// Returns the witness and the number of bytes read (frame header included),
+// or an error if the buffer is shorter than the frame header or the declared length
+pub fn deserialize_witness_framed(
+    serialized: &[u8],
+) -> Result<(RLNWitnessInput, usize), SerializationError> {
+    if serialized.len() < 4 {
+        return Err(SerializationError::InvalidLength {
+            expected: 4,
+            actual: serialized.len(),
+        });
+    }
+
+    let witness_len = u32::from_le_bytes(serialized[..4].try_into().unwrap()) as usize;
+    let frame_len = 4 + witness_len;
+    if serialized.len() < frame_len {
+        return Err(SerializationError::InvalidLength {
+            expected: frame_len,
+            actual: serialized.len(),
+        });
+    }
+
+    let (rln_witness, _) = deserialize_witness(&serialized[4..frame_len])?;
+
+    Ok((rln_witness, frame_len))
+}
+
 // This function deserializes input for kilic's rln generate_proof public API
 // https://github.com/kilic/rln/blob/7ac74183f8b69b399e3bc96c1ae8ab61c026dc43/src/public.rs#L148
 // input_data is [ identity_secret<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]
-// return value is a rln witness populated according to this information
+// return value is a rln witness populated according to this information,
+// or an error if input_data is too short or id_index is out of the tree bounds
 pub fn proof_inputs_to_rln_witness(
     tree: &mut PoseidonTree,
     serialized: &[u8],
-) -> (RLNWitnessInput, usize) {
+) -> Result<(RLNWitnessInput, usize), ProofError> {
+    // The length of [ identity_secret<32> | id_index<8> | epoch<32> | signal_len<8> ]
+    let header_len = fr_byte_size() + 8 + fr_byte_size() + 8;
+    if serialized.len() < header_len {
+        return Err(SerializationError::InvalidLength {
+            expected: header_len,
+            actual: serialized.len(),
+        }
+        .into());
+    }
+
     let mut all_read: usize = 0;
 
     let (identity_secret, read) = bytes_le_to_fr(&serialized[all_read..].to_vec());
@@ -163,77 +338,129 @@ pub fn proof_inputs_to_rln_witness(
     let signal_len = u64::from_le_bytes(serialized[all_read..all_read + 8].try_into().unwrap());
     all_read += 8;
 
-    let signal: Vec<u8> = serialized[all_read..all_read + (signal_len as usize)].to_vec();
-
-    let merkle_proof = tree.proof(id_index as usize).expect("proof should exist");
-    let path_elements = merkle_proof.get_path_elements();
-    let identity_path_index = merkle_proof.get_path_index();
+    let signal_end = usize::try_from(signal_len)
+        .ok()
+        .and_then(|signal_len| all_read.checked_add(signal_len))
+        .filter(|signal_end| *signal_end <= serialized.len())
+        .ok_or(SerializationError::InvalidLength {
+            expected: all_read.saturating_add(signal_len as usize),
+            actual: serialized.len(),
+        })?;
+    let signal: Vec<u8> = serialized[all_read..signal_end].to_vec();
+
+    // Indexes not fitting in a usize (e.g. above u32::MAX on wasm32) are out of bounds, rather than truncated to another leaf
+    let index = usize::try_from(id_index).map_err(|_| ProofError::TreeIndexOutOfBounds {
+        index: id_index,
+        capacity: tree.capacity(),
+    })?;
+    let rln_witness = witness_from_tree(tree, index, identity_secret, &signal, epoch)?;
+
+    Ok((rln_witness, all_read))
+}
 
-    let x = hash_to_field(&signal);
+// The canonical encoding of epochs in witness JSON, i.e. a 0x-prefixed 64 digits hexadecimal string, as in zk-kit witnesses.
+// This is the encoding emitted by get_json_inputs and parsed by rln_witness_from_json, so that epochs round-trip unchanged
+pub fn epoch_to_json_string(epoch: &Fr) -> String {
+    format!("0x{:064x}", to_bigint(epoch))
+}
 
-    let rln_identifier = hash_to_field(RLN_IDENTIFIER);
+// Parses an epoch from witness JSON. The radix is selected by the prefix: 0x-prefixed strings (as emitted by epoch_to_json_string)
+// are hexadecimal, while strings without prefix are decimal as all other witness values (e.g. as emitted by get_json_inputs_decimal)
+pub fn epoch_from_json_string(input: &str) -> Fr {
+    let input = input.trim();
+    if input.starts_with("0x") {
+        str_to_fr(input, 16)
+    } else {
+        str_to_fr(input, 10)
+    }
+}
 
-    (
-        RLNWitnessInput {
-            identity_secret,
-            path_elements,
-            identity_path_index,
-            x,
-            epoch,
-            rln_identifier,
-        },
-        all_read,
-    )
+// Parses a RLN witness from its JSON representation, where epoch is encoded as parsed by epoch_from_json_string
+// (i.e. 0x-prefixed hexadecimal in the canonical encoding) and all other field elements are decimal strings.
+// Returns an error if the input is not well-formatted JSON or a field is missing or has the wrong type
+pub fn rln_witness_from_json(input_json_str: &str) -> Result<RLNWitnessInput, serde_json::Error> {
+    let input_json: serde_json::Value = serde_json::from_str(input_json_str)?;
+    rln_witness_from_json_value(input_json)
 }
 
-pub fn rln_witness_from_json(input_json_str: &str) -> RLNWitnessInput {
-    let input_json: serde_json::Value =
-        serde_json::from_str(input_json_str).expect("JSON was not well-formatted");
+// Parses a RLN witness from a reader of its JSON representation, with no need to load the whole input in memory first.
+// Returns an error if the input is not well-formatted JSON or a field is missing or has the wrong type
+pub fn rln_witness_from_json_reader<R: Read>(
+    reader: R,
+) -> Result<RLNWitnessInput, serde_json::Error> {
+    let input_json: serde_json::Value = serde_json::from_reader(reader)?;
+    rln_witness_from_json_value(input_json)
+}
 
-    let identity_secret = str_to_fr(&input_json["identity_secret"].to_string(), 10);
+fn rln_witness_from_json_value(
+    mut input_json: serde_json::Value,
+) -> Result<RLNWitnessInput, serde_json::Error> {
+    let identity_secret = str_to_fr(
+        &serde_json::from_value::<String>(input_json["identity_secret"].take())?,
+        10,
+    );
 
-    let path_elements = input_json["path_elements"]
-        .as_array()
-        .unwrap()
+    let path_elements = serde_json::from_value::<Vec<String>>(input_json["path_elements"].take())?
         .iter()
-        .map(|v| str_to_fr(&v.to_string(), 10))
+        .map(|v| str_to_fr(v, 10))
         .collect();
 
-    let identity_path_index = input_json["identity_path_index"]
-        .as_array()
-        .unwrap()
-        .iter()
-        .map(|v| v.as_u64().unwrap() as u8)
-        .collect();
+    let identity_path_index =
+        serde_json::from_value::<Vec<u8>>(input_json["identity_path_index"].take())?;
 
-    let x = str_to_fr(&input_json["x"].to_string(), 10);
+    let x = str_to_fr(
+        &serde_json::from_value::<String>(input_json["x"].take())?,
+        10,
+    );
 
-    let epoch = str_to_fr(&input_json["epoch"].to_string(), 16);
+    let epoch = epoch_from_json_string(&serde_json::from_value::<String>(
+        input_json["epoch"].take(),
+    )?);
 
-    let rln_identifier = str_to_fr(&input_json["rln_identifier"].to_string(), 10);
+    let rln_identifier = str_to_fr(
+        &serde_json::from_value::<String>(input_json["rln_identifier"].take())?,
+        10,
+    );
 
     // TODO: check rln_identifier against public::RLN_IDENTIFIER
 
-    RLNWitnessInput {
+    Ok(RLNWitnessInput {
         identity_secret,
         path_elements,
         identity_path_index,
         x,
         epoch,
         rln_identifier,
-    }
+    })
 }
 
+// Builds a RLN witness for the default application RLN identifier, i.e. hash_to_field(RLN_IDENTIFIER)
 pub fn rln_witness_from_values(
     identity_secret: Fr,
     merkle_proof: &MerkleProof,
     x: Fr,
     epoch: Fr,
-    //rln_identifier: Fr,
+) -> RLNWitnessInput {
+    rln_witness_from_values_with_identifier(
+        identity_secret,
+        merkle_proof,
+        x,
+        epoch,
+        hash_to_field(RLN_IDENTIFIER),
+    )
+}
+
+// Builds a RLN witness for the provided RLN identifier.
+// Distinct identifiers give distinct external nullifiers (and hence nullifiers) for the same identity and epoch
+pub fn rln_witness_from_values_with_identifier(
+    identity_secret: Fr,
+    merkle_proof: &MerkleProof,
+    x: Fr,
+    epoch: Fr,
+    rln_identifier: Fr,
 ) -> RLNWitnessInput {
     let path_elements = merkle_proof.get_path_elements();
     let identity_path_index = merkle_proof.get_path_index();
-    let rln_identifier = hash_to_field(RLN_IDENTIFIER);
 
     RLNWitnessInput {
         identity_secret,
@@ -245,9 +472,148 @@ pub fn rln_witness_from_values(
     }
 }
 
+// Builds the RLN witness for the member at leaf index of tree sending signal in epoch, i.e. fetches the Merkle proof of index,
+// computes x = signal_to_field(signal) and uses the default application RLN identifier, i.e. hash_to_field(RLN_IDENTIFIER).
+// Returns an error if index is out of the tree bounds
+pub fn witness_from_tree(
+    tree: &PoseidonTree,
+    index: usize,
+    identity_secret: Fr,
+    signal: &[u8],
+    epoch: Fr,
+) -> Result<RLNWitnessInput, ProofError> {
+    if index >= tree.capacity() {
+        return Err(ProofError::TreeIndexOutOfBounds {
+            index: index as u64,
+            capacity: tree.capacity(),
+        });
+    }
+
+    let merkle_proof = tree.proof(index).map_err(ProofError::TreeError)?;
+
+    Ok(rln_witness_from_values(
+        identity_secret,
+        &merkle_proof,
+        signal_to_field(signal),
+        epoch,
+    ))
+}
+
+// Checks that the witness Merkle proof has the length expected by a circuit compiled for trees of height tree_height
+pub fn validate_tree_height<E: PairingEngine>(
+    rln_witness: &RLNWitnessInput<E>,
+    tree_height: usize,
+) -> Result<(), ProofError> {
+    for actual in [
+        rln_witness.path_elements.len(),
+        rln_witness.identity_path_index.len(),
+    ] {
+        if actual != tree_height {
+            return Err(ProofError::TreeHeightMismatch {
+                expected: tree_height,
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum WitnessValidationError {
+    #[error("Merkle proof is inconsistent: {path_elements} path elements, {identity_path_index} path indexes")]
+    PathLengthMismatch {
+        path_elements: usize,
+        identity_path_index: usize,
+    },
+    #[error("Invalid path index {value} at level {level}: path indexes must be 0 or 1")]
+    InvalidPathIndex { level: usize, value: u8 },
+    #[error("RLN identifier doesn't match the application RLN identifier")]
+    RlnIdentifierMismatch,
+    #[error("x is zero: the share y would be equal to the identity secret")]
+    ZeroX,
+}
+
+// Checks that the witness is well-formed before it is passed to the witness calculator, i.e. that
+// the Merkle proof path elements and indexes have the same length, that each path index is 0 or 1 and that x is not 0.
+// Proofs for x = 0 would publish the share y = a_0, i.e. the identity secret, hence they are never generated.
+// Any RLN identifier is accepted: applications can check it with validate_witness_identifier
+pub fn validate_witness<E: PairingEngine>(
+    rln_witness: &RLNWitnessInput<E>,
+) -> Result<(), WitnessValidationError> {
+    if rln_witness.path_elements.len() != rln_witness.identity_path_index.len() {
+        return Err(WitnessValidationError::PathLengthMismatch {
+            path_elements: rln_witness.path_elements.len(),
+            identity_path_index: rln_witness.identity_path_index.len(),
+        });
+    }
+
+    if let Some((level, value)) = rln_witness
+        .identity_path_index
+        .iter()
+        .enumerate()
+        .find(|(_, value)| **value > 1)
+    {
+        return Err(WitnessValidationError::InvalidPathIndex {
+            level,
+            value: *value,
+        });
+    }
+
+    if rln_witness.x.is_zero() {
+        return Err(WitnessValidationError::ZeroX);
+    }
+
+    Ok(())
+}
+
+// Checks that the witness passes validate_witness and that its RLN identifier is the expected application one,
+// e.g. hash_to_field(RLN_IDENTIFIER) for the default application or the output of rln_identifier_from_app
+pub fn validate_witness_identifier<E: PairingEngine>(
+    rln_witness: &RLNWitnessInput<E>,
+    expected_rln_identifier: &E::Fr,
+) -> Result<(), WitnessValidationError> {
+    validate_witness(rln_witness)?;
+
+    if rln_witness.rln_identifier != *expected_rln_identifier {
+        return Err(WitnessValidationError::RlnIdentifierMismatch);
+    }
+
+    Ok(())
+}
+
+// Checks that the witness passes validate_witness and that its Merkle proof has exactly tree_height levels (see validate_tree_height),
+// i.e. that the path indexes encode a position in a tree of the height the circuit was compiled for.
+// Path indexes are bits, so a wrong path length or a non-boolean index would otherwise yield a wrong root
+pub fn validate_witness_with_height<E: PairingEngine>(
+    rln_witness: &RLNWitnessInput<E>,
+    tree_height: usize,
+) -> Result<(), ProofError> {
+    validate_witness(rln_witness)?;
+    validate_tree_height(rln_witness, tree_height)
+}
+
 pub fn random_rln_witness(tree_height: usize) -> RLNWitnessInput {
     let mut rng = thread_rng();
+    rln_witness_from_rng(&mut rng, tree_height)
+}
+
+// Registers a dummy member with a fresh random identity in tree (see register) and builds its witness for signal in epoch.
+// Unlike random_rln_witness, the witness root is the tree root, so proofs generated from it verify against the tree,
+// e.g. to pad anonymity sets or benchmark the whole proving and verification flow without real identities.
+// Returns the identity secret of the dummy member together with its witness, or an error if the tree is full
+pub fn dummy_member_witness(
+    tree: &mut PoseidonTree,
+    epoch: Fr,
+    signal: &[u8],
+) -> Result<(Fr, RLNWitnessInput), ProofError> {
+    let (index, identity_secret, _) = register(tree).map_err(ProofError::TreeError)?;
+    let rln_witness = witness_from_tree(tree, index, identity_secret, signal, epoch)?;
+    Ok((identity_secret, rln_witness))
+}
 
+// Generates a witness with all values sampled from the provided RNG
+fn rln_witness_from_rng<R: Rng>(rng: &mut R, tree_height: usize) -> RLNWitnessInput {
     let identity_secret = hash_to_field(&rng.gen::<[u8; 32]>());
     let x = hash_to_field(&rng.gen::<[u8; 32]>());
     let epoch = hash_to_field(&rng.gen::<[u8; 32]>());
@@ -271,9 +637,39 @@ pub fn random_rln_witness(tree_height: usize) -> RLNWitnessInput {
     }
 }
 
-pub fn proof_values_from_witness(rln_witness: &RLNWitnessInput) -> RLNProofValues {
+// Generates a witness where all values are sampled from 20 rounds of ChaCha seeded with seed.
+// The same seed and tree_height always give the same witness, so that other RLN implementations can pin it as test vector
+#[cfg(feature = "test-vectors")]
+pub fn deterministic_rln_witness(seed: [u8; 32], tree_height: usize) -> RLNWitnessInput {
+    let mut rng = ChaCha20Rng::from_seed(seed);
+    rln_witness_from_rng(&mut rng, tree_height)
+}
+
+// Returns the expected proof values and circuit JSON inputs for the witness deterministic_rln_witness(seed, tree_height)
+#[cfg(feature = "test-vectors")]
+pub fn deterministic_test_vector(
+    seed: [u8; 32],
+    tree_height: usize,
+) -> (RLNProofValues, serde_json::Value) {
+    let rln_witness = deterministic_rln_witness(seed, tree_height);
+    (
+        proof_values_from_witness(&rln_witness)
+            .expect("randomly sampled witnesses are valid, x being 0 with negligible probability"),
+        get_json_inputs(&rln_witness),
+    )
+}
+
+// Computes the public values of the proof for the witness.
+// The witness is first checked with validate_witness: in particular, witnesses with x = 0 are rejected,
+// since the share y would then be the identity secret and publishing it would leak it
+pub fn proof_values_from_witness(
+    rln_witness: &RLNWitnessInput,
+) -> Result<RLNProofValues, WitnessValidationError> {
+    validate_witness(rln_witness)?;
+
     // y share
-    let external_nullifier = poseidon_hash(&[rln_witness.epoch, rln_witness.rln_identifier]);
+    let external_nullifier =
+        compute_external_nullifier(rln_witness.epoch, rln_witness.rln_identifier);
     let a_0 = rln_witness.identity_secret;
     let a_1 = poseidon_hash(&[a_0, external_nullifier]);
     let y = a_0 + rln_witness.x * a_1;
@@ -287,18 +683,145 @@ pub fn proof_values_from_witness(rln_witness: &RLNWitnessInput) -> RLNProofValue
         &rln_witness.path_elements,
         &rln_witness.identity_path_index,
         true,
-    );
+    )
+    .expect("validated witnesses have a well-formed Merkle proof");
 
-    RLNProofValues {
+    Ok(RLNProofValues {
         y,
         nullifier,
         root,
         x: rln_witness.x,
         epoch: rln_witness.epoch,
         rln_identifier: rln_witness.rln_identifier,
+    })
+}
+
+// Computes the proof values for each witness.
+// Proof values are returned in the order of witnesses, each one being an error if its witness is invalid (see proof_values_from_witness).
+// With the parallel feature enabled, witnesses are processed in parallel
+pub fn proof_values_batch(
+    rln_witnesses: &[RLNWitnessInput],
+) -> Vec<Result<RLNProofValues, WitnessValidationError>> {
+    cfg_if! {
+        if #[cfg(feature = "parallel")] {
+            rln_witnesses.par_iter().map(proof_values_from_witness).collect()
+        } else {
+            rln_witnesses.iter().map(proof_values_from_witness).collect()
+        }
+    }
+}
+
+// A RLN witness for a batch of messages sent in the same epoch and sharing one membership proof,
+// for circuits with multiple message slots as RLN-v2 ones, where each message has an x and a message_id.
+// No such circuit is shipped with zerokit yet: these structures let integrators build the inputs and the expected
+// public values of a multi-message circuit, while proofs for the current circuit are still generated one message at a time
+#[derive(Debug, PartialEq, Clone)]
+pub struct RLNMultiMessageWitnessInput<E: PairingEngine = Curve> {
+    identity_secret: E::Fr,
+    path_elements: Vec<E::Fr>,
+    identity_path_index: Vec<u8>,
+    // The (x, message_id) pair of each message
+    messages: Vec<(E::Fr, E::Fr)>,
+    epoch: E::Fr,
+    rln_identifier: E::Fr,
+}
+
+// The public values of a multi-message proof, with one share (x, y) and one nullifier per message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RLNMultiMessageProofValues<E: PairingEngine = Curve> {
+    // Public outputs:
+    pub ys: Vec<E::Fr>,
+    pub nullifiers: Vec<E::Fr>,
+    pub root: E::Fr,
+    // Public Inputs:
+    pub xs: Vec<E::Fr>,
+    pub epoch: E::Fr,
+    pub rln_identifier: E::Fr,
+}
+
+// Builds a multi-message RLN witness for the default application RLN identifier, i.e. hash_to_field(RLN_IDENTIFIER),
+// where messages holds the (x, message_id) pair of each message
+pub fn rln_multi_message_witness_from_values(
+    identity_secret: Fr,
+    merkle_proof: &MerkleProof,
+    messages: &[(Fr, Fr)],
+    epoch: Fr,
+) -> RLNMultiMessageWitnessInput {
+    RLNMultiMessageWitnessInput {
+        identity_secret,
+        path_elements: merkle_proof.get_path_elements(),
+        identity_path_index: merkle_proof.get_path_index(),
+        messages: messages.to_vec(),
+        epoch,
+        rln_identifier: hash_to_field(RLN_IDENTIFIER),
+    }
+}
+
+// Computes the public values of the multi-message proof for the witness.
+// As in RLN-v2, the share of each message is y = a_0 + x * a_1 with a_1 = PoseidonHash(identity_secret, external_nullifier, message_id)
+// and its nullifier is PoseidonHash(a_1), so that messages with distinct message_id have distinct nullifiers,
+// while reusing a message_id in the same epoch reveals the identity secret
+pub fn proof_values_from_multi_message_witness(
+    rln_witness: &RLNMultiMessageWitnessInput,
+) -> RLNMultiMessageProofValues {
+    let external_nullifier =
+        compute_external_nullifier(rln_witness.epoch, rln_witness.rln_identifier);
+    let a_0 = rln_witness.identity_secret;
+
+    let mut ys = Vec::with_capacity(rln_witness.messages.len());
+    let mut nullifiers = Vec::with_capacity(rln_witness.messages.len());
+    let mut xs = Vec::with_capacity(rln_witness.messages.len());
+    for (x, message_id) in &rln_witness.messages {
+        let a_1 = poseidon_hash(&[a_0, external_nullifier, *message_id]);
+        ys.push(a_0 + *x * a_1);
+        nullifiers.push(poseidon_hash(&[a_1]));
+        xs.push(*x);
+    }
+
+    // All messages share the same membership proof
+    let root = compute_tree_root(
+        &rln_witness.identity_secret,
+        &rln_witness.path_elements,
+        &rln_witness.identity_path_index,
+        true,
+    )
+    .expect("witness Merkle proof should be well-formed (see validate_witness)");
+
+    RLNMultiMessageProofValues {
+        ys,
+        nullifiers,
+        root,
+        xs,
+        epoch: rln_witness.epoch,
+        rln_identifier: rln_witness.rln_identifier,
     }
 }
 
+// The length in bytes of a serialized field element
+pub const SERIALIZED_FR_LEN: usize = 32;
+// The length in bytes of a serialized (compressed) zkSNARK proof
+pub const SERIALIZED_PROOF_LEN: usize = 128;
+// The length in bytes of serialized proof values, i.e. [ root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> | rln_identifier<32> ]
+pub const SERIALIZED_PROOF_VALUES_LEN: usize = 6 * SERIALIZED_FR_LEN;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum SerializationError {
+    #[error("Invalid serialized data length: expected {expected} bytes, got {actual}")]
+    InvalidLength { expected: usize, actual: usize },
+    #[error("Invalid serialized zkSNARK proof: {0}")]
+    InvalidProof(String),
+    #[error("Invalid serialized Merkle proof: {0}")]
+    InvalidMerkleProof(String),
+    #[error("Signal of {len} bytes exceeds the maximum length of {max} bytes")]
+    SignalTooLong { len: usize, max: usize },
+    #[error("Unknown serialized input kind {0}")]
+    UnknownInputKind(u8),
+    #[error("Unsupported serialization format version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Serialized field element is not smaller than the field modulus")]
+    NonCanonicalFieldElement,
+}
+
 pub fn serialize_proof_values(rln_proof_values: &RLNProofValues) -> Vec<u8> {
     let mut serialized: Vec<u8> = Vec::new();
 
@@ -312,7 +835,7 @@ pub fn serialize_proof_values(rln_proof_values: &RLNProofValues) -> Vec<u8> {
     serialized
 }
 
-// Note: don't forget to skip the 128 bytes ZK proof, if serialized contains it.
+// Note: don't forget to skip the SERIALIZED_PROOF_LEN bytes ZK proof, if serialized contains it.
 // This proc deserialzies only proof _values_, i.e. circuit outputs, not the zk proof.
 pub fn deserialize_proof_values(serialized: &[u8]) -> (RLNProofValues, usize) {
     let mut all_read: usize = 0;
@@ -348,66 +871,752 @@ pub fn deserialize_proof_values(serialized: &[u8]) -> (RLNProofValues, usize) {
     )
 }
 
+// Serializes a zkSNARK proof in compressed form, i.e. in SERIALIZED_PROOF_LEN bytes
+pub fn serialize_proof(proof: &ArkProof<Curve>) -> Result<Vec<u8>, SerializationError> {
+    let mut serialized: Vec<u8> = Vec::with_capacity(SERIALIZED_PROOF_LEN);
+    proof
+        .serialize(&mut serialized)
+        .map_err(|e| SerializationError::InvalidProof(e.to_string()))?;
+    Ok(serialized)
+}
+
+// Deserializes a zkSNARK proof serialized with serialize_proof.
+// Buffers with a length different than SERIALIZED_PROOF_LEN are rejected
+pub fn deserialize_proof(serialized: &[u8]) -> Result<ArkProof<Curve>, SerializationError> {
+    if serialized.len() != SERIALIZED_PROOF_LEN {
+        return Err(SerializationError::InvalidLength {
+            expected: SERIALIZED_PROOF_LEN,
+            actual: serialized.len(),
+        });
+    }
+
+    ArkProof::deserialize(serialized).map_err(|e| SerializationError::InvalidProof(e.to_string()))
+}
+
+// Deserializes a zkSNARK proof concatenated with its proof values, i.e.
+// [ proof<128> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> | rln_identifier<32> ]
+// Buffers with a different length are rejected
+pub fn deserialize_proof_and_values(
+    serialized: &[u8],
+) -> Result<(ArkProof<Curve>, RLNProofValues), SerializationError> {
+    let expected = SERIALIZED_PROOF_LEN + SERIALIZED_PROOF_VALUES_LEN;
+    if serialized.len() != expected {
+        return Err(SerializationError::InvalidLength {
+            expected,
+            actual: serialized.len(),
+        });
+    }
+
+    let proof = deserialize_proof(&serialized[..SERIALIZED_PROOF_LEN])?;
+    let (proof_values, _) = deserialize_proof_values(&serialized[SERIALIZED_PROOF_LEN..]);
+
+    Ok((proof, proof_values))
+}
+
+// The default maximum signal length in bytes accepted when serializing prove/verify inputs.
+// The circuit only takes the signal hash as input, so there is no reason for signals to be arbitrarily large
+pub const MAX_SIGNAL_LEN: usize = 1 << 20;
+
+// The width of the signal length prefix in serialized prove/verify inputs.
+// The default is U64, as expected by the RLN public API
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LengthPrefix {
+    U16,
+    U32,
+    #[default]
+    U64,
+}
+
+impl LengthPrefix {
+    // Returns the number of bytes used to serialize the length
+    pub fn byte_len(&self) -> usize {
+        match self {
+            LengthPrefix::U16 => 2,
+            LengthPrefix::U32 => 4,
+            LengthPrefix::U64 => 8,
+        }
+    }
+
+    // Returns the largest length representable with this prefix width
+    pub fn max_len(&self) -> usize {
+        match self {
+            LengthPrefix::U16 => u16::MAX as usize,
+            LengthPrefix::U32 => usize::try_from(u32::MAX).unwrap_or(usize::MAX),
+            LengthPrefix::U64 => usize::try_from(u64::MAX).unwrap_or(usize::MAX),
+        }
+    }
+
+    // Serializes len in little-endian, failing if it doesn't fit the prefix width
+    pub fn encode(&self, len: usize) -> Result<Vec<u8>, SerializationError> {
+        if len > self.max_len() {
+            return Err(SerializationError::SignalTooLong {
+                len,
+                max: self.max_len(),
+            });
+        }
+
+        Ok(u64::try_from(len).unwrap().to_le_bytes()[..self.byte_len()].to_vec())
+    }
+
+    // Deserializes a little-endian length from the first bytes of serialized
+    pub fn decode(&self, serialized: &[u8]) -> Result<usize, SerializationError> {
+        let byte_len = self.byte_len();
+        if serialized.len() < byte_len {
+            return Err(SerializationError::InvalidLength {
+                expected: byte_len,
+                actual: serialized.len(),
+            });
+        }
+
+        let mut bytes = [0u8; 8];
+        bytes[..byte_len].copy_from_slice(&serialized[..byte_len]);
+        usize::try_from(u64::from_le_bytes(bytes)).map_err(|_| SerializationError::InvalidLength {
+            expected: byte_len,
+            actual: serialized.len(),
+        })
+    }
+}
+
+// Serializes the prove input as [ identity_secret<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]
+// Returns an error if the signal is longer than MAX_SIGNAL_LEN
 pub fn prepare_prove_input(
     identity_secret: Fr,
     id_index: usize,
     epoch: Fr,
     signal: &[u8],
-) -> Vec<u8> {
-    let signal_len = u64::try_from(signal.len()).unwrap();
+) -> Result<Vec<u8>, SerializationError> {
+    prepare_prove_input_with_prefix(
+        identity_secret,
+        id_index,
+        epoch,
+        signal,
+        LengthPrefix::default(),
+        MAX_SIGNAL_LEN,
+    )
+}
+
+// Serializes the prove input using the provided signal length prefix width.
+// Returns an error if the signal is longer than max_signal_len or doesn't fit the prefix width
+pub fn prepare_prove_input_with_prefix(
+    identity_secret: Fr,
+    id_index: usize,
+    epoch: Fr,
+    signal: &[u8],
+    length_prefix: LengthPrefix,
+    max_signal_len: usize,
+) -> Result<Vec<u8>, SerializationError> {
+    check_signal_len(signal, max_signal_len)?;
+    let mut signal_len = length_prefix.encode(signal.len())?;
 
     let mut serialized: Vec<u8> = Vec::new();
 
     serialized.append(&mut fr_to_bytes_le(&identity_secret));
-    serialized.append(&mut id_index.to_le_bytes().to_vec());
+    // id_index is serialized as a u64 so that the serialization doesn't depend on the platform pointer width
+    serialized.append(&mut u64::try_from(id_index).unwrap().to_le_bytes().to_vec());
     serialized.append(&mut fr_to_bytes_le(&epoch));
-    serialized.append(&mut signal_len.to_le_bytes().to_vec());
+    serialized.append(&mut signal_len);
     serialized.append(&mut signal.to_vec());
 
-    return serialized;
+    Ok(serialized)
+}
+
+// Serializes the verify input as [ proof_data<var> | signal_len<8> | signal<var> ]
+// Returns an error if the signal is longer than MAX_SIGNAL_LEN
+pub fn prepare_verify_input(
+    proof_data: Vec<u8>,
+    signal: &[u8],
+) -> Result<Vec<u8>, SerializationError> {
+    prepare_verify_input_with_prefix(proof_data, signal, LengthPrefix::default(), MAX_SIGNAL_LEN)
 }
 
-pub fn prepare_verify_input(proof_data: Vec<u8>, signal: &[u8]) -> Vec<u8> {
-    let signal_len = u64::try_from(signal.len()).unwrap();
+// Serializes the verify input using the provided signal length prefix width.
+// Returns an error if the signal is longer than max_signal_len or doesn't fit the prefix width
+pub fn prepare_verify_input_with_prefix(
+    proof_data: Vec<u8>,
+    signal: &[u8],
+    length_prefix: LengthPrefix,
+    max_signal_len: usize,
+) -> Result<Vec<u8>, SerializationError> {
+    check_signal_len(signal, max_signal_len)?;
+    let mut signal_len = length_prefix.encode(signal.len())?;
 
-    let mut serialized: Vec<u8> = Vec::new();
+    let mut serialized: Vec<u8> = proof_data;
 
-    serialized.append(&mut proof_data.clone());
-    serialized.append(&mut signal_len.to_le_bytes().to_vec());
+    serialized.append(&mut signal_len);
     serialized.append(&mut signal.to_vec());
 
-    return serialized;
+    Ok(serialized)
+}
+
+// Checks the signal length before anything is serialized
+fn check_signal_len(signal: &[u8], max_signal_len: usize) -> Result<(), SerializationError> {
+    if signal.len() > max_signal_len {
+        return Err(SerializationError::SignalTooLong {
+            len: signal.len(),
+            max: max_signal_len,
+        });
+    }
+    Ok(())
 }
 
 ///////////////////////////////////////////////////////
-// Merkle tree utility functions
+// Proof bundle
 ///////////////////////////////////////////////////////
 
-pub fn compute_tree_root(
-    leaf: &Fr,
-    path_elements: &[Fr],
-    identity_path_index: &[u8],
-    hash_leaf: bool,
-) -> Fr {
-    let mut root = *leaf;
-    if hash_leaf {
-        root = poseidon_hash(&[root]);
+// A rate-limited message with its proof, i.e. a zkSNARK proof, its proof values and the signal it was generated for
+#[derive(Debug, Clone, PartialEq)]
+pub struct RLNProofBundle {
+    pub proof: ArkProof<Curve>,
+    pub values: RLNProofValues,
+    pub signal: Vec<u8>,
+}
+
+impl RLNProofBundle {
+    // Serializes the bundle as [ proof<128> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> | rln_identifier<32> | signal_len<8> | signal<var> ],
+    // i.e. the verify input of the RLN public API.
+    // Returns an error if the signal is longer than MAX_SIGNAL_LEN
+    pub fn serialize(&self) -> Result<Vec<u8>, SerializationError> {
+        let mut proof_data = serialize_proof(&self.proof)?;
+        proof_data.append(&mut serialize_proof_values(&self.values));
+
+        prepare_verify_input(proof_data, &self.signal)
     }
 
-    for i in 0..identity_path_index.len() {
-        if identity_path_index[i] == 0 {
-            root = poseidon_hash(&[root, path_elements[i]]);
-        } else {
-            root = poseidon_hash(&[path_elements[i], root]);
+    // Deserializes a bundle serialized with serialize.
+    // Returns an error if the buffer length doesn't match the signal length or the proof is invalid
+    pub fn deserialize(serialized: &[u8]) -> Result<Self, SerializationError> {
+        let proof_len = SERIALIZED_PROOF_LEN + SERIALIZED_PROOF_VALUES_LEN;
+        let length_prefix = LengthPrefix::default();
+        let header_len = proof_len + length_prefix.byte_len();
+        if serialized.len() < header_len {
+            return Err(SerializationError::InvalidLength {
+                expected: header_len,
+                actual: serialized.len(),
+            });
         }
-    }
 
-    root
-}
+        let (proof, values) = deserialize_proof_and_values(&serialized[..proof_len])?;
 
+        let signal_len = length_prefix.decode(&serialized[proof_len..])?;
+        if signal_len > MAX_SIGNAL_LEN {
+            return Err(SerializationError::SignalTooLong {
+                len: signal_len,
+                max: MAX_SIGNAL_LEN,
+            });
+        }
+        if serialized.len() != header_len + signal_len {
+            return Err(SerializationError::InvalidLength {
+                expected: header_len + signal_len,
+                actual: serialized.len(),
+            });
+        }
+
+        Ok(RLNProofBundle {
+            proof,
+            values,
+            signal: serialized[header_len..].to_vec(),
+        })
+    }
+
+    // Verifies the proof, checking that it was generated for the bundled signal (see verify_proof_for_signal)
+    pub fn verify(&self, verifying_key: &VerifyingKey<Curve>) -> Result<bool, ProofError> {
+        verify_proof_for_signal(verifying_key, &self.proof, &self.values, &self.signal)
+    }
+}
+
+///////////////////////////////////////////////////////
+// Merkle tree utility functions
 ///////////////////////////////////////////////////////
-// Protocol utility functions
-///////////////////////////////////////////////////////
+
+// Hashes a leaf secret as done in the circuit, i.e. the id_commitment corresponding to secret
+pub fn hash_leaf(secret: &Fr) -> Fr {
+    poseidon_hash(&[*secret])
+}
+
+// Hashes two sibling nodes into their parent node, as done at each level of the circuit Merkle tree
+pub fn merkle_parent(left: &Fr, right: &Fr) -> Fr {
+    poseidon_hash(&[*left, *right])
+}
+
+// Whether a leaf passed to compute_tree_root_with_mode is already the tree leaf or has to be hashed into it first.
+// This depends on the circuit variant: the RLN circuit hashes the identity secret into the leaf, i.e. the id_commitment,
+// so computing the root from an identity secret requires HashLeaf while computing it from an id_commitment requires Prehashed.
+// Using the wrong mode silently yields a root that doesn't match the tree one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafHashMode {
+    // The leaf is inserted in the tree as is
+    Prehashed,
+    // The leaf is hashed with hash_leaf before being inserted in the tree
+    HashLeaf,
+}
+
+impl From<bool> for LeafHashMode {
+    fn from(should_hash_leaf: bool) -> Self {
+        if should_hash_leaf {
+            LeafHashMode::HashLeaf
+        } else {
+            LeafHashMode::Prehashed
+        }
+    }
+}
+
+// Computes the root of the Merkle tree containing leaf at the position encoded by identity_path_index,
+// where should_hash_leaf is equivalent to LeafHashMode::HashLeaf (see compute_tree_root_with_mode).
+// Returns an error if path_elements and identity_path_index have different lengths
+pub fn compute_tree_root(
+    leaf: &Fr,
+    path_elements: &[Fr],
+    identity_path_index: &[u8],
+    should_hash_leaf: bool,
+) -> Result<Fr, String> {
+    compute_tree_root_with_mode(
+        leaf,
+        path_elements,
+        identity_path_index,
+        LeafHashMode::from(should_hash_leaf),
+    )
+}
+
+// Computes the root of the Merkle tree containing leaf at the position encoded by identity_path_index,
+// hashing leaf first according to leaf_hash_mode.
+// Returns an error if path_elements and identity_path_index have different lengths
+pub fn compute_tree_root_with_mode(
+    leaf: &Fr,
+    path_elements: &[Fr],
+    identity_path_index: &[u8],
+    leaf_hash_mode: LeafHashMode,
+) -> Result<Fr, String> {
+    if path_elements.len() != identity_path_index.len() {
+        return Err(format!(
+            "Inconsistent Merkle proof: path_elements has length {}, identity_path_index has length {}",
+            path_elements.len(),
+            identity_path_index.len()
+        ));
+    }
+
+    compute_tree_root_with_arity(
+        leaf,
+        path_elements,
+        identity_path_index,
+        2,
+        leaf_hash_mode == LeafHashMode::HashLeaf,
+    )
+}
+
+// Computes the root of each (leaf, path_elements, identity_path_index) Merkle proof with compute_tree_root.
+// Roots are returned in the order of inputs, each one being an error if its Merkle proof is inconsistent.
+// With the parallel feature enabled, roots are computed in parallel, e.g. to pre-check the roots of a burst of proofs before their zkSNARK verification
+pub fn compute_tree_roots_batch(
+    inputs: &[(Fr, Vec<Fr>, Vec<u8>)],
+    should_hash_leaf: bool,
+) -> Vec<Result<Fr, String>> {
+    let compute_root = |(leaf, path_elements, identity_path_index): &(Fr, Vec<Fr>, Vec<u8>)| {
+        compute_tree_root(leaf, path_elements, identity_path_index, should_hash_leaf)
+    };
+
+    cfg_if! {
+        if #[cfg(feature = "parallel")] {
+            inputs.par_iter().map(compute_root).collect()
+        } else {
+            inputs.iter().map(compute_root).collect()
+        }
+    }
+}
+
+// The largest tree arity supported by the Poseidon parameters in ROUND_PARAMS
+pub const MAX_TREE_ARITY: usize = ROUND_PARAMS.len();
+
+// Computes the root of an arity-ary Merkle tree containing leaf.
+// At each level, identity_path_index holds the position of the current node among its arity siblings (a base-arity digit)
+// and path_elements holds the other arity-1 siblings in order, so that path_elements has (arity-1) elements per level.
+// For arity 2 this is the binary tree used by the RLN circuit, where digits are the path bits.
+// Returns an error if the arity is not supported, the lengths are inconsistent or a digit is not smaller than arity
+pub fn compute_tree_root_with_arity(
+    leaf: &Fr,
+    path_elements: &[Fr],
+    identity_path_index: &[u8],
+    arity: usize,
+    should_hash_leaf: bool,
+) -> Result<Fr, String> {
+    if !(2..=MAX_TREE_ARITY).contains(&arity) {
+        return Err(format!(
+            "Unsupported tree arity {arity}: arity must be between 2 and {MAX_TREE_ARITY}"
+        ));
+    }
+
+    let siblings_per_level = arity - 1;
+    if path_elements.len() != identity_path_index.len() * siblings_per_level {
+        return Err(format!(
+            "Inconsistent Merkle proof: path_elements has length {}, expected {} for identity_path_index of length {} and arity {}",
+            path_elements.len(),
+            identity_path_index.len() * siblings_per_level,
+            identity_path_index.len(),
+            arity
+        ));
+    }
+
+    let mut root = *leaf;
+    if should_hash_leaf {
+        root = hash_leaf(&root);
+    }
+
+    for (level, siblings) in path_elements.chunks(siblings_per_level).enumerate() {
+        let position = identity_path_index[level] as usize;
+        if position >= arity {
+            return Err(format!(
+                "Invalid Merkle proof: identity_path_index has value {position} at level {level}, expected a value smaller than {arity}"
+            ));
+        }
+
+        let mut children = siblings.to_vec();
+        children.insert(position, root);
+        root = poseidon_hash(&children);
+    }
+
+    Ok(root)
+}
+
+// Serializes a Merkle proof as [ path_elements_len<8> | path_elements<var> | identity_path_index_len<8> | identity_path_index<var> ]
+pub fn serialize_merkle_proof(merkle_proof: &MerkleProof) -> Vec<u8> {
+    let mut serialized: Vec<u8> = Vec::new();
+
+    serialized.append(&mut vec_fr_to_bytes_le(&merkle_proof.get_path_elements()));
+    serialized.append(&mut vec_u8_to_bytes_le(&merkle_proof.get_path_index()));
+
+    serialized
+}
+
+// Deserializes a Merkle proof serialized with serialize_merkle_proof.
+// Returns an error if the input is truncated, has trailing bytes or encodes an inconsistent Merkle proof
+pub fn deserialize_merkle_proof(serialized: &[u8]) -> Result<MerkleProof, SerializationError> {
+    // We read a u64 length prefix at the beginning of input, checking that input contains len elements of el_size bytes after it
+    let read_len = |input: &[u8], el_size: usize| -> Result<usize, SerializationError> {
+        if input.len() < 8 {
+            return Err(SerializationError::InvalidLength {
+                expected: 8,
+                actual: input.len(),
+            });
+        }
+        let len = u64::from_le_bytes(input[0..8].try_into().unwrap()) as usize;
+        let expected = len
+            .checked_mul(el_size)
+            .and_then(|size| size.checked_add(8))
+            .ok_or_else(|| {
+                SerializationError::InvalidMerkleProof(format!("invalid length prefix {len}"))
+            })?;
+        if input.len() < expected {
+            return Err(SerializationError::InvalidLength {
+                expected,
+                actual: input.len(),
+            });
+        }
+        Ok(len)
+    };
+
+    let mut all_read: usize = 0;
+
+    read_len(&serialized[all_read..], fr_byte_size())?;
+    let (path_elements, read) = bytes_le_to_vec_fr(&serialized[all_read..]);
+    all_read += read;
+
+    read_len(&serialized[all_read..], 1)?;
+    let (identity_path_index, read) = bytes_le_to_vec_u8(&serialized[all_read..]);
+    all_read += read;
+
+    if all_read != serialized.len() {
+        return Err(SerializationError::InvalidLength {
+            expected: all_read,
+            actual: serialized.len(),
+        });
+    }
+
+    if path_elements.len() != identity_path_index.len() {
+        return Err(SerializationError::InvalidMerkleProof(format!(
+            "path_elements has length {}, identity_path_index has length {}",
+            path_elements.len(),
+            identity_path_index.len()
+        )));
+    }
+
+    if identity_path_index.iter().any(|index| *index > 1) {
+        return Err(SerializationError::InvalidMerkleProof(
+            "identity_path_index contains values other than 0 and 1".to_string(),
+        ));
+    }
+
+    cfg_if! {
+        if #[cfg(feature = "fullmerkletree")] {
+            use utils::merkle_tree::{FullMerkleBranch, FullMerkleProof};
+
+            let branches = path_elements
+                .into_iter()
+                .zip(identity_path_index)
+                .map(|(path_element, index)| {
+                    if index == 0 {
+                        FullMerkleBranch::Left(path_element)
+                    } else {
+                        FullMerkleBranch::Right(path_element)
+                    }
+                })
+                .collect();
+            Ok(FullMerkleProof(branches))
+        } else {
+            use utils::merkle_tree::OptimalMerkleProof;
+
+            Ok(OptimalMerkleProof(path_elements.into_iter().zip(identity_path_index).collect()))
+        }
+    }
+}
+
+// A bounds-checked reader over serialized data: reads past the end of the buffer return an error instead of panicking
+struct CheckedReader<'a> {
+    serialized: &'a [u8],
+    all_read: usize,
+}
+
+impl<'a> CheckedReader<'a> {
+    fn new(serialized: &'a [u8]) -> Self {
+        CheckedReader {
+            serialized,
+            all_read: 0,
+        }
+    }
+
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SerializationError> {
+        let end = self
+            .all_read
+            .checked_add(len)
+            .filter(|end| *end <= self.serialized.len())
+            .ok_or(SerializationError::InvalidLength {
+                expected: self.all_read.saturating_add(len),
+                actual: self.serialized.len(),
+            })?;
+        let bytes = &self.serialized[self.all_read..end];
+        self.all_read = end;
+        Ok(bytes)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, SerializationError> {
+        Ok(u64::from_le_bytes(self.read_bytes(8)?.try_into().unwrap()))
+    }
+
+    fn read_len(&mut self) -> Result<usize, SerializationError> {
+        let len = self.read_u64()?;
+        usize::try_from(len).map_err(|_| SerializationError::InvalidLength {
+            expected: usize::MAX,
+            actual: self.serialized.len(),
+        })
+    }
+
+    fn read_fr(&mut self) -> Result<Fr, SerializationError> {
+        Ok(bytes_le_to_fr(self.read_bytes(fr_byte_size())?).0)
+    }
+
+    fn read_vec_fr(&mut self) -> Result<Vec<Fr>, SerializationError> {
+        let len = self.read_len()?;
+        let byte_len =
+            len.checked_mul(fr_byte_size())
+                .ok_or(SerializationError::InvalidLength {
+                    expected: usize::MAX,
+                    actual: self.serialized.len(),
+                })?;
+        Ok(self
+            .read_bytes(byte_len)?
+            .chunks(fr_byte_size())
+            .map(|bytes| bytes_le_to_fr(bytes).0)
+            .collect())
+    }
+
+    fn read_vec_u8(&mut self) -> Result<Vec<u8>, SerializationError> {
+        let len = self.read_len()?;
+        Ok(self.read_bytes(len)?.to_vec())
+    }
+
+    // Reads a witness serialized with serialize_witness, checking its format version
+    fn read_witness(&mut self) -> Result<RLNWitnessInput, SerializationError> {
+        match self.read_bytes(1)?[0] {
+            WITNESS_FORMAT_VERSION => self.read_unversioned_witness(),
+            version => Err(SerializationError::UnsupportedVersion(version)),
+        }
+    }
+
+    // Reads a witness serialized with serialize_witness, without the leading version byte
+    fn read_unversioned_witness(&mut self) -> Result<RLNWitnessInput, SerializationError> {
+        // TODO: check rln_identifier against public::RLN_IDENTIFIER
+        Ok(RLNWitnessInput {
+            identity_secret: self.read_fr()?,
+            path_elements: self.read_vec_fr()?,
+            identity_path_index: self.read_vec_u8()?,
+            x: self.read_fr()?,
+            epoch: self.read_fr()?,
+            rln_identifier: self.read_fr()?,
+        })
+    }
+
+    fn read_rest(&mut self) -> &'a [u8] {
+        let rest = &self.serialized[self.all_read..];
+        self.all_read = self.serialized.len();
+        rest
+    }
+
+    // Checks that all the input was consumed
+    fn finish(self) -> Result<(), SerializationError> {
+        if self.all_read != self.serialized.len() {
+            return Err(SerializationError::InvalidLength {
+                expected: self.all_read,
+                actual: self.serialized.len(),
+            });
+        }
+        Ok(())
+    }
+}
+
+// An input parsed by try_parse_any. The kind of input is selected by the first byte of the serialized data
+#[derive(Debug, PartialEq)]
+pub enum ParsedInput {
+    // 0: a field element, see deserialize_field_element
+    FieldElement(Fr),
+    // 1: an identity pair, see deserialize_identity_pair
+    IdentityPair(Fr, Fr),
+    // 2: an identity tuple, see deserialize_identity_tuple
+    IdentityTuple(Fr, Fr, Fr, Fr),
+    // 3: a RLN witness, see deserialize_witness
+    Witness(RLNWitnessInput),
+    // 4: proof values, see deserialize_proof_values
+    ProofValues(RLNProofValues),
+    // 5: a zkSNARK proof and its proof values, see deserialize_proof_and_values
+    ProofAndValues(ArkProof<Curve>, RLNProofValues),
+    // 6: a Merkle proof, see deserialize_merkle_proof
+    MerkleProof {
+        path_elements: Vec<Fr>,
+        identity_path_index: Vec<u8>,
+    },
+    // 7: a prove input, see prepare_prove_input
+    ProveInput {
+        identity_secret: Fr,
+        id_index: u64,
+        epoch: Fr,
+        signal: Vec<u8>,
+    },
+}
+
+// Parses arbitrary bytes as [ kind<1> | input<var> ], where input is serialized according to kind (see ParsedInput).
+// Never panics: every read is bounds-checked, and inputs must be consumed entirely,
+// so this is suitable as a fuzz target for the deserialization code
+pub fn try_parse_any(serialized: &[u8]) -> Result<ParsedInput, SerializationError> {
+    let (kind, input) = serialized
+        .split_first()
+        .ok_or(SerializationError::InvalidLength {
+            expected: 1,
+            actual: 0,
+        })?;
+
+    let mut reader = CheckedReader::new(input);
+
+    let parsed = match kind {
+        0 => ParsedInput::FieldElement(reader.read_fr()?),
+        1 => ParsedInput::IdentityPair(reader.read_fr()?, reader.read_fr()?),
+        2 => ParsedInput::IdentityTuple(
+            reader.read_fr()?,
+            reader.read_fr()?,
+            reader.read_fr()?,
+            reader.read_fr()?,
+        ),
+        3 => ParsedInput::Witness(reader.read_witness()?),
+        4 => {
+            let (proof_values, _) =
+                deserialize_proof_values(reader.read_bytes(SERIALIZED_PROOF_VALUES_LEN)?);
+            ParsedInput::ProofValues(proof_values)
+        }
+        5 => {
+            let (proof, proof_values) = deserialize_proof_and_values(reader.read_rest())?;
+            ParsedInput::ProofAndValues(proof, proof_values)
+        }
+        6 => {
+            let merkle_proof = deserialize_merkle_proof(reader.read_rest())?;
+            ParsedInput::MerkleProof {
+                path_elements: merkle_proof.get_path_elements(),
+                identity_path_index: merkle_proof.get_path_index(),
+            }
+        }
+        7 => {
+            let identity_secret = reader.read_fr()?;
+            let id_index = reader.read_u64()?;
+            let epoch = reader.read_fr()?;
+            let signal_len = reader.read_len()?;
+            if signal_len > MAX_SIGNAL_LEN {
+                return Err(SerializationError::SignalTooLong {
+                    len: signal_len,
+                    max: MAX_SIGNAL_LEN,
+                });
+            }
+            ParsedInput::ProveInput {
+                identity_secret,
+                id_index,
+                epoch,
+                signal: reader.read_bytes(signal_len)?.to_vec(),
+            }
+        }
+        _ => return Err(SerializationError::UnknownInputKind(*kind)),
+    };
+
+    reader.finish()?;
+
+    Ok(parsed)
+}
+
+// Checks that the tree root matches the root recomputed from all its leaves.
+// Useful to detect a corrupted tree, e.g. after loading it from disk
+pub fn tree_integrity_check(tree: &PoseidonTree) -> Result<(), RLNError> {
+    if tree.recompute_root() != tree.root() {
+        return Err(RLNError::TreeCorrupted);
+    }
+    Ok(())
+}
+
+// Registers a new member in the tree: generates credentials with keygen and sets id_commitment at the next available leaf.
+// Returns (index, identity_secret_hash, id_commitment), where index is the leaf of id_commitment.
+// The identity secret hash is never inserted in the tree and has to be stored privately by the caller.
+// Returns an error if the tree is full
+pub fn register(tree: &mut PoseidonTree) -> io::Result<(usize, Fr, Fr)> {
+    let index = tree.leaves_set();
+    let (identity_secret_hash, id_commitment) = keygen();
+    tree.update_next(id_commitment)?;
+    Ok((index, identity_secret_hash, id_commitment))
+}
+
+// Computes the Merkle proof of value as if it were set at leaf index, without mutating the tree, together with the
+// anticipated root of the tree after the insertion. This lets a client prepare its proof before its registration is finalized.
+// Since the Merkle path of a leaf only depends on the other leaves, this is the current proof of index: the proof and the root
+// are valid only once the tree reaches that state, i.e. value is set at index and no other leaf is changed in between.
+// Returns an error if index is out of the tree bounds
+pub fn proof_for_insertion(
+    tree: &PoseidonTree,
+    index: usize,
+    value: Fr,
+) -> io::Result<(MerkleProof, Fr)> {
+    let merkle_proof = tree.proof(index)?;
+    let anticipated_root = merkle_proof.compute_root_from(&value);
+    Ok((merkle_proof, anticipated_root))
+}
+
+///////////////////////////////////////////////////////
+// Protocol utility functions
+///////////////////////////////////////////////////////
+
+// Computes the identity commitment corresponding to identity_secret_hash, i.e. id_commitment = PoseidonHash(identity_secret_hash)
+pub fn id_commitment_from_secret(identity_secret_hash: &Fr) -> Fr {
+    poseidon_hash(&[*identity_secret_hash])
+}
+
+// Computes the tuple (identity_secret_hash, id_commitment) of the identity (identity_trapdoor, identity_nullifier), where
+// identity_secret_hash = PoseidonHash(identity_trapdoor, identity_nullifier) and id_commitment = PoseidonHash(identity_secret_hash),
+// as done by extended_keygen. This allows to restore the tree leaf of an identity from a backed-up trapdoor and nullifier
+pub fn commitment_from_identity(identity_trapdoor: Fr, identity_nullifier: Fr) -> (Fr, Fr) {
+    let identity_secret_hash = poseidon_hash(&[identity_trapdoor, identity_nullifier]);
+    let id_commitment = id_commitment_from_secret(&identity_secret_hash);
+    (identity_secret_hash, id_commitment)
+}
 
 // Generates a tuple (identity_secret_hash, id_commitment) where
 // identity_secret_hash is random and id_commitment = PoseidonHash(identity_secret_hash)
@@ -415,7 +1624,7 @@ pub fn compute_tree_root(
 pub fn keygen() -> (Fr, Fr) {
     let mut rng = thread_rng();
     let identity_secret_hash = Fr::rand(&mut rng);
-    let id_commitment = poseidon_hash(&[identity_secret_hash]);
+    let id_commitment = id_commitment_from_secret(&identity_secret_hash);
     (identity_secret_hash, id_commitment)
 }
 
@@ -424,13 +1633,14 @@ pub fn keygen() -> (Fr, Fr) {
 // identity_secret_hash = PoseidonHash(identity_trapdoor, identity_nullifier),
 // id_commitment = PoseidonHash(identity_secret_hash),
 // RNG is instantiated using thread_rng()
-// Generated credentials are compatible with Semaphore credentials
+// Note that Semaphore hashes the nullifier first, so the secret hash and commitment differ from the Semaphore ones
+// for the same trapdoor and nullifier (see Identity::semaphore_secret_hash)
 pub fn extended_keygen() -> (Fr, Fr, Fr, Fr) {
     let mut rng = thread_rng();
     let identity_trapdoor = Fr::rand(&mut rng);
     let identity_nullifier = Fr::rand(&mut rng);
-    let identity_secret_hash = poseidon_hash(&[identity_trapdoor, identity_nullifier]);
-    let id_commitment = poseidon_hash(&[identity_secret_hash]);
+    let (identity_secret_hash, id_commitment) =
+        commitment_from_identity(identity_trapdoor, identity_nullifier);
     (
         identity_trapdoor,
         identity_nullifier,
@@ -439,42 +1649,38 @@ pub fn extended_keygen() -> (Fr, Fr, Fr, Fr) {
     )
 }
 
-// Generates a tuple (identity_secret_hash, id_commitment) where
-// identity_secret_hash is random and id_commitment = PoseidonHash(identity_secret_hash)
-// RNG is instantiated using 20 rounds of ChaCha seeded with the hash of the input
-pub fn seeded_keygen(signal: &[u8]) -> (Fr, Fr) {
-    // ChaCha20 requires a seed of exactly 32 bytes.
-    // We first hash the input seed signal to a 32 bytes array and pass this as seed to ChaCha20
+// Derives the 32 bytes seed used by seeded keygens, i.e. the Keccak256 hash of the input signal.
+// ChaCha20 requires a seed of exactly 32 bytes, so we hash the input seed signal to a 32 bytes array
+pub fn derive_seed(signal: &[u8]) -> [u8; 32] {
+    keccak256(signal)
+}
+
+// Derives the seed for the index-th identity of the family rooted in signal,
+// i.e. the Keccak256 hash of signal || index, with index serialized as 8 bytes little-endian
+pub fn derive_seed_with_index(signal: &[u8], index: u64) -> [u8; 32] {
     let mut seed = [0; 32];
     let mut hasher = Keccak::v256();
     hasher.update(signal);
+    hasher.update(&index.to_le_bytes());
     hasher.finalize(&mut seed);
+    seed
+}
 
-    let mut rng = ChaCha20Rng::from_seed(seed);
-    let identity_secret_hash = Fr::rand(&mut rng);
-    let id_commitment = poseidon_hash(&[identity_secret_hash]);
+// Generates a tuple (identity_secret_hash, id_commitment) sampling identity_secret_hash from rng.
+// Only cryptographically secure RNGs are accepted, since the identity secret must be unpredictable
+fn keygen_from_rng<R: Rng + CryptoRng>(rng: &mut R) -> (Fr, Fr) {
+    let identity_secret_hash = Fr::rand(rng);
+    let id_commitment = id_commitment_from_secret(&identity_secret_hash);
     (identity_secret_hash, id_commitment)
 }
 
-// Generates a tuple (identity_trapdoor, identity_nullifier, identity_secret_hash, id_commitment) where
-// identity_trapdoor and identity_nullifier are random,
-// identity_secret_hash = PoseidonHash(identity_trapdoor, identity_nullifier),
-// id_commitment = PoseidonHash(identity_secret_hash),
-// RNG is instantiated using 20 rounds of ChaCha seeded with the hash of the input
-// Generated credentials are compatible with Semaphore credentials
-pub fn extended_seeded_keygen(signal: &[u8]) -> (Fr, Fr, Fr, Fr) {
-    // ChaCha20 requires a seed of exactly 32 bytes.
-    // We first hash the input seed signal to a 32 bytes array and pass this as seed to ChaCha20
-    let mut seed = [0; 32];
-    let mut hasher = Keccak::v256();
-    hasher.update(signal);
-    hasher.finalize(&mut seed);
-
-    let mut rng = ChaCha20Rng::from_seed(seed);
-    let identity_trapdoor = Fr::rand(&mut rng);
-    let identity_nullifier = Fr::rand(&mut rng);
-    let identity_secret_hash = poseidon_hash(&[identity_trapdoor, identity_nullifier]);
-    let id_commitment = poseidon_hash(&[identity_secret_hash]);
+// Generates a tuple (identity_trapdoor, identity_nullifier, identity_secret_hash, id_commitment) sampling
+// identity_trapdoor and identity_nullifier from rng
+fn extended_keygen_from_rng<R: Rng + CryptoRng>(rng: &mut R) -> (Fr, Fr, Fr, Fr) {
+    let identity_trapdoor = Fr::rand(rng);
+    let identity_nullifier = Fr::rand(rng);
+    let (identity_secret_hash, id_commitment) =
+        commitment_from_identity(identity_trapdoor, identity_nullifier);
     (
         identity_trapdoor,
         identity_nullifier,
@@ -483,12 +1689,63 @@ pub fn extended_seeded_keygen(signal: &[u8]) -> (Fr, Fr, Fr, Fr) {
     )
 }
 
-// Hashes arbitrary signal to the underlying prime field
+// Generates a tuple (identity_secret_hash, id_commitment) where
+// identity_secret_hash is random and id_commitment = PoseidonHash(identity_secret_hash)
+// RNG is instantiated using 20 rounds of ChaCha seeded with derive_seed(signal)
+pub fn seeded_keygen(signal: &[u8]) -> (Fr, Fr) {
+    let mut rng = ChaCha20Rng::from_seed(derive_seed(signal));
+    keygen_from_rng(&mut rng)
+}
+
+// Generates the index-th tuple (identity_secret_hash, id_commitment) of the deterministic family rooted in signal.
+// RNG is instantiated using 20 rounds of ChaCha seeded with derive_seed_with_index(signal, index),
+// so that a single master seed can be used to derive multiple unrelated identities
+pub fn seeded_keygen_with_index(signal: &[u8], index: u64) -> (Fr, Fr) {
+    let mut rng = ChaCha20Rng::from_seed(derive_seed_with_index(signal, index));
+    keygen_from_rng(&mut rng)
+}
+
+// Generates a tuple (identity_trapdoor, identity_nullifier, identity_secret_hash, id_commitment) where
+// identity_trapdoor and identity_nullifier are random,
+// identity_secret_hash = PoseidonHash(identity_trapdoor, identity_nullifier),
+// id_commitment = PoseidonHash(identity_secret_hash),
+// RNG is instantiated using 20 rounds of ChaCha seeded with derive_seed(signal)
+// Note that Semaphore hashes the nullifier first, so the secret hash and commitment differ from the Semaphore ones
+// for the same trapdoor and nullifier (see Identity::semaphore_secret_hash)
+pub fn extended_seeded_keygen(signal: &[u8]) -> (Fr, Fr, Fr, Fr) {
+    let mut rng = ChaCha20Rng::from_seed(derive_seed(signal));
+    extended_keygen_from_rng(&mut rng)
+}
+
+// Computes the Keccak256 hash of input
+fn keccak256(input: &[u8]) -> [u8; 32] {
+    let mut hash = [0; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(input);
+    hasher.finalize(&mut hash);
+    hash
+}
+
+// Hashes arbitrary signal to the underlying prime field.
+// This is hash_to_field_with_domain with an empty domain
 pub fn hash_to_field(signal: &[u8]) -> Fr {
+    hash_to_field_with_domain(&[], signal)
+}
+
+// Hashes arbitrary signal to the underlying prime field, separating hashes computed for different domains.
+// For a non-empty domain, Keccak256 absorbs [ domain_len<8> | domain<var> | signal<var> ], while for an empty domain
+// it absorbs the signal only, so that hash_to_field outputs are unchanged.
+// Note that a signal hashed with an empty domain can still collide with a domain-separated one if it starts with a valid
+// length-prefixed domain: protocols needing full separation should use non-empty domains only
+pub fn hash_to_field_with_domain(domain: &[u8], signal: &[u8]) -> Fr {
     // We hash the input signal using Keccak256
     // (note that a bigger curve order might require a bigger hash blocksize)
     let mut hash = [0; 32];
     let mut hasher = Keccak::v256();
+    if !domain.is_empty() {
+        hasher.update(&(domain.len() as u64).to_le_bytes());
+        hasher.update(domain);
+    }
     hasher.update(signal);
     hasher.finalize(&mut hash);
 
@@ -497,46 +1754,924 @@ pub fn hash_to_field(signal: &[u8]) -> Fr {
     el
 }
 
+// The domain used by rln_identifier_from_app to hash application names
+pub const RLN_IDENTIFIER_DOMAIN: &[u8] = b"zerokit/rln/rln_identifier";
+
+// Derives an rln_identifier from a human-readable application name (e.g. "my-chat-app/v1"), hashing it to the field
+// in the RLN_IDENTIFIER_DOMAIN domain. Independent deployments using the same name agree on the identifier with no coordination.
+// Two different app names yield independent spam budgets: the rln_identifier is part of the external nullifier,
+// so messages sent in the same epoch to different applications don't count against each other.
+// Note that the default identifier hash_to_field(RLN_IDENTIFIER) is not domain-separated, hence it differs from rln_identifier_from_app(RLN_IDENTIFIER)
+pub fn rln_identifier_from_app(name: &[u8]) -> Fr {
+    hash_to_field_with_domain(RLN_IDENTIFIER_DOMAIN, name)
+}
+
+// Maps a signal to the circuit public input x, i.e. hash_to_field(signal).
+// This is the canonical binding between the message bytes and the proof: witnesses built with a
+// different mapping yield proofs that verify against a different message than the intended one.
+// Since the share y = a_0 + x * a_1 equals the identity secret a_0 when x = 0, signals hashing to 0 are remapped
+// with remap_zero_x, so that x is never 0
+pub fn signal_to_field(signal: &[u8]) -> Fr {
+    let x = hash_to_field(signal);
+    if x.is_zero() {
+        return remap_zero_x(signal);
+    }
+    x
+}
+
+// The domain used by signal_to_field_epoch_bound to hash signals together with their epoch
+pub const EPOCH_BOUND_SIGNAL_DOMAIN: &[u8] = b"zerokit/rln/epoch_bound_signal";
+
+// Maps a signal to the circuit public input x binding it to epoch, i.e. hashes [ epoch<32> | signal<var> ]
+// in the EPOCH_BOUND_SIGNAL_DOMAIN domain, so that the same signal maps to a different x in each epoch.
+// This is opt-in and distinct from signal_to_field: the circuit takes x as a public input and doesn't hash the signal,
+// so provers and verifiers must both compute x this way. In particular, verify_proof_for_signal expects x = signal_to_field(signal),
+// hence verifiers should check x = signal_to_field_epoch_bound(signal, proof_values.epoch) and then call verify_proof.
+// As for signal_to_field, x is never 0
+pub fn signal_to_field_epoch_bound(signal: &[u8], epoch: Fr) -> Fr {
+    let input = [fr_to_bytes_le(&epoch), signal.to_vec()].concat();
+    let x = hash_to_field_with_domain(EPOCH_BOUND_SIGNAL_DOMAIN, &input);
+    if x.is_zero() {
+        return remap_zero_x(&input);
+    }
+    x
+}
+
+// Rehashes signal with an increasing counter, i.e. computes hash_to_field(signal || counter) with counter
+// a 8 bytes little-endian integer starting from 1, until a non-zero field element is found
+pub(crate) fn remap_zero_x(signal: &[u8]) -> Fr {
+    remap_zero_x_with(SignalHashFunction::Keccak256, signal)
+}
+
+// Rehashes signal with an increasing counter as remap_zero_x does, using the selected hash function
+fn remap_zero_x_with(hash_function: SignalHashFunction, signal: &[u8]) -> Fr {
+    (1u64..)
+        .map(|counter| {
+            hash_to_field_with(hash_function, &[signal, &counter.to_le_bytes()].concat())
+        })
+        .find(|x| !x.is_zero())
+        .expect("a non-zero hash is found")
+}
+
+// The number of 31 bytes signal chunks absorbed by each Poseidon call in poseidon_hash_signal.
+// Together with the sponge state, this gives 8 inputs, the largest Poseidon width supported
+const POSEIDON_SIGNAL_RATE: usize = 7;
+
+// Hashes arbitrary signal to the underlying prime field using Poseidon as a sponge.
+// The signal is packed in 31 bytes little-endian chunks (hence smaller than the field modulus), which are absorbed
+// POSEIDON_SIGNAL_RATE at a time into a state initialized with the signal length.
+// Prefer it over hash_to_field when the signal hash has to be recomputed in a circuit, where Poseidon is much cheaper than Keccak
+pub fn poseidon_hash_signal(signal: &[u8]) -> Fr {
+    let chunks: Vec<Fr> = signal
+        .chunks(31)
+        .map(|chunk| Fr::from(BigUint::from_bytes_le(chunk)))
+        .collect();
+
+    // The length prevents collisions between signals differing only by trailing zero bytes
+    let mut state = Fr::from(signal.len() as u64);
+
+    if chunks.is_empty() {
+        return poseidon_hash(&[state]);
+    }
+
+    for block in chunks.chunks(POSEIDON_SIGNAL_RATE) {
+        let mut input = vec![state];
+        input.extend_from_slice(block);
+        state = poseidon_hash(&input);
+    }
+
+    state
+}
+
+// The hash function used to map signals to the field in hash_to_field_with and signal_to_field_with.
+// The two hash functions give different field elements for the same signal, so they are not interchangeable:
+// provers and verifiers (and the circuit, if it recomputes the signal hash) must all use the same one.
+// Keccak256 is the default, i.e. the one used by hash_to_field and signal_to_field
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignalHashFunction {
+    // Keccak256, see hash_to_field
+    #[default]
+    Keccak256,
+    // The native Poseidon hash over 31 bytes chunks, see poseidon_hash_signal
+    Poseidon,
+}
+
+// Hashes arbitrary signal to the underlying prime field with the selected hash function.
+// Poseidon-only deployments can use SignalHashFunction::Poseidon to avoid Keccak for signals
+pub fn hash_to_field_with(hash_function: SignalHashFunction, signal: &[u8]) -> Fr {
+    match hash_function {
+        SignalHashFunction::Keccak256 => hash_to_field(signal),
+        SignalHashFunction::Poseidon => poseidon_hash_signal(signal),
+    }
+}
+
+// Maps a signal to the circuit public input x with the selected hash function, as signal_to_field does for Keccak256.
+// Signals hashing to 0 are rehashed with an increasing counter as in remap_zero_x, so that x is never 0
+pub fn signal_to_field_with(hash_function: SignalHashFunction, signal: &[u8]) -> Fr {
+    let x = hash_to_field_with(hash_function, signal);
+    if x.is_zero() {
+        return remap_zero_x_with(hash_function, signal);
+    }
+    x
+}
+
+// Memoizes signal_to_field for recently hashed signals, evicting the least recently used one when full.
+// Useful to verifiers checking many proofs for the same (e.g. pinned) signal, see verify_proof_for_signal_with_hasher.
+// The hasher is not internally synchronized: callers sharing it across threads should wrap it in a Mutex
+#[derive(Debug, Clone)]
+pub struct SignalHasher {
+    capacity: usize,
+    // The cached x for each signal, together with the tick of its last use
+    entries: HashMap<Vec<u8>, (Fr, u64)>,
+    // The cached signals ordered by the tick of their last use
+    recency: BTreeMap<u64, Vec<u8>>,
+    tick: u64,
+}
+
+impl SignalHasher {
+    // Creates a hasher caching at most capacity signals. A zero capacity disables caching
+    pub fn new(capacity: usize) -> Self {
+        SignalHasher {
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+        }
+    }
+
+    // Returns signal_to_field(signal), computing it only if signal is not cached
+    pub fn hash(&mut self, signal: &[u8]) -> Fr {
+        self.tick += 1;
+
+        if let Some((x, last_used)) = self.entries.get_mut(signal) {
+            let signal = self
+                .recency
+                .remove(last_used)
+                .expect("cached signals are tracked");
+            *last_used = self.tick;
+            let x = *x;
+            self.recency.insert(self.tick, signal);
+            return x;
+        }
+
+        let x = signal_to_field(signal);
+        if self.capacity == 0 {
+            return x;
+        }
+
+        if self.entries.len() == self.capacity {
+            let oldest = *self
+                .recency
+                .keys()
+                .next()
+                .expect("a full cache is not empty");
+            let evicted = self.recency.remove(&oldest).expect("the key exists");
+            self.entries.remove(&evicted);
+        }
+        self.entries.insert(signal.to_vec(), (x, self.tick));
+        self.recency.insert(self.tick, signal.to_vec());
+
+        x
+    }
+
+    // Returns true if the hash of signal is cached
+    pub fn contains(&self, signal: &[u8]) -> bool {
+        self.entries.contains_key(signal)
+    }
+
+    // Returns the maximum number of cached signals
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Returns the number of currently cached signals
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+// Derives a stable 16 bytes seed from an identity commitment, suitable to generate identicons/avatars for members.
+// The seed is the first half of the Keccak256 hash of the little-endian serialized commitment
+pub fn commitment_visual_seed(commitment: Fr) -> [u8; 16] {
+    let mut hash = [0; 32];
+    let mut hasher = Keccak::v256();
+    hasher.update(&fr_to_bytes_le(&commitment));
+    hasher.finalize(&mut hash);
+
+    let mut seed = [0; 16];
+    seed.copy_from_slice(&hash[..16]);
+    seed
+}
+
+// Computes the external nullifier of epoch for the application rln_identifier, i.e. PoseidonHash(epoch, rln_identifier),
+// as done in the circuit
+pub fn compute_external_nullifier(epoch: Fr, rln_identifier: Fr) -> Fr {
+    poseidon_hash(&[epoch, rln_identifier])
+}
+
+// Computes the external nullifier of each epoch for the application rln_identifier, e.g. to let clients cache
+// the external nullifiers of the upcoming epochs
+pub fn external_nullifiers_for_epochs(rln_identifier: Fr, epochs: &[Fr]) -> Vec<Fr> {
+    epochs
+        .iter()
+        .map(|epoch| compute_external_nullifier(*epoch, rln_identifier))
+        .collect()
+}
+
+// Evaluates at x the secret sharing line of identity_secret for external_nullifier, returning the share (x, y) where
+// y = a_0 + x * a_1, with a_0 = identity_secret and a_1 = PoseidonHash(identity_secret, external_nullifier).
+// This is the share computed in proof values, and two shares at different x recover identity_secret with compute_id_secret
+pub fn eval_share(identity_secret: Fr, external_nullifier: Fr, x: Fr) -> (Fr, Fr) {
+    let a_1 = poseidon_hash(&[identity_secret, external_nullifier]);
+    (x, identity_secret + x * a_1)
+}
+
+// Computes the nullifier that identity_secret yields for epoch and rln_identifier, i.e.
+// external_nullifier = PoseidonHash(epoch, rln_identifier), a_1 = PoseidonHash(identity_secret, external_nullifier)
+// and nullifier = PoseidonHash(a_1), as computed in proof values.
+// This allows auditors to recompute the whole derivation from a recovered secret and the public epoch and rln_identifier
+pub fn nullifier_from_secret(identity_secret: Fr, epoch: Fr, rln_identifier: Fr) -> Fr {
+    let external_nullifier = compute_external_nullifier(epoch, rln_identifier);
+    let a_1 = poseidon_hash(&[identity_secret, external_nullifier]);
+    poseidon_hash(&[a_1])
+}
+
 pub fn compute_id_secret(
     share1: (Fr, Fr),
     share2: (Fr, Fr),
     external_nullifier: Fr,
-) -> Result<Fr, String> {
+) -> Result<Fr, RecoveryError> {
     // Assuming a0 is the identity secret and a1 = poseidonHash([a0, external_nullifier]),
     // a (x,y) share satisfies the following relation
     // y = a_0 + x * a_1
     let (x1, y1) = share1;
     let (x2, y2) = share2;
 
-    // If the two input shares were computed for the same external_nullifier and identity secret, we can recover the latter
-    // y1 = a_0 + x1 * a_1
-    // y2 = a_0 + x2 * a_1
-    let a_1 = (y1 - y2) / (x1 - x2);
-    let a_0 = y1 - x1 * a_1;
+    // Shares at the same x cannot be interpolated (and dividing by x1 - x2 = 0 would panic).
+    // This happens when the same proof is replayed, rather than when a member double-signals
+    if x1 == x2 {
+        return Err(RecoveryError::IdenticalShares);
+    }
+
+    // If the two input shares were computed for the same external_nullifier and identity secret, we can recover the latter
+    // y1 = a_0 + x1 * a_1
+    // y2 = a_0 + x2 * a_1
+    let a_1 = (y1 - y2) / (x1 - x2);
+    let a_0 = y1 - x1 * a_1;
+
+    // If shares come from the same polynomial, a0 is correctly recovered and a1 = poseidonHash([a0, external_nullifier])
+    let computed_a_1 = poseidon_hash(&[a_0, external_nullifier]);
+
+    if a_1 == computed_a_1 {
+        // We successfully recovered the identity secret
+        return Ok(a_0);
+    } else {
+        return Err(RecoveryError::InvalidShares(
+            "Cannot recover identity_secret_hash from provided shares".into(),
+        ));
+    }
+}
+
+// Recovers the identity secret from several shares computed for external_nullifier, some of which may be malformed.
+// Pairs of shares at different x are tried in order with compute_id_secret, which checks the recovered secret
+// against a_1 = PoseidonHash(a_0, external_nullifier), and the first consistent secret is returned.
+// A single malformed share thus can't poison recovery as long as two valid shares are provided.
+// Returns an error if no pair of shares is consistent
+pub fn recover_from_shares(
+    shares: &[(Fr, Fr)],
+    external_nullifier: Fr,
+) -> Result<Fr, RecoveryError> {
+    for (i, share1) in shares.iter().enumerate() {
+        for share2 in &shares[i + 1..] {
+            if share1.0 == share2.0 {
+                continue;
+            }
+            if let Ok(identity_secret) = compute_id_secret(*share1, *share2, external_nullifier) {
+                return Ok(identity_secret);
+            }
+        }
+    }
+
+    Err(RecoveryError::InvalidShares(format!(
+        "no consistent pair among {} shares",
+        shares.len()
+    )))
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum RecoveryError {
+    #[error("Proof values are computed for different epochs")]
+    EpochMismatch,
+    #[error("Proof values are computed for different RLN identifiers")]
+    RlnIdentifierMismatch,
+    #[error("Proof values have different nullifiers")]
+    NullifierMismatch,
+    #[error("Cannot recover identity secret: {0}")]
+    InvalidShares(String),
+    #[error("Shares have the same x coordinate and cannot be interpolated")]
+    IdenticalShares,
+}
+
+// Checks whether two proof values constitute a slashable double signal, i.e. share the same epoch, rln_identifier and nullifier
+// but have different shares x: in such case, recover_identity_from_proof_values recovers the identity secret of their sender.
+// Proof values with the same x are either the same message sent twice or unusable shares, and are not double signals
+pub fn is_double_signal(proof_values_1: &RLNProofValues, proof_values_2: &RLNProofValues) -> bool {
+    proof_values_1.epoch == proof_values_2.epoch
+        && proof_values_1.rln_identifier == proof_values_2.rln_identifier
+        && proof_values_1.nullifier == proof_values_2.nullifier
+        && proof_values_1.x != proof_values_2.x
+}
+
+// Recovers the identity secret from the proof values of two proofs generated by the same identity for the same epoch and rln_identifier.
+// Callers can check beforehand with is_double_signal that recovery applies to the proof values
+pub fn recover_identity_from_proof_values(
+    proof_values_1: &RLNProofValues,
+    proof_values_2: &RLNProofValues,
+) -> Result<Fr, RecoveryError> {
+    if proof_values_1.epoch != proof_values_2.epoch {
+        return Err(RecoveryError::EpochMismatch);
+    }
+    if proof_values_1.rln_identifier != proof_values_2.rln_identifier {
+        return Err(RecoveryError::RlnIdentifierMismatch);
+    }
+    if proof_values_1.nullifier != proof_values_2.nullifier {
+        return Err(RecoveryError::NullifierMismatch);
+    }
+
+    let external_nullifier =
+        compute_external_nullifier(proof_values_1.epoch, proof_values_1.rln_identifier);
+
+    let share1 = (proof_values_1.x, proof_values_1.y);
+    let share2 = (proof_values_2.x, proof_values_2.y);
+
+    compute_id_secret(share1, share2, external_nullifier)
+}
+
+///////////////////////////////////////////////////////
+// Slashing
+///////////////////////////////////////////////////////
+
+// A claim that the member with commitment id_commitment double-signaled and has identity secret identity_secret
+#[derive(Debug, Clone, PartialEq)]
+pub struct SlashingReport {
+    pub identity_secret: Fr,
+    pub id_commitment: Fr,
+}
+
+// Independently checks a slashing claim against the two proofs it is based on, serialized as output by RLN::generate_rln_proof, i.e.
+// [ proof<128> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> | rln_identifier<32> ]
+// Returns true only if each proof was generated for the provided signal, the identity secret recovered from the two proofs
+// matches the claimed one and the claimed commitment is its hash. zkSNARK proofs are not verified here: use verify_proof for that
+// Returns an error if a serialized proof is too short
+pub fn verify_slashing_claim(
+    claim: &SlashingReport,
+    proof1: &[u8],
+    signal1: &[u8],
+    proof2: &[u8],
+    signal2: &[u8],
+) -> Result<bool, RLNError> {
+    let proof_values_1 = proof_values_from_serialized_proof(proof1)?;
+    let proof_values_2 = proof_values_from_serialized_proof(proof2)?;
+
+    // Each share must correspond to the signal it is claimed for
+    if proof_values_1.x != signal_to_field(signal1) || proof_values_2.x != signal_to_field(signal2)
+    {
+        return Ok(false);
+    }
+
+    let identity_secret = match recover_identity_from_proof_values(&proof_values_1, &proof_values_2)
+    {
+        Ok(identity_secret) => identity_secret,
+        Err(_) => return Ok(false),
+    };
+
+    Ok(identity_secret == claim.identity_secret
+        && poseidon_hash(&[identity_secret]) == claim.id_commitment)
+}
+
+// Checks that claimed_nullifier is the nullifier that recovered_secret yields for external_nullifier,
+// i.e. that claimed_nullifier = PoseidonHash(PoseidonHash(recovered_secret, external_nullifier)).
+// This allows slashers to confirm a recovered secret corresponds to the reported nullifier before banning a member
+pub fn verify_nullifier(
+    recovered_secret: &Fr,
+    external_nullifier: &Fr,
+    claimed_nullifier: &Fr,
+) -> bool {
+    let a_1 = poseidon_hash(&[*recovered_secret, *external_nullifier]);
+    poseidon_hash(&[a_1]) == *claimed_nullifier
+}
+
+// A log of the shares observed for each (epoch, nullifier) pair, used by relays to detect double signaling.
+// Only the first share (x, y) of each pair is stored: when a second distinct share arrives for the same pair,
+// the identity secret of the spammer is recovered with compute_id_secret.
+// Proofs are expected to be verified before being observed, otherwise forged shares can fill the log.
+// Epochs are numbered in the order they are first observed, so that the log can be pruned whatever the epoch encoding
+// (e.g. hashed epochs), see prune
+#[derive(Debug, Default, Clone)]
+pub struct NullifierLog {
+    // The shares observed for each epoch, together with the epoch observation number
+    shares: HashMap<Fr, (u64, HashMap<Fr, (Fr, Fr)>)>,
+    // The observation number of the next newly observed epoch
+    next_epoch_number: u64,
+}
+
+impl NullifierLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Records the share of proof_values, returning the identity secret of the sender if a different share
+    // was previously observed for the same epoch and nullifier, i.e. if the sender double signaled.
+    // Observing the same share again (e.g. a replayed message) returns None
+    pub fn observe(&mut self, proof_values: &RLNProofValues) -> Option<Fr> {
+        let share = (proof_values.x, proof_values.y);
+        let next_epoch_number = &mut self.next_epoch_number;
+        let (_, nullifiers) = self.shares.entry(proof_values.epoch).or_insert_with(|| {
+            *next_epoch_number += 1;
+            (*next_epoch_number - 1, HashMap::new())
+        });
+        let first_share = *nullifiers.entry(proof_values.nullifier).or_insert(share);
+
+        if first_share == share {
+            return None;
+        }
+
+        let external_nullifier =
+            compute_external_nullifier(proof_values.epoch, proof_values.rln_identifier);
+        let identity_secret = compute_id_secret(first_share, share, external_nullifier).ok()?;
+
+        // We check that the recovered secret is the one of the nullifier owner
+        verify_nullifier(
+            &identity_secret,
+            &external_nullifier,
+            &proof_values.nullifier,
+        )
+        .then_some(identity_secret)
+    }
+
+    // Returns true if a share was observed for the epoch and nullifier
+    pub fn contains(&self, epoch: &Fr, nullifier: &Fr) -> bool {
+        self.shares
+            .get(epoch)
+            .is_some_and(|(_, nullifiers)| nullifiers.contains_key(nullifier))
+    }
+
+    // Returns the number of (epoch, nullifier) pairs observed
+    pub fn len(&self) -> usize {
+        self.shares
+            .values()
+            .map(|(_, nullifiers)| nullifiers.len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shares.is_empty()
+    }
+
+    // Removes all shares observed for epoch
+    pub fn remove_epoch(&mut self, epoch: &Fr) {
+        self.shares.remove(epoch);
+    }
+
+    // Removes all shares observed for epochs first observed more than tolerance epochs before current_epoch,
+    // e.g. tolerance 1 keeps current_epoch and the epoch observed just before it. Epochs are compared by observation order
+    // rather than by value, so that hashed epochs can be pruned too. If current_epoch was never observed,
+    // it is taken as the next observed epoch, i.e. only the last tolerance observed epochs are kept
+    pub fn prune(&mut self, current_epoch: Fr, tolerance: u64) {
+        let current_epoch_number = self
+            .shares
+            .get(&current_epoch)
+            .map_or(self.next_epoch_number, |(epoch_number, _)| *epoch_number);
+        self.shares.retain(|_, (epoch_number, _)| {
+            *epoch_number >= current_epoch_number.saturating_sub(tolerance)
+        });
+    }
+}
+
+// Checks that no nullifier appears under different epochs in the proof values, e.g. those observed by a relay.
+// Since nullifiers are bound to the epoch through the external nullifier, the same nullifier in two epochs
+// denotes a malformed proof or a circuit bug. Returns an error describing the first such nullifier found
+pub fn nullifier_epoch_consistent(proof_values: &[RLNProofValues]) -> Result<(), String> {
+    let mut epochs: HashMap<Fr, Fr> = HashMap::new();
+
+    for values in proof_values {
+        let epoch = epochs.entry(values.nullifier).or_insert(values.epoch);
+        if *epoch != values.epoch {
+            return Err(format!(
+                "nullifier 0x{:064x} appears in epochs 0x{:064x} and 0x{:064x}",
+                to_bigint(&values.nullifier),
+                to_bigint(epoch),
+                to_bigint(&values.epoch)
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+// Deserializes the proof values from a serialized proof, skipping the zk proof
+fn proof_values_from_serialized_proof(serialized: &[u8]) -> Result<RLNProofValues, RLNError> {
+    let expected = SERIALIZED_PROOF_LEN + SERIALIZED_PROOF_VALUES_LEN;
+    if serialized.len() < expected {
+        return Err(RLNError::ProofDataTooShort {
+            expected,
+            actual: serialized.len(),
+        });
+    }
+
+    let (proof_values, _) = deserialize_proof_values(&serialized[SERIALIZED_PROOF_LEN..]);
+    Ok(proof_values)
+}
+
+///////////////////////////////////////////////////////
+// Secret provider
+///////////////////////////////////////////////////////
+
+// Performs the operations depending on the identity secret, so that the secret can be kept in external storage
+// (e.g. a secure element) and never handed to the caller: only the values derived from it are returned.
+// Since the RLN circuit takes the identity secret as private input, proofs are generated by the provider too:
+// a hardware provider runs the witness calculation and the Groth16 prover where the secret is stored.
+// SoftwareSecretProvider is the default implementation, holding the secret in memory
+pub trait SecretProvider {
+    // Returns the identity commitment, i.e. poseidon_hash(identity_secret)
+    fn id_commitment(&self) -> Fr;
+
+    // Returns a_1 = poseidon_hash(identity_secret, external_nullifier)
+    fn a_1(&self, external_nullifier: Fr) -> Fr;
+
+    // Returns the Shamir share y = identity_secret + x * a_1
+    fn share(&self, x: Fr, a_1: Fr) -> Fr;
+
+    // Generates a RLN proof for the identity held by the provider, with the member Merkle proof and the public inputs
+    // x, epoch and rln_identifier, using witness_generator to compute the circuit witness
+    fn generate_proof(
+        &self,
+        witness_generator: &mut dyn WitnessGenerator,
+        proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+        merkle_proof: &MerkleProof,
+        x: Fr,
+        epoch: Fr,
+        rln_identifier: Fr,
+    ) -> Result<ArkProof<Curve>, ProofError>;
+}
+
+// A SecretProvider holding the identity secret in memory
+pub struct SoftwareSecretProvider {
+    identity_secret: Fr,
+}
+
+impl SoftwareSecretProvider {
+    pub fn new(identity_secret: Fr) -> Self {
+        SoftwareSecretProvider { identity_secret }
+    }
+}
+
+impl SecretProvider for SoftwareSecretProvider {
+    fn id_commitment(&self) -> Fr {
+        poseidon_hash(&[self.identity_secret])
+    }
+
+    fn a_1(&self, external_nullifier: Fr) -> Fr {
+        poseidon_hash(&[self.identity_secret, external_nullifier])
+    }
+
+    fn share(&self, x: Fr, a_1: Fr) -> Fr {
+        self.identity_secret + x * a_1
+    }
+
+    fn generate_proof(
+        &self,
+        witness_generator: &mut dyn WitnessGenerator,
+        proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+        merkle_proof: &MerkleProof,
+        x: Fr,
+        epoch: Fr,
+        rln_identifier: Fr,
+    ) -> Result<ArkProof<Curve>, ProofError> {
+        let rln_witness = rln_witness_from_values_with_identifier(
+            self.identity_secret,
+            merkle_proof,
+            x,
+            epoch,
+            rln_identifier,
+        );
+        generate_proof(witness_generator, proving_key, &rln_witness)
+    }
+}
+
+// Computes the proof values using only the secret-dependent operations exposed by a SecretProvider,
+// i.e. the identity commitment, a_1 and the share y.
+// Returns an error if x is 0, since the share y would then be the identity secret
+pub fn proof_values_from_secret_provider<S: SecretProvider + ?Sized>(
+    secret_provider: &S,
+    merkle_proof: &MerkleProof,
+    x: Fr,
+    epoch: Fr,
+    rln_identifier: Fr,
+) -> Result<RLNProofValues, WitnessValidationError> {
+    if x.is_zero() {
+        return Err(WitnessValidationError::ZeroX);
+    }
+
+    // y share
+    let external_nullifier = compute_external_nullifier(epoch, rln_identifier);
+    let a_1 = secret_provider.a_1(external_nullifier);
+    let y = secret_provider.share(x, a_1);
+
+    // Nullifier
+    let nullifier = poseidon_hash(&[a_1]);
+
+    // Merkle tree root computations (the leaf is the already hashed identity commitment)
+    let root = compute_tree_root(
+        &secret_provider.id_commitment(),
+        &merkle_proof.get_path_elements(),
+        &merkle_proof.get_path_index(),
+        false,
+    )
+    .expect("Merkle proofs from the tree have path elements and indexes of the same length");
+
+    Ok(RLNProofValues {
+        y,
+        nullifier,
+        root,
+        x,
+        epoch,
+        rln_identifier,
+    })
+}
+
+///////////////////////////////////////////////////////
+// zkSNARK utility functions
+///////////////////////////////////////////////////////
+
+#[derive(Error, Debug)]
+pub enum ProofError {
+    #[error("Error reading circuit key: {0}")]
+    CircuitKeyError(#[from] std::io::Error),
+    #[error("Error producing witness: {0}")]
+    WitnessError(color_eyre::Report),
+    #[error("Error producing proof: {0}")]
+    SynthesisError(#[from] SynthesisError),
+    #[error("Merkle proof length mismatch: the circuit expects a tree of height {expected}, got a path of length {actual}")]
+    TreeHeightMismatch { expected: usize, actual: usize },
+    #[error("Error deserializing proof: {0}")]
+    SerializationError(#[from] SerializationError),
+    #[error("Error reading proof stream: {0}")]
+    StreamError(std::io::Error),
+    #[error("Tree index {index} is out of bounds for a tree of capacity {capacity}")]
+    TreeIndexOutOfBounds { index: u64, capacity: usize },
+    #[error("Merkle tree error: {0}")]
+    TreeError(std::io::Error),
+    #[error("Verifying key expects {expected} public inputs, got {got}")]
+    PublicInputCountMismatch { expected: usize, got: usize },
+    #[error("Invalid witness: {0}")]
+    InvalidWitness(#[from] WitnessValidationError),
+    #[error("Proof generation failed for witness {index} of the batch: {source}")]
+    BatchItemFailed {
+        index: usize,
+        source: Box<ProofError>,
+    },
+}
+
+impl ProofError {
+    // Returns the kind of the error, i.e. the name of its variant, so that callers (e.g. JavaScript ones) can branch on it
+    pub fn kind(&self) -> &'static str {
+        match self {
+            ProofError::CircuitKeyError(_) => "CircuitKeyError",
+            ProofError::WitnessError(_) => "WitnessError",
+            ProofError::SynthesisError(_) => "SynthesisError",
+            ProofError::TreeHeightMismatch { .. } => "TreeHeightMismatch",
+            ProofError::SerializationError(_) => "SerializationError",
+            ProofError::StreamError(_) => "StreamError",
+            ProofError::TreeIndexOutOfBounds { .. } => "TreeIndexOutOfBounds",
+            ProofError::TreeError(_) => "TreeError",
+            ProofError::PublicInputCountMismatch { .. } => "PublicInputCountMismatch",
+            ProofError::InvalidWitness(_) => "InvalidWitness",
+            ProofError::BatchItemFailed { .. } => "BatchItemFailed",
+        }
+    }
+}
+
+// Converts the error to a JavaScript object { kind, message }, where kind is ProofError::kind and message the error description
+#[cfg(feature = "wasm")]
+impl From<ProofError> for wasm_bindgen::JsValue {
+    fn from(error: ProofError) -> Self {
+        let object = js_sys::Object::new();
+        // Setting a property on a fresh plain object can't fail
+        js_sys::Reflect::set(&object, &"kind".into(), &error.kind().into())
+            .expect("property can be set");
+        js_sys::Reflect::set(&object, &"message".into(), &error.to_string().into())
+            .expect("property can be set");
+        object.into()
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RLNError {
+    #[error("Proof error: {0}")]
+    ProofError(#[from] ProofError),
+    #[error("Serialized proof is too short: expected at least {expected} bytes, got {actual}")]
+    ProofDataTooShort { expected: usize, actual: usize },
+    #[error(
+        "Merkle tree is corrupted: the stored root doesn't match the root recomputed from leaves"
+    )]
+    TreeCorrupted,
+    #[error("Invalid Solidity uint256 field element: {0}")]
+    InvalidSolidityUint(String),
+    #[error("I/O error: {0}")]
+    IoError(#[from] std::io::Error),
+}
+
+// Loads the proving key and constraint matrices from the bytes of a circom .zkey file
+// Returns an error if bytes is empty or is not a valid .zkey file
+pub fn load_proving_key(
+    bytes: &[u8],
+) -> Result<(ProvingKey<Curve>, ConstraintMatrices<Fr>), ProofError> {
+    if bytes.is_empty() {
+        return Err(ProofError::CircuitKeyError(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No proving key found!",
+        )));
+    }
+
+    let mut reader = Cursor::new(bytes);
+    let proving_key_and_matrices = read_zkey(&mut reader)?;
+    Ok(proving_key_and_matrices)
+}
+
+// The header of proving keys serialized in the arkzkey format, i.e. a magic string followed by the format version
+const ARKZKEY_HEADER: &[u8; 8] = b"arkzkey\x01";
+
+// Maps an error raised while (de)serializing an arkzkey to a circuit key error
+fn arkzkey_error<E: ToString>(error: E) -> ProofError {
+    ProofError::CircuitKeyError(io::Error::new(
+        io::ErrorKind::InvalidData,
+        error.to_string(),
+    ))
+}
+
+// Serializes a proving key and its constraint matrices in the arkzkey format, i.e.
+// [ header<8> | proving_key<var> | matrices_sizes<6*8> | a<var> | b<var> | c<var> ]
+// where the proving key is uncompressed, each constraint matrix is serialized as its rows of (coefficient, column) entries
+// and all lengths and sizes are 8 bytes little-endian. Loading it with load_arkzkey skips the .zkey parsing and point decompression of load_proving_key
+// Returns an error if the proving key cannot be serialized
+pub fn serialize_arkzkey(
+    proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+) -> Result<Vec<u8>, ProofError> {
+    let (proving_key, matrices) = proving_key;
+
+    let mut serialized = ARKZKEY_HEADER.to_vec();
+    proving_key
+        .serialize_uncompressed(&mut serialized)
+        .map_err(arkzkey_error)?;
+    serialized.append(&mut serialize_constraint_matrices(matrices));
+
+    Ok(serialized)
+}
+
+// Serializes constraint matrices as [ matrices_sizes<6*8> | a<var> | b<var> | c<var> ], as in the arkzkey format
+fn serialize_constraint_matrices(matrices: &ConstraintMatrices<Fr>) -> Vec<u8> {
+    let mut serialized = Vec::new();
+
+    for size in [
+        matrices.num_instance_variables,
+        matrices.num_witness_variables,
+        matrices.num_constraints,
+        matrices.a_num_non_zero,
+        matrices.b_num_non_zero,
+        matrices.c_num_non_zero,
+    ] {
+        serialized.extend_from_slice(&u64::try_from(size).unwrap().to_le_bytes());
+    }
+
+    for matrix in [&matrices.a, &matrices.b, &matrices.c] {
+        serialized.extend_from_slice(&u64::try_from(matrix.len()).unwrap().to_le_bytes());
+        for row in matrix {
+            serialized.extend_from_slice(&u64::try_from(row.len()).unwrap().to_le_bytes());
+            for (coefficient, column) in row {
+                serialized.append(&mut fr_to_bytes_le(coefficient));
+                serialized.extend_from_slice(&u64::try_from(*column).unwrap().to_le_bytes());
+            }
+        }
+    }
+
+    serialized
+}
+
+// The domain separating proving key fingerprints from other Keccak256 hashes
+const PROVING_KEY_FINGERPRINT_DOMAIN: &[u8] = b"zerokit/rln/proving_key_fingerprint";
 
-    // If shares come from the same polynomial, a0 is correctly recovered and a1 = poseidonHash([a0, external_nullifier])
-    let computed_a_1 = poseidon_hash(&[a_0, external_nullifier]);
+// Computes a stable fingerprint of a proving key and its constraint matrices, i.e. the Keccak256 hash of the matrices sizes,
+// of the matrices themselves and of the verifying key embedded in the proving key. The matrices identify the circuit and the verifying key
+// the trusted setup ceremony, so that nodes can check the loaded key against a known-good fingerprint at startup
+pub fn proving_key_fingerprint(
+    proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+) -> [u8; 32] {
+    let (proving_key, matrices) = proving_key;
 
-    if a_1 == computed_a_1 {
-        // We successfully recovered the identity secret
-        return Ok(a_0);
-    } else {
-        return Err("Cannot recover identity_secret_hash from provided shares".into());
+    let mut verifying_key = Vec::new();
+    proving_key
+        .vk
+        .serialize(&mut verifying_key)
+        .expect("serialization to a vector can't fail");
+
+    let mut hasher = Keccak::v256();
+    hasher.update(PROVING_KEY_FINGERPRINT_DOMAIN);
+    hasher.update(&serialize_constraint_matrices(matrices));
+    hasher.update(&verifying_key);
+
+    let mut fingerprint = [0; 32];
+    hasher.finalize(&mut fingerprint);
+    fingerprint
+}
+
+// Converts the bytes of a circom .zkey file to the arkzkey format, so that keys can be converted once and then loaded with load_arkzkey
+// Returns an error if zkey_bytes is not a valid .zkey file
+pub fn zkey_to_arkzkey(zkey_bytes: &[u8]) -> Result<Vec<u8>, ProofError> {
+    serialize_arkzkey(&load_proving_key(zkey_bytes)?)
+}
+
+// Loads the proving key and constraint matrices from their arkzkey serialization (see serialize_arkzkey)
+// Curve points are deserialized without subgroup checks, which makes loading much faster than load_proving_key:
+// as for .zkey files, arkzkey files must come from a trusted source, e.g. converted locally with zkey_to_arkzkey
+// Returns an error if bytes is not a valid arkzkey serialization
+pub fn load_arkzkey(
+    bytes: &[u8],
+) -> Result<(ProvingKey<Curve>, ConstraintMatrices<Fr>), ProofError> {
+    let mut serialized = bytes
+        .strip_prefix(ARKZKEY_HEADER)
+        .ok_or_else(|| arkzkey_error("invalid arkzkey header"))?;
+
+    let proving_key =
+        ProvingKey::<Curve>::deserialize_unchecked(&mut serialized).map_err(arkzkey_error)?;
+
+    let mut reader = CheckedReader::new(serialized);
+    let mut sizes = [0usize; 6];
+    for size in sizes.iter_mut() {
+        *size = reader.read_len().map_err(arkzkey_error)?;
     }
+
+    let mut read_matrix = || -> Result<Vec<Vec<(Fr, usize)>>, SerializationError> {
+        let rows = reader.read_len()?;
+        let mut matrix = Vec::new();
+        for _ in 0..rows {
+            let entries = reader.read_len()?;
+            let mut row = Vec::new();
+            for _ in 0..entries {
+                row.push((reader.read_fr()?, reader.read_len()?));
+            }
+            matrix.push(row);
+        }
+        Ok(matrix)
+    };
+    let a = read_matrix().map_err(arkzkey_error)?;
+    let b = read_matrix().map_err(arkzkey_error)?;
+    let c = read_matrix().map_err(arkzkey_error)?;
+    reader.finish().map_err(arkzkey_error)?;
+
+    let [num_instance_variables, num_witness_variables, num_constraints, a_num_non_zero, b_num_non_zero, c_num_non_zero] =
+        sizes;
+
+    Ok((
+        proving_key,
+        ConstraintMatrices {
+            num_instance_variables,
+            num_witness_variables,
+            num_constraints,
+            a_num_non_zero,
+            b_num_non_zero,
+            c_num_non_zero,
+            a,
+            b,
+            c,
+        },
+    ))
 }
 
-///////////////////////////////////////////////////////
-// zkSNARK utility functions
-///////////////////////////////////////////////////////
+// Loads a verifying key from its arkworks canonical serialization. If only the proving key is available, use extract_verifying_key
+// Returns an error if bytes is not a valid serialized verifying key
+pub fn load_verifying_key(bytes: &[u8]) -> Result<VerifyingKey<Curve>, ProofError> {
+    VerifyingKey::<Curve>::deserialize(bytes).map_err(|e| {
+        ProofError::CircuitKeyError(io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    })
+}
 
-#[derive(Error, Debug)]
-pub enum ProofError {
-    #[error("Error reading circuit key: {0}")]
-    CircuitKeyError(#[from] std::io::Error),
-    #[error("Error producing witness: {0}")]
-    WitnessError(color_eyre::Report),
-    #[error("Error producing proof: {0}")]
-    SynthesisError(#[from] SynthesisError),
+// Derives the verifying key from the proving key it is embedded in
+pub fn extract_verifying_key(proving_key: &ProvingKey<Curve>) -> VerifyingKey<Curve> {
+    proving_key.vk.clone()
+}
+
+// Derives the verifying key from a proving key bundle, as returned by zkey_from_folder or load_proving_key.
+// This allows a single artifact to be used both for proving and verifying,
+// and prevents mismatches with verifying keys exported from different ceremonies
+pub fn verifying_key_from_proving_key(
+    proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+) -> VerifyingKey<Curve> {
+    extract_verifying_key(&proving_key.0)
 }
 
 fn calculate_witness_element<E: ark_ec::PairingEngine>(witness: Vec<BigInt>) -> Result<Vec<E::Fr>> {
@@ -561,28 +2696,42 @@ fn calculate_witness_element<E: ark_ec::PairingEngine>(witness: Vec<BigInt>) ->
     Ok(witness)
 }
 
+// Generates a RLN proof from a witness computed outside zerokit, without running the circom witness calculator
+// witness is the full circuit assignment in the circom wire order (e.g. as output by WitnessCalculator::calculate_witness or in snarkjs .wtns files),
+// i.e. the constant 1, the public outputs [y, root, nullifier], the public inputs [x, epoch, rln_identifier] and then all private inputs
+// and intermediate signals. The circuit inputs to compute it are given by inputs_for_witness_calculation. Negative values are reduced modulo the field order
+// Returns an error if the witness cannot be converted to field elements or proving fails
 pub fn generate_proof_with_witness(
     witness: Vec<BigInt>,
     proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
 ) -> Result<ArkProof<Curve>, ProofError> {
-    // If in debug mode, we measure and later print time take to compute witness
-    #[cfg(debug_assertions)]
+    // With the tracing feature enabled, we record witness and proof generation times in a debug span
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        "generate_proof",
+        witness_generation_time = tracing::field::Empty,
+        proof_generation_time = tracing::field::Empty,
+    );
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
     let now = Instant::now();
 
-    let full_assignment = calculate_witness_element::<Curve>(witness)
-        .map_err(ProofError::WitnessError)
-        .unwrap();
+    let full_assignment =
+        calculate_witness_element::<Curve>(witness).map_err(ProofError::WitnessError)?;
 
-    #[cfg(debug_assertions)]
-    println!("witness generation took: {:.2?}", now.elapsed());
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+    span.record(
+        "witness_generation_time",
+        &tracing::field::debug(now.elapsed()),
+    );
 
     // Random Values
     let mut rng = thread_rng();
     let r = Fr::rand(&mut rng);
     let s = Fr::rand(&mut rng);
 
-    // If in debug mode, we measure and later print time take to compute proof
-    #[cfg(debug_assertions)]
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
     let now = Instant::now();
 
     let proof = create_proof_with_reduction_and_matrices::<_, CircomReduction>(
@@ -593,23 +2742,33 @@ pub fn generate_proof_with_witness(
         proving_key.1.num_instance_variables,
         proving_key.1.num_constraints,
         full_assignment.as_slice(),
-    )
-    .unwrap();
+    )?;
 
-    #[cfg(debug_assertions)]
-    println!("proof generation took: {:.2?}", now.elapsed());
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+    span.record(
+        "proof_generation_time",
+        &tracing::field::debug(now.elapsed()),
+    );
 
     Ok(proof)
 }
 
-pub fn inputs_for_witness_calculation(rln_witness: &RLNWitnessInput) -> [(&str, Vec<BigInt>); 6] {
+// Converts a field element of any prime field to a BigInt
+fn field_to_bigint<F: PrimeField>(el: &F) -> BigInt {
+    let res: BigUint = (*el).into();
+    BigInt::from(res)
+}
+
+pub fn inputs_for_witness_calculation<E: PairingEngine>(
+    rln_witness: &RLNWitnessInput<E>,
+) -> [(&str, Vec<BigInt>); 6] {
     // We confert the path indexes to field elements
     // TODO: check if necessary
     let mut path_elements = Vec::new();
     rln_witness
         .path_elements
         .iter()
-        .for_each(|v| path_elements.push(to_bigint(v)));
+        .for_each(|v| path_elements.push(field_to_bigint(v)));
 
     let mut identity_path_index = Vec::new();
     rln_witness
@@ -620,64 +2779,151 @@ pub fn inputs_for_witness_calculation(rln_witness: &RLNWitnessInput) -> [(&str,
     [
         (
             "identity_secret",
-            vec![to_bigint(&rln_witness.identity_secret)],
+            vec![field_to_bigint(&rln_witness.identity_secret)],
         ),
         ("path_elements", path_elements),
         ("identity_path_index", identity_path_index),
-        ("x", vec![to_bigint(&rln_witness.x)]),
-        ("epoch", vec![to_bigint(&rln_witness.epoch)]),
+        ("x", vec![field_to_bigint(&rln_witness.x)]),
+        ("epoch", vec![field_to_bigint(&rln_witness.epoch)]),
         (
             "rln_identifier",
-            vec![to_bigint(&rln_witness.rln_identifier)],
+            vec![field_to_bigint(&rln_witness.rln_identifier)],
         ),
     ]
 }
 
-/// Generates a RLN proof
-///
-/// # Errors
-///
-/// Returns a [`ProofError`] if proving fails.
-pub fn generate_proof(
-    #[cfg(not(target_arch = "wasm32"))] witness_calculator: &Mutex<WitnessCalculator>,
-    #[cfg(target_arch = "wasm32")] witness_calculator: &mut WitnessCalculator,
-    proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
-    rln_witness: &RLNWitnessInput,
-) -> Result<ArkProof<Curve>, ProofError> {
-    let inputs = inputs_for_witness_calculation(rln_witness)
-        .into_iter()
-        .map(|(name, values)| (name.to_string(), values));
+// A backend computing the circuit witness, i.e. the full assignment of the RLN circuit wires, from a RLN witness
+// The assignment must follow the circom wire order: the constant 1, the public outputs [y, root, nullifier],
+// the public inputs [x, epoch, rln_identifier] and then all private inputs and intermediate signals.
+// WitnessCalculator is the default implementation. The trait is also implemented for references to a generator and (except on wasm32)
+// for a Mutex wrapping one, so that native witness generators can be plugged into generate_proof in place of the circom one
+pub trait WitnessGenerator<E: PairingEngine = Curve> {
+    // Computes the full circuit assignment for the RLN witness, or returns an error if it cannot be computed
+    fn calculate(&mut self, rln_witness: &RLNWitnessInput<E>) -> Result<Vec<E::Fr>, ProofError>;
+}
 
-    // If in debug mode, we measure and later print time take to compute witness
-    #[cfg(debug_assertions)]
-    let now = Instant::now();
+impl<E: PairingEngine> WitnessGenerator<E> for WitnessCalculator {
+    fn calculate(&mut self, rln_witness: &RLNWitnessInput<E>) -> Result<Vec<E::Fr>, ProofError> {
+        let inputs = inputs_for_witness_calculation(rln_witness)
+            .into_iter()
+            .map(|(name, values)| (name.to_string(), values));
 
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let full_assignment = witness_calculator
-            .calculate_witness_element::<Curve, _>(inputs, false)
-            .map_err(ProofError::WitnessError)?;
-        } else {
-            let full_assignment = witness_calculator
-            .lock()
-            .expect("witness_calculator mutex should not get poisoned")
-            .calculate_witness_element::<Curve, _>(inputs, false)
-            .map_err(ProofError::WitnessError)?;
-        }
+        self.calculate_witness_element::<E, _>(inputs, false)
+            .map_err(ProofError::WitnessError)
+    }
+}
+
+impl<E: PairingEngine, W: WitnessGenerator<E> + ?Sized> WitnessGenerator<E> for &mut W {
+    fn calculate(&mut self, rln_witness: &RLNWitnessInput<E>) -> Result<Vec<E::Fr>, ProofError> {
+        (**self).calculate(rln_witness)
     }
+}
 
-    #[cfg(debug_assertions)]
-    println!("witness generation took: {:.2?}", now.elapsed());
+#[cfg(not(target_arch = "wasm32"))]
+impl<E: PairingEngine, W: WitnessGenerator<E>> WitnessGenerator<E> for &Mutex<W> {
+    fn calculate(&mut self, rln_witness: &RLNWitnessInput<E>) -> Result<Vec<E::Fr>, ProofError> {
+        // If a thread panicked while holding the lock, we recover the witness generator:
+        // the witness calculator state is reset at each witness calculation, so it can still be safely used
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .calculate(rln_witness)
+    }
+}
 
+// Generates a RLN proof over the pairing engine E of the proving key, usually Curve
+// The circuit witness is computed by witness_generator, usually the circom WitnessCalculator
+// Returns an error if the witness doesn't pass validate_witness, or if witness generation or proving fails
+pub fn generate_proof<E: PairingEngine, W: WitnessGenerator<E>>(
+    witness_generator: W,
+    proving_key: &(ProvingKey<E>, ConstraintMatrices<E::Fr>),
+    rln_witness: &RLNWitnessInput<E>,
+) -> Result<ArkProof<E>, ProofError> {
     // Random Values
     let mut rng = thread_rng();
-    let r = Fr::rand(&mut rng);
-    let s = Fr::rand(&mut rng);
+    let r = E::Fr::rand(&mut rng);
+    let s = E::Fr::rand(&mut rng);
+
+    generate_proof_with_blinding(witness_generator, proving_key, rln_witness, r, s)
+}
+
+// Generates a RLN proof using the provided Groth16 blinding values r and s, so that the same inputs always give the same proof
+// This is meant for tests and conformance vectors only: proofs are zero-knowledge only if r and s are sampled from fresh randomness
+// for each proof, and reusing them may leak the witness, so production callers must use generate_proof
+// Returns an error if the witness doesn't pass validate_witness, or if witness generation or proving fails
+pub fn generate_proof_deterministic<E: PairingEngine, W: WitnessGenerator<E>>(
+    witness_generator: W,
+    proving_key: &(ProvingKey<E>, ConstraintMatrices<E::Fr>),
+    rln_witness: &RLNWitnessInput<E>,
+    r: E::Fr,
+    s: E::Fr,
+) -> Result<ArkProof<E>, ProofError> {
+    generate_proof_with_blinding(witness_generator, proving_key, rln_witness, r, s)
+}
+
+// Generates a RLN proof with the Groth16 blinding values r and s
+fn generate_proof_with_blinding<E: PairingEngine, W: WitnessGenerator<E>>(
+    mut witness_generator: W,
+    proving_key: &(ProvingKey<E>, ConstraintMatrices<E::Fr>),
+    rln_witness: &RLNWitnessInput<E>,
+    r: E::Fr,
+    s: E::Fr,
+) -> Result<ArkProof<E>, ProofError> {
+    validate_witness(rln_witness)?;
+
+    // With the tracing feature enabled, we record witness and proof generation times in a debug span
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!(
+        "generate_proof",
+        witness_generation_time = tracing::field::Empty,
+        proof_generation_time = tracing::field::Empty,
+    );
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+    let now = Instant::now();
+
+    let full_assignment = witness_generator.calculate(rln_witness)?;
+
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+    span.record(
+        "witness_generation_time",
+        &tracing::field::debug(now.elapsed()),
+    );
 
-    // If in debug mode, we measure and later print time take to compute proof
-    #[cfg(debug_assertions)]
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
     let now = Instant::now();
 
+    let proof = prove_full_assignment_with_blinding(proving_key, &full_assignment, r, s)?;
+
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+    span.record(
+        "proof_generation_time",
+        &tracing::field::debug(now.elapsed()),
+    );
+
+    Ok(proof)
+}
+
+// Generates a zkSNARK proof for the full circuit assignment computed by the witness calculator
+fn prove_full_assignment<E: PairingEngine>(
+    proving_key: &(ProvingKey<E>, ConstraintMatrices<E::Fr>),
+    full_assignment: &[E::Fr],
+) -> Result<ArkProof<E>, ProofError> {
+    // Random Values
+    let mut rng = thread_rng();
+    let r = E::Fr::rand(&mut rng);
+    let s = E::Fr::rand(&mut rng);
+
+    prove_full_assignment_with_blinding(proving_key, full_assignment, r, s)
+}
+
+// Generates a zkSNARK proof for the full circuit assignment with the Groth16 blinding values r and s
+fn prove_full_assignment_with_blinding<E: PairingEngine>(
+    proving_key: &(ProvingKey<E>, ConstraintMatrices<E::Fr>),
+    full_assignment: &[E::Fr],
+    r: E::Fr,
+    s: E::Fr,
+) -> Result<ArkProof<E>, ProofError> {
     let proof = create_proof_with_reduction_and_matrices::<_, CircomReduction>(
         &proving_key.0,
         r,
@@ -685,57 +2931,475 @@ pub fn generate_proof(
         &proving_key.1,
         proving_key.1.num_instance_variables,
         proving_key.1.num_constraints,
-        full_assignment.as_slice(),
+        full_assignment,
     )?;
 
-    #[cfg(debug_assertions)]
-    println!("proof generation took: {:.2?}", now.elapsed());
-
     Ok(proof)
 }
 
-/// Verifies a given RLN proof
-///
-/// # Errors
-///
-/// Returns a [`ProofError`] if verifying fails. Verification failure does not
-/// necessarily mean the proof is incorrect.
-pub fn verify_proof(
+// Generates a RLN proof for each witness, sharing the witness calculator and the proving key
+// All witnesses are first validated and calculated holding the witness calculator lock only once,
+// then proofs are generated (in parallel, with the parallel feature enabled)
+// Returns a BatchItemFailed error with the index of the first witness for which validation, witness calculation or proving fails
+pub fn generate_proofs_batch<E: PairingEngine>(
+    #[cfg(not(target_arch = "wasm32"))] witness_calculator: &Mutex<WitnessCalculator>,
+    #[cfg(target_arch = "wasm32")] witness_calculator: &mut WitnessCalculator,
+    proving_key: &(ProvingKey<E>, ConstraintMatrices<E::Fr>),
+    witnesses: &[RLNWitnessInput<E>],
+) -> Result<Vec<ArkProof<E>>, ProofError> {
+    fn batch_error(index: usize, error: ProofError) -> ProofError {
+        ProofError::BatchItemFailed {
+            index,
+            source: Box::new(error),
+        }
+    }
+
+    for (index, rln_witness) in witnesses.iter().enumerate() {
+        validate_witness(rln_witness).map_err(|e| batch_error(index, e.into()))?;
+    }
+
+    let full_assignments = {
+        cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                let calculator = witness_calculator;
+            } else {
+                // As in generate_proof, we recover the witness calculator if the lock is poisoned
+                let mut calculator = witness_calculator
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+
+        witnesses
+            .iter()
+            .enumerate()
+            .map(|(index, rln_witness)| {
+                WitnessGenerator::<E>::calculate(&mut *calculator, rln_witness)
+                    .map_err(|e| batch_error(index, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+
+    cfg_if! {
+        if #[cfg(feature = "parallel")] {
+            let full_assignments_iter = full_assignments.par_iter();
+        } else {
+            let full_assignments_iter = full_assignments.iter();
+        }
+    }
+
+    // Results are collected per witness before picking the first error, since collecting
+    // a parallel iterator into a Result returns an arbitrary error when several proofs fail
+    let proofs: Vec<Result<ArkProof<E>, ProofError>> = full_assignments_iter
+        .enumerate()
+        .map(|(index, full_assignment)| {
+            prove_full_assignment(proving_key, full_assignment).map_err(|e| batch_error(index, e))
+        })
+        .collect();
+
+    proofs.into_iter().collect()
+}
+
+// Generates a RLN proof from a witness serialized with serialize_witness
+// Returns an error if the witness cannot be deserialized or proving fails
+pub fn generate_proof_from_serialized_witness<W: WitnessGenerator>(
+    serialized: &[u8],
+    witness_generator: W,
+    proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+) -> Result<ArkProof<Curve>, RLNError> {
+    let (rln_witness, _) = deserialize_witness(serialized).map_err(ProofError::from)?;
+    Ok(generate_proof(
+        witness_generator,
+        proving_key,
+        &rln_witness,
+    )?)
+}
+
+// Generates a RLN proof and returns it serialized with serialize_proof, so that FFI bindings only marshal byte buffers, never arkworks types
+// The proof can be verified with verify_proof_serialized. Returns an error if proving or serializing the proof fails
+pub fn generate_proof_serialized<W: WitnessGenerator>(
+    witness_generator: W,
+    proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+    rln_witness: &RLNWitnessInput,
+) -> Result<Vec<u8>, ProofError> {
+    let proof = generate_proof(witness_generator, proving_key, rln_witness)?;
+    Ok(serialize_proof(&proof)?)
+}
+
+// Verifies a RLN proof serialized with serialize_proof against proof values serialized with serialize_proof_values
+// Meant for FFI bindings, together with generate_proof_serialized
+// Returns an error if the proof or the proof values are malformed, or if verifying fails
+pub fn verify_proof_serialized(
     verifying_key: &VerifyingKey<Curve>,
-    proof: &ArkProof<Curve>,
-    proof_values: &RLNProofValues,
+    proof: &[u8],
+    proof_values: &[u8],
 ) -> Result<bool, ProofError> {
+    let proof = deserialize_proof(proof)?;
+    if proof_values.len() != SERIALIZED_PROOF_VALUES_LEN {
+        return Err(SerializationError::InvalidLength {
+            expected: SERIALIZED_PROOF_VALUES_LEN,
+            actual: proof_values.len(),
+        }
+        .into());
+    }
+    let (proof_values, _) = deserialize_proof_values(proof_values);
+
+    verify_proof(verifying_key, &proof, &proof_values)
+}
+
+/// Returns the public inputs of the RLN circuit in the order expected by the verifier, i.e.
+/// `[ y, root, nullifier, x, epoch, rln_identifier ]`.
+///
+/// This is the ordering used by [`verify_proof`], to be used by external verifiers and calldata builders.
+pub fn public_inputs<E: PairingEngine>(proof_values: &RLNProofValues<E>) -> Vec<E::Fr> {
     // We re-arrange proof-values according to the circuit specification
-    let inputs = vec![
+    vec![
         proof_values.y,
         proof_values.root,
         proof_values.nullifier,
         proof_values.x,
         proof_values.epoch,
         proof_values.rln_identifier,
-    ];
+    ]
+}
+
+// Checks that the verifying key expects as many public inputs as provided, i.e. that it was generated for the RLN circuit.
+// This gives a clear error when a mismatched verifying key is loaded, rather than an arkworks verification error
+fn check_public_input_count<E: PairingEngine>(
+    verifying_key: &VerifyingKey<E>,
+    inputs: &[E::Fr],
+) -> Result<(), ProofError> {
+    // The verifying key has a point for the constant one in addition to one per public input
+    let expected = verifying_key.gamma_abc_g1.len().saturating_sub(1);
+    if inputs.len() != expected {
+        return Err(ProofError::PublicInputCountMismatch {
+            expected,
+            got: inputs.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Verifies a given RLN proof
+///
+/// The proof is verified over the pairing engine `E` of the verifying key, usually [`Curve`].
+///
+/// # Errors
+///
+/// Returns a [`ProofError`] if verifying fails. Verification failure does not
+/// necessarily mean the proof is incorrect.
+pub fn verify_proof<E: PairingEngine>(
+    verifying_key: &VerifyingKey<E>,
+    proof: &ArkProof<E>,
+    proof_values: &RLNProofValues<E>,
+) -> Result<bool, ProofError> {
+    let inputs = public_inputs(proof_values);
+    check_public_input_count(verifying_key, &inputs)?;
 
     // Check that the proof is valid
     let pvk = prepare_verifying_key(verifying_key);
     //let pr: ArkProof<Curve> = (*proof).into();
 
-    // If in debug mode, we measure and later print time take to verify proof
-    #[cfg(debug_assertions)]
+    // With the tracing feature enabled, we record the verification time in a debug span
+    #[cfg(feature = "tracing")]
+    let span = tracing::debug_span!("verify_proof", verification_time = tracing::field::Empty);
+    #[cfg(feature = "tracing")]
+    let _entered = span.enter();
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
     let now = Instant::now();
 
     let verified = ark_verify_proof(&pvk, proof, &inputs)?;
 
-    #[cfg(debug_assertions)]
-    println!("verify took: {:.2?}", now.elapsed());
+    #[cfg(all(feature = "tracing", not(target_arch = "wasm32")))]
+    span.record("verification_time", &tracing::field::debug(now.elapsed()));
 
     Ok(verified)
 }
 
+// Verifies a RLN proof against a root trusted by the verifier, e.g. a root known from a smart contract
+// The root in the proof values is ignored and replaced by trusted_root in the public inputs, so that the proof verifies
+// only if it was generated against trusted_root rather than against a root asserted by the prover
+// Returns an error if verifying fails. Verification failure does not necessarily mean the proof is incorrect
+pub fn verify_proof_against_root<E: PairingEngine>(
+    verifying_key: &VerifyingKey<E>,
+    proof: &ArkProof<E>,
+    proof_values: &RLNProofValues<E>,
+    trusted_root: &E::Fr,
+) -> Result<bool, ProofError> {
+    let proof_values = RLNProofValues {
+        root: *trusted_root,
+        ..*proof_values
+    };
+
+    verify_proof(verifying_key, proof, &proof_values)
+}
+
+// Verifies a RLN proof for a signal known to the verifier only through its hash x, i.e. without the original signal bytes
+// The proof is accepted only if the provided x matches the one in the proof values
+// Returns an error if verifying fails
+pub fn verify_with_prehashed_x(
+    verifying_key: &VerifyingKey<Curve>,
+    proof: &ArkProof<Curve>,
+    proof_values: &RLNProofValues,
+    x: Fr,
+) -> Result<bool, RLNError> {
+    if proof_values.x != x {
+        return Ok(false);
+    }
+
+    Ok(verify_proof(verifying_key, proof, proof_values)?)
+}
+
+// Verifies a RLN proof for the given signal. The proof is accepted only if x in the proof values is signal_to_field(signal),
+// so that the proof is never validated against a different message than the one the verifier holds
+// Returns an error if verifying fails
+pub fn verify_proof_for_signal(
+    verifying_key: &VerifyingKey<Curve>,
+    proof: &ArkProof<Curve>,
+    proof_values: &RLNProofValues,
+    signal: &[u8],
+) -> Result<bool, ProofError> {
+    if proof_values.x != signal_to_field(signal) {
+        return Ok(false);
+    }
+
+    verify_proof(verifying_key, proof, proof_values)
+}
+
+// Verifies the proof as verify_proof_for_signal does, computing the signal hash with signal_hasher, so that
+// repeated verifications for the same signal don't recompute it
+pub fn verify_proof_for_signal_with_hasher(
+    verifying_key: &VerifyingKey<Curve>,
+    proof: &ArkProof<Curve>,
+    proof_values: &RLNProofValues,
+    signal: &[u8],
+    signal_hasher: &mut SignalHasher,
+) -> Result<bool, ProofError> {
+    if proof_values.x != signal_hasher.hash(signal) {
+        return Ok(false);
+    }
+
+    verify_proof(verifying_key, proof, proof_values)
+}
+
+// Verifies with verify_proof_for_signal a zkSNARK proof concatenated with its proof values, i.e.
+// [ proof<128> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> | rln_identifier<32> ]
+// Returns an error if proof_and_values has the wrong length or doesn't contain a valid proof, or if verifying fails
+pub fn verify_serialized(
+    verifying_key: &VerifyingKey<Curve>,
+    proof_and_values: &[u8],
+    signal: &[u8],
+) -> Result<bool, ProofError> {
+    let (proof, proof_values) = deserialize_proof_and_values(proof_and_values)?;
+
+    verify_proof_for_signal(verifying_key, &proof, &proof_values, signal)
+}
+
+// Verifies a RLN proof generated against any of the acceptable Merkle tree roots, e.g. the recent roots recorded by a RootHistory
+// The zkSNARK proof is verified only once, and only if proof_values.root is one of acceptable_roots
+// Returns an error if verifying fails
+pub fn verify_proof_with_roots(
+    verifying_key: &VerifyingKey<Curve>,
+    proof: &ArkProof<Curve>,
+    proof_values: &RLNProofValues,
+    acceptable_roots: &[Fr],
+) -> Result<bool, ProofError> {
+    if !acceptable_roots.contains(&proof_values.root) {
+        return Ok(false);
+    }
+
+    verify_proof(verifying_key, proof, proof_values)
+}
+
+// Converts a field element to a u64, if it fits
+fn fr_to_u64(el: &Fr) -> Option<u64> {
+    let el: BigUint = (*el).into();
+    u64::try_from(&el).ok()
+}
+
+// Checks that epoch is within tolerance epochs of current_epoch (in either direction), e.g. tolerance 1 accepts
+// the previous, the current and the next epoch. Both epochs are expected to encode small epoch numbers,
+// e.g. Fr::from(epoch_number): epochs that don't fit in a u64 are never in the window
+pub fn epoch_in_window(epoch: Fr, current_epoch: Fr, tolerance: u64) -> bool {
+    match (fr_to_u64(&epoch), fr_to_u64(&current_epoch)) {
+        (Some(epoch), Some(current_epoch)) => epoch.abs_diff(current_epoch) <= tolerance,
+        _ => false,
+    }
+}
+
+// The length in bytes of a record in a proof stream, i.e. a serialized proof concatenated with its proof values
+const PROOF_STREAM_RECORD_LEN: usize = SERIALIZED_PROOF_LEN + SERIALIZED_PROOF_VALUES_LEN;
+
+/// Verifies a stream of serialized RLN proofs, one record at a time.
+///
+/// Each record is a zkSNARK proof concatenated with its proof values, i.e.
+/// `[ proof<128> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> | rln_identifier<32> ]`,
+/// and the returned iterator yields the verification result of each record in stream order.
+///
+/// Memory usage does not depend on the number of records: the verifying key is prepared once,
+/// a single record buffer is reused and results are not accumulated, so at most one proof
+/// is held in memory at any time. Callers consuming the iterator lazily (rather than collecting it)
+/// can therefore verify archives of any size with the memory needed to verify a single proof.
+///
+/// A record that fails to deserialize yields an error and verification continues with the next one.
+/// The iterator ends after a truncated record or a read error.
+pub fn verify_proof_stream<R: Read>(
+    verifying_key: &VerifyingKey<Curve>,
+    reader: R,
+) -> ProofStreamVerifier<R> {
+    ProofStreamVerifier {
+        prepared_verifying_key: prepare_verifying_key(verifying_key),
+        reader,
+        record: [0; PROOF_STREAM_RECORD_LEN],
+        done: false,
+    }
+}
+
+// The iterator returned by verify_proof_stream
+pub struct ProofStreamVerifier<R: Read> {
+    prepared_verifying_key: PreparedVerifyingKey<Curve>,
+    reader: R,
+    record: [u8; PROOF_STREAM_RECORD_LEN],
+    done: bool,
+}
+
+impl<R: Read> ProofStreamVerifier<R> {
+    // Fills the record buffer and returns the number of bytes read, which is smaller than the record length only at the end of the stream
+    fn read_record(&mut self) -> io::Result<usize> {
+        let mut read = 0;
+        while read < PROOF_STREAM_RECORD_LEN {
+            match self.reader.read(&mut self.record[read..]) {
+                Ok(0) => break,
+                Ok(n) => read += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(read)
+    }
+
+    fn verify_record(&self) -> Result<bool, ProofError> {
+        let (proof, proof_values) = deserialize_proof_and_values(&self.record)?;
+
+        let inputs = public_inputs(&proof_values);
+        check_public_input_count(&self.prepared_verifying_key.vk, &inputs)?;
+
+        Ok(ark_verify_proof(
+            &self.prepared_verifying_key,
+            &proof,
+            &inputs,
+        )?)
+    }
+}
+
+impl<R: Read> Iterator for ProofStreamVerifier<R> {
+    type Item = Result<bool, ProofError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.read_record() {
+            Ok(0) => {
+                self.done = true;
+                None
+            }
+            Ok(read) if read < PROOF_STREAM_RECORD_LEN => {
+                self.done = true;
+                Some(Err(SerializationError::InvalidLength {
+                    expected: PROOF_STREAM_RECORD_LEN,
+                    actual: read,
+                }
+                .into()))
+            }
+            Ok(_) => Some(self.verify_record()),
+            Err(e) => {
+                self.done = true;
+                Some(Err(ProofError::StreamError(e)))
+            }
+        }
+    }
+}
+
+// Serializes a base field element as a big-endian uint256
+fn fq_to_uint256_be(element: &Fq) -> Vec<u8> {
+    element.into_repr().to_bytes_be()
+}
+
+/// Encodes a proof as the `uint256[8]` expected by Solidity Groth16 verifiers.
+///
+/// The output is 256 bytes, i.e. 8 big-endian 32 bytes words laid out as
+/// `[a.x, a.y, b.x.c1, b.x.c0, b.y.c1, b.y.c0, c.x, c.y]`.
+/// This matches the snarkjs `exportSolidityCallData` convention, where the two
+/// coefficients of each G2 coordinate are swapped as required by the EIP-197 precompile.
+/// No point negation is applied: Solidity verifiers negate `a` themselves.
+pub fn proof_to_eth_abi(proof: &ArkProof<Curve>) -> Vec<u8> {
+    let mut serialized: Vec<u8> = Vec::with_capacity(256);
+
+    serialized.append(&mut fq_to_uint256_be(&proof.a.x));
+    serialized.append(&mut fq_to_uint256_be(&proof.a.y));
+    serialized.append(&mut fq_to_uint256_be(&proof.b.x.c1));
+    serialized.append(&mut fq_to_uint256_be(&proof.b.x.c0));
+    serialized.append(&mut fq_to_uint256_be(&proof.b.y.c1));
+    serialized.append(&mut fq_to_uint256_be(&proof.b.y.c0));
+    serialized.append(&mut fq_to_uint256_be(&proof.c.x));
+    serialized.append(&mut fq_to_uint256_be(&proof.c.y));
+
+    serialized
+}
+
+// Encodes a field element as a Solidity uint256, i.e. a 0x-prefixed 64 hex digits big-endian string
+pub fn fr_to_solidity_uint(element: &Fr) -> String {
+    format!("0x{:064x}", to_bigint(element))
+}
+
+// Parses a field element from a Solidity uint256, i.e. a 0x-prefixed 64 hex digits big-endian string.
+// Values greater or equal than the field modulus are rejected
+pub fn fr_from_solidity_uint(input: &str) -> Result<Fr, RLNError> {
+    let digits = input
+        .strip_prefix("0x")
+        .ok_or_else(|| RLNError::InvalidSolidityUint(format!("{input} is not 0x-prefixed")))?;
+
+    if digits.len() != 64 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(RLNError::InvalidSolidityUint(format!(
+            "{input} is not made of 64 hex digits"
+        )));
+    }
+
+    let value = BigUint::from_str_radix(digits, 16)
+        .map_err(|e| RLNError::InvalidSolidityUint(e.to_string()))?;
+
+    // Fr::from(BigUint) reduces modulo the field modulus, so non-canonical values have to be rejected beforehand
+    let modulus: BigUint = <<Fr as PrimeField>::Params as ark_ff::FpParameters>::MODULUS.into();
+    if value >= modulus {
+        return Err(RLNError::InvalidSolidityUint(format!(
+            "{input} exceeds the field modulus"
+        )));
+    }
+
+    Ok(Fr::from(value))
+}
+
 /// Get CIRCOM JSON inputs
 ///
 /// Returns a JSON object containing the inputs necessary to calculate
-/// the witness with CIRCOM on javascript
+/// the witness with CIRCOM on javascript.
+///
+/// Note that `epoch` is formatted as a 0x-prefixed 64 digits hexadecimal string (see [`epoch_to_json_string`]), while all other values are decimal strings.
+/// This is the canonical epoch encoding of the zk-kit witness JSON parsed by [`rln_witness_from_json`], so that witnesses round-trip unchanged.
+/// Witness calculators expecting all inputs in the same radix (e.g. snarkjs) should use [`get_json_inputs_decimal`] instead.
 pub fn get_json_inputs(rln_witness: &RLNWitnessInput) -> serde_json::Value {
+    let mut inputs = get_json_inputs_decimal(rln_witness);
+    inputs["epoch"] = serde_json::Value::String(epoch_to_json_string(&rln_witness.epoch));
+
+    inputs
+}
+
+/// Get CIRCOM JSON inputs with all values formatted as decimal strings
+///
+/// Returns the same JSON object as [`get_json_inputs`], except that `epoch` is a decimal string as all other inputs.
+pub fn get_json_inputs_decimal(rln_witness: &RLNWitnessInput) -> serde_json::Value {
     let mut path_elements = Vec::new();
     rln_witness
         .path_elements
@@ -753,9 +3417,46 @@ pub fn get_json_inputs(rln_witness: &RLNWitnessInput) -> serde_json::Value {
         "path_elements": path_elements,
         "identity_path_index": identity_path_index,
         "x": to_bigint(&rln_witness.x).to_str_radix(10),
-        "epoch":  format!("0x{:064x}", to_bigint(&rln_witness.epoch)),
+        "epoch": to_bigint(&rln_witness.epoch).to_str_radix(10),
         "rln_identifier": to_bigint(&rln_witness.rln_identifier).to_str_radix(10),
     });
 
     inputs
 }
+
+///////////////////////////////////////////////////////
+// Lifecycle check
+///////////////////////////////////////////////////////
+
+// Runs the whole RLN flow with the given circuit resources: derives an identity from seed, inserts its commitment
+// in a fresh tree of height tree_height (which must be the one the circuit was compiled for), builds the witness
+// for signal and epoch, generates a proof and verifies it.
+// Returns the result of the proof verification, or an error if proving or verifying fails
+pub fn full_lifecycle_check<W: WitnessGenerator>(
+    witness_generator: W,
+    proving_key: &(ProvingKey<Curve>, ConstraintMatrices<Fr>),
+    verifying_key: &VerifyingKey<Curve>,
+    tree_height: usize,
+    seed: &[u8],
+    signal: &[u8],
+    epoch: Fr,
+) -> Result<bool, RLNError> {
+    // We derive the identity
+    let (_, _, identity_secret_hash, id_commitment) = extended_seeded_keygen(seed);
+
+    // We insert its commitment in a fresh tree
+    let leaf_index = 0;
+    let mut tree = PoseidonTree::default(tree_height);
+    tree.set(leaf_index, id_commitment)?;
+    let merkle_proof = tree.proof(leaf_index)?;
+
+    // We build the witness
+    let x = signal_to_field(signal);
+    let rln_witness = rln_witness_from_values(identity_secret_hash, &merkle_proof, x, epoch);
+    let proof_values = proof_values_from_witness(&rln_witness).map_err(ProofError::from)?;
+
+    // We generate and verify the proof
+    let proof = generate_proof(witness_generator, proving_key, &rln_witness)?;
+
+    Ok(verify_proof(verifying_key, &proof, &proof_values)?)
+}
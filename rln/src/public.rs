@@ -1,5 +1,4 @@
 use crate::circuit::{vk_from_raw, zkey_from_raw, Curve, Fr};
-use crate::poseidon_hash::poseidon_hash;
 use crate::poseidon_tree::PoseidonTree;
 use crate::protocol::*;
 use crate::utils::*;
@@ -167,6 +166,43 @@ impl RLN<'_> {
         Ok(())
     }
 
+    /// Initializes the internal Merkle tree with a custom empty leaf value.
+    ///
+    /// Empty leaves are set to the provided value rather than to the PoseidonTree default (zero), so that
+    /// empty leaves can be distinguished from leaves holding a zero commitment, e.g. in non-membership proofs.
+    /// The empty subtree hashes are precomputed from this value.
+    ///
+    /// Input values are:
+    /// - `tree_height`: the height of the Merkle tree.
+    /// - `input_data`: a reader for the serialization of the empty leaf value (serialization done with [`rln::utils::fr_to_bytes_le`](crate::utils::fr_to_bytes_le))
+    ///
+    /// Example:
+    /// ```
+    /// use rln::protocol::hash_to_field;
+    /// use rln::utils::fr_to_bytes_le;
+    ///
+    /// let tree_height = 20;
+    /// let empty_leaf = hash_to_field(b"nothing-up-my-sleeve");
+    ///
+    /// let mut buffer = Cursor::new(fr_to_bytes_le(&empty_leaf));
+    /// rln.set_tree_with_empty_leaf(tree_height, &mut buffer).unwrap();
+    /// ```
+    pub fn set_tree_with_empty_leaf<R: Read>(
+        &mut self,
+        tree_height: usize,
+        mut input_data: R,
+    ) -> io::Result<()> {
+        // We read input
+        let mut leaf_byte: Vec<u8> = Vec::new();
+        input_data.read_to_end(&mut leaf_byte)?;
+
+        // We compute an empty tree of desired height with the given empty leaf
+        let (empty_leaf, _) = bytes_le_to_fr(&leaf_byte);
+        self.tree = PoseidonTree::new(tree_height, empty_leaf);
+
+        Ok(())
+    }
+
     /// Sets a leaf value at position index in the internal Merkle tree.
     ///
     /// Input values are:
@@ -393,7 +429,7 @@ impl RLN<'_> {
     /// use rln::protocol::*;
     ///
     /// let rln_witness = random_rln_witness(tree_height);
-    /// let proof_values = proof_values_from_witness(&rln_witness);
+    /// let proof_values = proof_values_from_witness(&rln_witness).unwrap();
     ///
     /// // We compute a Groth16 proof
     /// let mut input_buffer = Cursor::new(serialize_witness(&rln_witness));
@@ -410,7 +446,12 @@ impl RLN<'_> {
         // We read input RLN witness and we deserialize it
         let mut serialized: Vec<u8> = Vec::new();
         input_data.read_to_end(&mut serialized)?;
-        let (rln_witness, _) = deserialize_witness(&serialized);
+        let (rln_witness, _) = deserialize_witness(&serialized)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+
+        // We check that the witness was built for the tree height the circuit was compiled for
+        validate_tree_height(&rln_witness, self.tree.depth())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
 
         /*
         if self.witness_calculator.is_none() {
@@ -452,7 +493,7 @@ impl RLN<'_> {
     ///
     /// // We prepare the input to prove API, consisting of zk_proof (compressed, 4*32 bytes) || proof_values (6*32 bytes)
     /// // In this example, we compute proof values directly from witness using the utility proof_values_from_witness
-    /// let proof_values = proof_values_from_witness(&rln_witness);
+    /// let proof_values = proof_values_from_witness(&rln_witness).unwrap();
     /// let serialized_proof_values = serialize_proof_values(&proof_values);
     ///
     /// // We build the input to the verify method
@@ -472,9 +513,13 @@ impl RLN<'_> {
         // [ proof<128> | root<32> | epoch<32> | share_x<32> | share_y<32> | nullifier<32> | rln_identifier<32> ]
         let mut input_byte: Vec<u8> = Vec::new();
         input_data.read_to_end(&mut input_byte)?;
-        let proof = ArkProof::deserialize(&mut Cursor::new(&input_byte[..128].to_vec())).unwrap();
+        let proof = ArkProof::deserialize(&mut Cursor::new(
+            &input_byte[..SERIALIZED_PROOF_LEN].to_vec(),
+        ))
+        .unwrap();
 
-        let (proof_values, _) = deserialize_proof_values(&input_byte[128..].to_vec());
+        let (proof_values, _) =
+            deserialize_proof_values(&input_byte[SERIALIZED_PROOF_LEN..].to_vec());
 
         let verified = verify_proof(
             self.verification_key.as_ref().unwrap(),
@@ -519,7 +564,7 @@ impl RLN<'_> {
     /// // input_data is [ identity_secret<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]
     /// let mut serialized: Vec<u8> = Vec::new();
     /// serialized.append(&mut fr_to_bytes_le(&identity_secret_hash));
-    /// serialized.append(&mut identity_index.to_le_bytes().to_vec());
+    /// serialized.append(&mut u64::try_from(identity_index).unwrap().to_le_bytes().to_vec());
     /// serialized.append(&mut fr_to_bytes_le(&epoch));
     /// serialized.append(&mut signal_len.to_le_bytes().to_vec());
     /// serialized.append(&mut signal.to_vec());
@@ -541,8 +586,10 @@ impl RLN<'_> {
         // We read input RLN witness and we deserialize it
         let mut witness_byte: Vec<u8> = Vec::new();
         input_data.read_to_end(&mut witness_byte)?;
-        let (rln_witness, _) = proof_inputs_to_rln_witness(&mut self.tree, &witness_byte);
-        let proof_values = proof_values_from_witness(&rln_witness);
+        let (rln_witness, _) = proof_inputs_to_rln_witness(&mut self.tree, &witness_byte)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let proof_values = proof_values_from_witness(&rln_witness)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
 
         let proof = generate_proof(
             self.witness_calculator,
@@ -571,8 +618,12 @@ impl RLN<'_> {
         rln_witness_vec: Vec<u8>,
         mut output_data: W,
     ) -> io::Result<()> {
-        let (rln_witness, _) = deserialize_witness(&rln_witness_vec[..]);
-        let proof_values = proof_values_from_witness(&rln_witness);
+        let (rln_witness, _) = deserialize_witness(&rln_witness_vec[..])
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        validate_tree_height(&rln_witness, self.tree.depth())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        let proof_values = proof_values_from_witness(&rln_witness)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
 
         let proof =
             generate_proof_with_witness(calculated_witness, self.proving_key.as_ref().unwrap())
@@ -616,8 +667,11 @@ impl RLN<'_> {
         let mut serialized: Vec<u8> = Vec::new();
         input_data.read_to_end(&mut serialized)?;
         let mut all_read = 0;
-        let proof = ArkProof::deserialize(&mut Cursor::new(&serialized[..128].to_vec())).unwrap();
-        all_read += 128;
+        let proof = ArkProof::deserialize(&mut Cursor::new(
+            &serialized[..SERIALIZED_PROOF_LEN].to_vec(),
+        ))
+        .unwrap();
+        all_read += SERIALIZED_PROOF_LEN;
         let (proof_values, read) = deserialize_proof_values(&serialized[all_read..].to_vec());
         all_read += read;
 
@@ -635,7 +689,7 @@ impl RLN<'_> {
         .unwrap();
 
         // Consistency checks to counter proof tampering
-        let x = hash_to_field(&signal);
+        let x = signal_to_field(&signal);
         Ok(verified
             && (self.tree.root() == proof_values.root)
             && (x == proof_values.x)
@@ -701,8 +755,11 @@ impl RLN<'_> {
         let mut serialized: Vec<u8> = Vec::new();
         input_data.read_to_end(&mut serialized)?;
         let mut all_read = 0;
-        let proof = ArkProof::deserialize(&mut Cursor::new(&serialized[..128].to_vec())).unwrap();
-        all_read += 128;
+        let proof = ArkProof::deserialize(&mut Cursor::new(
+            &serialized[..SERIALIZED_PROOF_LEN].to_vec(),
+        ))
+        .unwrap();
+        all_read += SERIALIZED_PROOF_LEN;
         let (proof_values, read) = deserialize_proof_values(&serialized[all_read..].to_vec());
         all_read += read;
 
@@ -720,7 +777,7 @@ impl RLN<'_> {
         .unwrap();
 
         // First consistency checks to counter proof tampering
-        let x = hash_to_field(&signal);
+        let x = signal_to_field(&signal);
         let partial_result = verified
             && (x == proof_values.x)
             && (proof_values.rln_identifier == hash_to_field(RLN_IDENTIFIER));
@@ -798,7 +855,8 @@ impl RLN<'_> {
     ///
     /// The identity commitment is the Poseidon hash of the identity secret.
     ///
-    /// Generated credentials are compatible with [Semaphore](https://semaphore.appliedzkp.org/docs/guides/identities)'s credentials.
+    /// Note that [Semaphore](https://semaphore.appliedzkp.org/docs/guides/identities) hashes the identity nullifier first, so the identity secret and commitment
+    /// differ from the Semaphore ones for the same trapdoor and nullifier (see [`Identity::semaphore_secret_hash`](crate::identity::Identity::semaphore_secret_hash)).
     ///
     /// Output values are:
     /// - `output_data`: a writer receiving the serialization of the identity tapdoor, identity nullifier, identity secret and identity commitment (serialization done with `rln::utils::fr_to_bytes_le`)
@@ -870,7 +928,8 @@ impl RLN<'_> {
     ///
     /// The identity commitment is the Poseidon hash of the identity secret.
     ///
-    /// Generated credentials are compatible with [Semaphore](https://semaphore.appliedzkp.org/docs/guides/identities)'s credentials.
+    /// Note that [Semaphore](https://semaphore.appliedzkp.org/docs/guides/identities) hashes the identity nullifier first, so the identity secret and commitment
+    /// differ from the Semaphore ones for the same trapdoor and nullifier (see [`Identity::semaphore_secret_hash`](crate::identity::Identity::semaphore_secret_hash)).
     ///
     /// Input values are:
     /// - `input_data`: a reader for the byte vector containing the seed
@@ -952,35 +1011,24 @@ impl RLN<'_> {
         let mut serialized: Vec<u8> = Vec::new();
         input_proof_data_1.read_to_end(&mut serialized)?;
         // We skip deserialization of the zk-proof at the beginning
-        let (proof_values_1, _) = deserialize_proof_values(&serialized[128..].to_vec());
-        let external_nullifier_1 =
-            poseidon_hash(&[proof_values_1.epoch, proof_values_1.rln_identifier]);
+        let (proof_values_1, _) =
+            deserialize_proof_values(&serialized[SERIALIZED_PROOF_LEN..].to_vec());
 
         let mut serialized: Vec<u8> = Vec::new();
         input_proof_data_2.read_to_end(&mut serialized)?;
         // We skip deserialization of the zk-proof at the beginning
-        let (proof_values_2, _) = deserialize_proof_values(&serialized[128..].to_vec());
-        let external_nullifier_2 =
-            poseidon_hash(&[proof_values_2.epoch, proof_values_2.rln_identifier]);
+        let (proof_values_2, _) =
+            deserialize_proof_values(&serialized[SERIALIZED_PROOF_LEN..].to_vec());
 
-        // We continue only if the proof values are for the same epoch
+        // We continue only if the proof values are for the same epoch, rln_identifier and nullifier
         // The idea is that proof values that go as input to this function are verified first (with zk-proof verify), hence ensuring validity of epoch and other fields.
         // Only in case all fields are valid, an external_nullifier for the message will be stored (otherwise signal/proof will be simply discarded)
         // If the nullifier matches one already seen, we can recovery of identity secret.
-        if external_nullifier_1 == external_nullifier_2 {
-            // We extract the two shares
-            let share1 = (proof_values_1.x, proof_values_1.y);
-            let share2 = (proof_values_2.x, proof_values_2.y);
-
-            // We recover the secret
-            let recovered_identity_secret_hash =
-                compute_id_secret(share1, share2, external_nullifier_1);
-
-            // If an identity secret hash is recovered, we write it to output_data, otherwise nothing will be written.
-            if recovered_identity_secret_hash.is_ok() {
-                let identity_secret_hash = recovered_identity_secret_hash.unwrap();
-                output_data.write_all(&fr_to_bytes_le(&identity_secret_hash))?;
-            }
+        // If an identity secret hash is recovered, we write it to output_data, otherwise nothing will be written.
+        if let Ok(identity_secret_hash) =
+            recover_identity_from_proof_values(&proof_values_1, &proof_values_2)
+        {
+            output_data.write_all(&fr_to_bytes_le(&identity_secret_hash))?;
         }
 
         Ok(())
@@ -1023,14 +1071,19 @@ impl RLN<'_> {
     /// Input values are:
     /// - `input_data`: a reader for the serialization of `[ identity_secret<32> | id_index<8> | epoch<32> | signal_len<8> | signal<var> ]`
     ///
-    /// The function returns the corresponding [`RLNWitnessInput`](crate::protocol::RLNWitnessInput) object serialized using [`rln::protocol::serialize_witness`](crate::protocol::serialize_witness)).
-    pub fn get_serialized_rln_witness<R: Read>(&mut self, mut input_data: R) -> Vec<u8> {
+    /// The function returns the corresponding [`RLNWitnessInput`](crate::protocol::RLNWitnessInput) object serialized using [`rln::protocol::serialize_witness`](crate::protocol::serialize_witness)),
+    /// or an error if `input_data` is too short or `id_index` is out of the tree bounds.
+    pub fn get_serialized_rln_witness<R: Read>(
+        &mut self,
+        mut input_data: R,
+    ) -> io::Result<Vec<u8>> {
         // We read input RLN witness and we deserialize it
         let mut witness_byte: Vec<u8> = Vec::new();
-        input_data.read_to_end(&mut witness_byte).unwrap();
-        let (rln_witness, _) = proof_inputs_to_rln_witness(&mut self.tree, &witness_byte);
+        input_data.read_to_end(&mut witness_byte)?;
+        let (rln_witness, _) = proof_inputs_to_rln_witness(&mut self.tree, &witness_byte)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
 
-        serialize_witness(&rln_witness)
+        Ok(serialize_witness(&rln_witness))
     }
 
     /// Converts a byte serialization of a [`RLNWitnessInput`](crate::protocol::RLNWitnessInput) object to the corresponding JSON serialization.
@@ -1043,7 +1096,8 @@ impl RLN<'_> {
         &mut self,
         serialized_witness: &[u8],
     ) -> io::Result<serde_json::Value> {
-        let (rln_witness, _) = deserialize_witness(serialized_witness);
+        let (rln_witness, _) = deserialize_witness(serialized_witness)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
         Ok(get_json_inputs(&rln_witness))
     }
 }
@@ -1426,7 +1480,7 @@ mod test {
 
         // We double check that the proof computed from public API is correct
         let root_from_proof =
-            compute_tree_root(&id_commitment, &path_elements, &identity_path_index, false);
+            compute_tree_root(&id_commitment, &path_elements, &identity_path_index, false).unwrap();
 
         assert_eq!(root, root_from_proof);
     }
@@ -1441,7 +1495,7 @@ mod test {
 
         // Note: we only test Groth16 proof generation, so we ignore setting the tree in the RLN object
         let rln_witness = random_rln_witness(tree_height);
-        let proof_values = proof_values_from_witness(&rln_witness);
+        let proof_values = proof_values_from_witness(&rln_witness).unwrap();
 
         // We compute a Groth16 proof
         let mut input_buffer = Cursor::new(serialize_witness(&rln_witness));
@@ -1536,6 +1590,29 @@ mod test {
         assert!(verified);
     }
 
+    #[test]
+    fn test_get_serialized_rln_witness_errors() {
+        let tree_height = TEST_TREE_HEIGHT;
+
+        let input_buffer = Cursor::new(TEST_RESOURCES_FOLDER);
+        let mut rln = RLN::new(tree_height, input_buffer);
+
+        let (identity_secret_hash, _) = keygen();
+        let epoch = hash_to_field(b"test-epoch");
+
+        // An index beyond the tree capacity gives an error rather than panicking
+        let serialized =
+            prepare_prove_input(identity_secret_hash, 1 << tree_height, epoch, b"hey hey").unwrap();
+        assert!(rln.get_serialized_rln_witness(&serialized[..]).is_err());
+
+        // So does a truncated input
+        let serialized = prepare_prove_input(identity_secret_hash, 0, epoch, b"hey hey").unwrap();
+        assert!(rln
+            .get_serialized_rln_witness(&serialized[..serialized.len() - 1])
+            .is_err());
+        assert!(rln.get_serialized_rln_witness(&serialized[..]).is_ok());
+    }
+
     #[test]
     fn test_rln_with_witness() {
         let tree_height = TEST_TREE_HEIGHT;
@@ -1586,7 +1663,7 @@ mod test {
         // We read input RLN witness and we deserialize it
         let mut witness_byte: Vec<u8> = Vec::new();
         input_buffer.read_to_end(&mut witness_byte).unwrap();
-        let (rln_witness, _) = proof_inputs_to_rln_witness(&mut rln.tree, &witness_byte);
+        let (rln_witness, _) = proof_inputs_to_rln_witness(&mut rln.tree, &witness_byte).unwrap();
 
         let serialized_witness = serialize_witness(&rln_witness);
 
@@ -1,7 +1,7 @@
 // This crate provides cross-module useful utilities (mainly type conversions) not necessarily specific to RLN
 
 use crate::circuit::Fr;
-use ark_ff::PrimeField;
+use ark_ff::{FpParameters, One, PrimeField, Zero};
 use num_bigint::{BigInt, BigUint};
 use num_traits::Num;
 use std::iter::Extend;
@@ -16,6 +16,26 @@ pub fn fr_byte_size() -> usize {
     (mbs + 64 - (mbs % 64)) / 8
 }
 
+// Returns the field element 0
+pub fn fr_zero() -> Fr {
+    Fr::zero()
+}
+
+// Returns the field element 1
+pub fn fr_one() -> Fr {
+    Fr::one()
+}
+
+// Returns the field element p - 1, i.e. the largest element of the field
+pub fn fr_modulus_minus_one() -> Fr {
+    -Fr::one()
+}
+
+// Returns the field element corresponding to the input integer
+pub fn fr_from_u64(n: u64) -> Fr {
+    Fr::from(n)
+}
+
 pub fn str_to_fr(input: &str, radix: u32) -> Fr {
     assert!((radix == 10) || (radix == 16));
 
@@ -54,6 +74,9 @@ pub fn bytes_be_to_fr(input: &[u8]) -> (Fr, usize) {
     )
 }
 
+// Serializes a field element in little-endian.
+// Not constant time: the Montgomery reduction and the BigUint conversion branch on the value,
+// hence use fr_to_bytes_le_ct for secret values
 pub fn fr_to_bytes_le(input: &Fr) -> Vec<u8> {
     let input_biguint: BigUint = (*input).into();
     let mut res = input_biguint.to_bytes_le();
@@ -64,6 +87,52 @@ pub fn fr_to_bytes_le(input: &Fr) -> Vec<u8> {
     res
 }
 
+// Computes a + b * c + carry, returning the result and the carry
+#[inline(always)]
+fn mac(a: u64, b: u64, c: u64, carry: u64) -> (u64, u64) {
+    let tmp = (a as u128) + (b as u128) * (c as u128) + (carry as u128);
+    (tmp as u64, (tmp >> 64) as u64)
+}
+
+// Serializes a field element in little-endian with no value-dependent branches or memory accesses.
+// This is the only constant time serializer in this module, and it should be used for secret values (e.g. identity secrets).
+// The Montgomery representation is converted to canonical form with a Montgomery reduction whose final
+// subtraction is selected with a mask, rather than a branch as in arkworks into_repr.
+// Outputs the same bytes as fr_to_bytes_le
+pub fn fr_to_bytes_le_ct(input: &Fr) -> Vec<u8> {
+    let modulus = <<Fr as PrimeField>::Params as FpParameters>::MODULUS.0;
+    let inv = <<Fr as PrimeField>::Params as FpParameters>::INV;
+
+    // We compute input * R^-1 mod p, i.e. the canonical representation, one limb at a time
+    let mut r = (input.0).0;
+    for _ in 0..r.len() {
+        let m = r[0].wrapping_mul(inv);
+        let (_, mut carry) = mac(r[0], m, modulus[0], 0);
+        for j in 1..r.len() {
+            let (limb, c) = mac(r[j], m, modulus[j], carry);
+            r[j - 1] = limb;
+            carry = c;
+        }
+        r[r.len() - 1] = carry;
+    }
+
+    // The result is in [0, 2p), so we conditionally subtract p using the final borrow as mask
+    let mut reduced = [0u64; 4];
+    let mut borrow = 0u64;
+    for ((reduced_limb, r_limb), modulus_limb) in reduced.iter_mut().zip(r).zip(modulus) {
+        let tmp = (r_limb as u128).wrapping_sub((modulus_limb as u128) + (borrow as u128));
+        *reduced_limb = tmp as u64;
+        borrow = ((tmp >> 64) as u64) & 1;
+    }
+    // mask is all ones if r < p, i.e. if the subtraction underflowed
+    let mask = 0u64.wrapping_sub(borrow);
+
+    r.iter()
+        .zip(reduced.iter())
+        .flat_map(|(r_limb, reduced_limb)| ((r_limb & mask) | (reduced_limb & !mask)).to_le_bytes())
+        .collect()
+}
+
 pub fn fr_to_bytes_be(input: &Fr) -> Vec<u8> {
     let input_biguint: BigUint = (*input).into();
     let mut res = input_biguint.to_bytes_be();
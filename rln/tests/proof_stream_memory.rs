@@ -0,0 +1,127 @@
+// This test checks the memory contract of verify_proof_stream, i.e. that memory usage doesn't depend on the number of records.
+// It lives in its own test binary since it installs a global allocator counting the bytes allocated by the whole process
+
+use ark_serialize::CanonicalSerialize;
+use rln::circuit::{
+    circom_from_folder, vk_from_folder, zkey_from_folder, TEST_RESOURCES_FOLDER, TEST_TREE_HEIGHT,
+};
+use rln::protocol::*;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::Read;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// The number of bytes currently allocated, and the largest value it took since the last reset
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+            PEAK.fetch_max(current, Ordering::SeqCst);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Resets the peak to the currently allocated bytes, which are returned
+fn reset_peak() -> usize {
+    let current = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(current, Ordering::SeqCst);
+    current
+}
+
+// A synthetic stream repeating the same record, generated on the fly so that the stream itself is never held in memory
+struct RepeatedRecords<'a> {
+    record: &'a [u8],
+    records_left: usize,
+    position: usize,
+}
+
+impl Read for RepeatedRecords<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.records_left == 0 {
+            return Ok(0);
+        }
+        let read = buf.len().min(self.record.len() - self.position);
+        buf[..read].copy_from_slice(&self.record[self.position..self.position + read]);
+        self.position += read;
+        if self.position == self.record.len() {
+            self.position = 0;
+            self.records_left -= 1;
+        }
+        Ok(read)
+    }
+}
+
+#[test]
+fn test_verify_proof_stream_memory() {
+    // Allocations of the verifier may vary slightly between records, e.g. because of deferred frees in the rayon thread pool,
+    // but never by the size of the records verified so far
+    const TOLERANCE: usize = 64 * 1024;
+
+    let rln_witness = random_rln_witness(TEST_TREE_HEIGHT);
+    let proof_values = proof_values_from_witness(&rln_witness).unwrap();
+
+    let proving_key = zkey_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+    let verification_key = vk_from_folder(TEST_RESOURCES_FOLDER).unwrap();
+    let builder = circom_from_folder(TEST_RESOURCES_FOLDER);
+    let proof = generate_proof(builder, &proving_key, &rln_witness).unwrap();
+    drop(proving_key);
+
+    let mut record: Vec<u8> = Vec::new();
+    proof.serialize(&mut record).unwrap();
+    record.append(&mut serialize_proof_values(&proof_values));
+
+    // Returns the peak of allocated bytes over the verification of a stream of stream_len records,
+    // checking that the allocated bytes after each verified record don't grow
+    let peak_for_stream = |stream_len: usize| -> usize {
+        let stream = RepeatedRecords {
+            record: &record,
+            records_left: stream_len,
+            position: 0,
+        };
+
+        let baseline = reset_peak();
+        let mut verifier = verify_proof_stream(&verification_key, stream);
+
+        let mut live_after_first = None;
+        let mut verified_count = 0;
+        for verified in verifier.by_ref() {
+            assert!(verified.unwrap());
+            verified_count += 1;
+
+            let live = CURRENT.load(Ordering::SeqCst);
+            let live_after_first = *live_after_first.get_or_insert(live);
+            assert!(
+                live <= live_after_first + TOLERANCE,
+                "{live} bytes allocated after {verified_count} records, {live_after_first} after the first one"
+            );
+        }
+        assert_eq!(verified_count, stream_len);
+        drop(verifier);
+
+        PEAK.load(Ordering::SeqCst) - baseline
+    };
+
+    // A first run warms up lazily allocated state, e.g. the rayon thread pool
+    peak_for_stream(1);
+
+    let short_stream_peak = peak_for_stream(2);
+    let long_stream_peak = peak_for_stream(32);
+    assert!(
+        long_stream_peak <= short_stream_peak + TOLERANCE,
+        "peak of {long_stream_peak} bytes for 32 records, {short_stream_peak} for 2 records"
+    );
+}
@@ -36,6 +36,26 @@ pub trait Hasher {
     fn hash(input: &[Self::Fr]) -> Self::Fr;
 }
 
+/// Errors returned when building a Merkle tree
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum TreeError {
+    /// More leaves than the tree capacity were provided
+    TooManyLeaves { leaves: usize, capacity: usize },
+}
+
+impl std::fmt::Display for TreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeError::TooManyLeaves { leaves, capacity } => write!(
+                f,
+                "{leaves} leaves exceed the tree capacity of {capacity} leaves"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TreeError {}
+
 ////////////////////////////////////////////////////////////
 /// Optimal Merkle Tree Implementation
 ////////////////////////////////////////////////////////////
@@ -92,6 +112,20 @@ impl<H: Hasher> OptimalMerkleTree<H> {
         }
     }
 
+    /// Creates a new `MerkleTree` of the given depth with leaves set from index 0, the remaining leaves being default
+    pub fn from_leaves(depth: usize, leaves: &[H::Fr]) -> Result<Self, TreeError> {
+        let mut tree = Self::default(depth);
+        if leaves.len() > tree.capacity() {
+            return Err(TreeError::TooManyLeaves {
+                leaves: leaves.len(),
+                capacity: tree.capacity(),
+            });
+        }
+        tree.set_range(0, leaves.iter().copied())
+            .expect("leaves fit in the tree");
+        Ok(tree)
+    }
+
     // Returns the depth of the tree
     pub fn depth(&self) -> usize {
         self.depth
@@ -107,12 +141,42 @@ impl<H: Hasher> OptimalMerkleTree<H> {
         self.next_index
     }
 
+    // Returns the value of empty leaves, i.e. the default leaf the tree was created with
+    pub fn default_leaf(&self) -> H::Fr {
+        self.cached_nodes[self.depth]
+    }
+
     #[must_use]
-    // Returns the root of the tree
+    // Returns the root of the tree.
+    // The root is kept up to date at each leaf update, so this is a constant time lookup
     pub fn root(&self) -> H::Fr {
         self.get_node(0, 0)
     }
 
+    // Recomputes the root from the set leaves only, i.e. without using the stored intermediate nodes.
+    // A mismatch with root() indicates a corrupted tree
+    pub fn recompute_root(&self) -> H::Fr {
+        let mut nodes: Vec<H::Fr> = (0..self.next_index).map(|i| self.get_leaf(i)).collect();
+        for depth in (0..self.depth).rev() {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| {
+                    H::hash(&[
+                        pair[0],
+                        *pair.get(1).unwrap_or(&self.cached_nodes[depth + 1]),
+                    ])
+                })
+                .collect();
+        }
+        nodes.first().copied().unwrap_or(self.cached_nodes[0])
+    }
+
+    // Overwrites the stored root without updating the leaves, so that dependent crates can test their integrity checks
+    #[cfg(feature = "test-hooks")]
+    pub fn corrupt_root(&mut self, root: H::Fr) {
+        self.nodes.insert((0, 0), root);
+    }
+
     // Sets a leaf at the specified tree index
     pub fn set(&mut self, index: usize, leaf: H::Fr) -> io::Result<()> {
         if index >= self.capacity() {
@@ -149,21 +213,95 @@ impl<H: Hasher> OptimalMerkleTree<H> {
         Ok(())
     }
 
+    // Sets multiple leaves from the specified tree index, returning the root after each insertion.
+    // Nodes are recomputed level by level (see tracked_updates), so that the internal nodes shared by
+    // consecutive leaves are stored once per batch rather than once per leaf.
+    // The range is checked before any leaf is set, so that the tree is left untouched on error
+    pub fn set_leaves_tracked<I: IntoIterator<Item = H::Fr>>(
+        &mut self,
+        start: usize,
+        leaves: I,
+    ) -> io::Result<Vec<H::Fr>> {
+        let leaves = leaves.into_iter().collect::<Vec<_>>();
+        if start
+            .checked_add(leaves.len())
+            .map_or(true, |end| end > self.capacity())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "provided range exceeds set size",
+            ));
+        }
+        let (roots, updates) = tracked_updates::<H>(self.depth, start, &leaves, |level, index| {
+            self.get_node(level, index)
+        });
+        for (level, index, node) in updates {
+            self.nodes.insert((level, index), node);
+        }
+        self.next_index = max(self.next_index, start + leaves.len());
+        Ok(roots)
+    }
+
+    // Sets a leaf at the specified tree index, returning the new root and the Merkle proof for that index.
+    // The proof is built from the siblings used to recompute the path to the root, so this is a single pass
+    pub fn update_leaf(
+        &mut self,
+        index: usize,
+        leaf: H::Fr,
+    ) -> io::Result<(H::Fr, OptimalMerkleProof<H>)> {
+        if index >= self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds set size",
+            ));
+        }
+        self.nodes.insert((self.depth, index), leaf);
+        let mut witness = Vec::<(H::Fr, u8)>::with_capacity(self.depth);
+        let mut node = leaf;
+        let mut i = index;
+        for depth in (1..=self.depth).rev() {
+            let sibling = self.get_node(depth, i ^ 1);
+            if i & 1 == 0 {
+                witness.push((sibling, 0));
+                node = H::hash(&[node, sibling]);
+            } else {
+                witness.push((sibling, 1));
+                node = H::hash(&[sibling, node]);
+            }
+            i >>= 1;
+            self.nodes.insert((depth - 1, i), node);
+        }
+        self.next_index = max(self.next_index, index + 1);
+        Ok((node, OptimalMerkleProof(witness)))
+    }
+
     // Sets a leaf at the next available index
     pub fn update_next(&mut self, leaf: H::Fr) -> io::Result<()> {
         self.set(self.next_index, leaf)?;
         Ok(())
     }
 
-    // Deletes a leaf at a certain index by setting it to its default value (next_index is not updated)
+    // Deletes a leaf at a certain index by setting it to the tree default leaf (next_index is not updated)
     pub fn delete(&mut self, index: usize) -> io::Result<()> {
         // We reset the leaf only if we previously set a leaf at that index
         if index < self.next_index {
-            self.set(index, H::default_leaf())?;
+            self.set(index, self.cached_nodes[self.depth])?;
         }
         Ok(())
     }
 
+    // Returns true if the leaf at the specified index is occupied, i.e. if it differs from the tree default leaf.
+    // Deleted leaves are not occupied
+    pub fn is_occupied(&self, index: usize) -> io::Result<bool> {
+        if index >= self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds set size",
+            ));
+        }
+        Ok(self.get_leaf(index) != self.cached_nodes[self.depth])
+    }
+
     // Computes a merkle proof the the leaf at the specified index
     pub fn proof(&self, index: usize) -> io::Result<OptimalMerkleProof<H>> {
         if index >= self.capacity() {
@@ -365,6 +503,20 @@ impl<H: Hasher> FullMerkleTree<H> {
         }
     }
 
+    /// Creates a new `MerkleTree` of the given depth with leaves set from index 0, the remaining leaves being default
+    pub fn from_leaves(depth: usize, leaves: &[H::Fr]) -> Result<Self, TreeError> {
+        let mut tree = Self::default(depth);
+        if leaves.len() > tree.capacity() {
+            return Err(TreeError::TooManyLeaves {
+                leaves: leaves.len(),
+                capacity: tree.capacity(),
+            });
+        }
+        tree.set_range(0, leaves.iter().copied())
+            .expect("leaves fit in the tree");
+        Ok(tree)
+    }
+
     // Returns the depth of the tree
     pub fn depth(&self) -> usize {
         self.depth
@@ -380,12 +532,41 @@ impl<H: Hasher> FullMerkleTree<H> {
         self.next_index
     }
 
+    // Returns the value of empty leaves, i.e. the default leaf the tree was created with
+    pub fn default_leaf(&self) -> H::Fr {
+        self.cached_nodes[0]
+    }
+
     #[must_use]
-    // Returns the root of the tree
+    // Returns the root of the tree.
+    // The root is kept up to date at each leaf update, so this is a constant time lookup
     pub fn root(&self) -> H::Fr {
         self.nodes[0]
     }
 
+    // Recomputes the root from the set leaves only, i.e. without using the stored intermediate nodes.
+    // A mismatch with root() indicates a corrupted tree
+    pub fn recompute_root(&self) -> H::Fr {
+        let first_leaf = self.capacity() - 1;
+        let mut nodes: Vec<H::Fr> = self.nodes[first_leaf..first_leaf + self.next_index].to_vec();
+        for level in 0..self.depth {
+            nodes = nodes
+                .chunks(2)
+                .map(|pair| H::hash(&[pair[0], *pair.get(1).unwrap_or(&self.cached_nodes[level])]))
+                .collect();
+        }
+        nodes
+            .first()
+            .copied()
+            .unwrap_or(self.cached_nodes[self.depth])
+    }
+
+    // Overwrites the stored root without updating the leaves, so that dependent crates can test their integrity checks
+    #[cfg(feature = "test-hooks")]
+    pub fn corrupt_root(&mut self, root: H::Fr) {
+        self.nodes[0] = root;
+    }
+
     // Sets a leaf at the specified tree index
     pub fn set(&mut self, leaf: usize, hash: H::Fr) -> io::Result<()> {
         self.set_range(leaf, once(hash))?;
@@ -422,21 +603,98 @@ impl<H: Hasher> FullMerkleTree<H> {
         Ok(())
     }
 
+    // Sets multiple leaves from the specified tree index, returning the root after each insertion.
+    // Nodes are recomputed level by level (see tracked_updates), so that the internal nodes shared by
+    // consecutive leaves are stored once per batch rather than once per leaf.
+    // The range is checked before any leaf is set, so that the tree is left untouched on error
+    pub fn set_leaves_tracked<I: IntoIterator<Item = H::Fr>>(
+        &mut self,
+        start: usize,
+        leaves: I,
+    ) -> io::Result<Vec<H::Fr>> {
+        let leaves = leaves.into_iter().collect::<Vec<_>>();
+        if start
+            .checked_add(leaves.len())
+            .map_or(true, |end| end > self.capacity())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "provided range exceeds set size",
+            ));
+        }
+        let (roots, updates) = tracked_updates::<H>(self.depth, start, &leaves, |level, index| {
+            self.nodes[(1 << level) - 1 + index]
+        });
+        for (level, index, node) in updates {
+            self.nodes[(1 << level) - 1 + index] = node;
+        }
+        self.next_index = max(self.next_index, start + leaves.len());
+        Ok(roots)
+    }
+
+    // Sets a leaf at the specified tree index, returning the new root and the Merkle proof for that index.
+    // The proof is built from the siblings used to recompute the path to the root, so this is a single pass
+    pub fn update_leaf(
+        &mut self,
+        leaf: usize,
+        hash: H::Fr,
+    ) -> io::Result<(H::Fr, FullMerkleProof<H>)> {
+        if leaf >= self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds set size",
+            ));
+        }
+        let mut index = self.capacity() + leaf - 1;
+        self.nodes[index] = hash;
+        let mut path = Vec::with_capacity(self.depth);
+        while let Some(parent) = self.parent(index) {
+            let (branch, parent_hash) = match index & 1 {
+                1 => (
+                    FullMerkleBranch::Left(self.nodes[index + 1]),
+                    H::hash(&[self.nodes[index], self.nodes[index + 1]]),
+                ),
+                0 => (
+                    FullMerkleBranch::Right(self.nodes[index - 1]),
+                    H::hash(&[self.nodes[index - 1], self.nodes[index]]),
+                ),
+                _ => unreachable!(),
+            };
+            self.nodes[parent] = parent_hash;
+            path.push(branch);
+            index = parent;
+        }
+        self.next_index = max(self.next_index, leaf + 1);
+        Ok((self.root(), FullMerkleProof(path)))
+    }
+
     // Sets a leaf at the next available index
     pub fn update_next(&mut self, leaf: H::Fr) -> io::Result<()> {
         self.set(self.next_index, leaf)?;
         Ok(())
     }
 
-    // Deletes a leaf at a certain index by setting it to its default value (next_index is not updated)
+    // Deletes a leaf at a certain index by setting it to the tree default leaf (next_index is not updated)
     pub fn delete(&mut self, index: usize) -> io::Result<()> {
         // We reset the leaf only if we previously set a leaf at that index
         if index < self.next_index {
-            self.set(index, H::default_leaf())?;
+            self.set(index, self.cached_nodes[0])?;
         }
         Ok(())
     }
 
+    // Returns true if the leaf at the specified index is occupied, i.e. if it differs from the tree default leaf.
+    // Deleted leaves are not occupied
+    pub fn is_occupied(&self, index: usize) -> io::Result<bool> {
+        if index >= self.capacity() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "index exceeds set size",
+            ));
+        }
+        Ok(self.nodes[self.capacity() + index - 1] != self.cached_nodes[0])
+    }
+
     // Computes a merkle proof the the leaf at the specified index
     pub fn proof(&self, leaf: usize) -> io::Result<FullMerkleProof<H>> {
         if leaf >= self.capacity() {
@@ -573,6 +831,62 @@ where
     }
 }
 
+////////////////////////////////////////////////////////////
+/// Batch updates
+////////////////////////////////////////////////////////////
+
+// Computes the roots taken by a tree of the given depth when leaves are set one after the other from index start,
+// together with the final value of each node changed by the batch as (level, index, node), level 0 being the root level.
+// get_node returns the node value before the batch at the given level and index.
+// Each insertion changes every node on the path from its leaf to the root, so roots cost depth hashes per leaf;
+// nodes are however computed one level at a time from the changes of the level below, so that each changed node
+// is returned only once, no matter how many consecutive leaves share it
+fn tracked_updates<H: Hasher>(
+    depth: usize,
+    start: usize,
+    leaves: &[H::Fr],
+    get_node: impl Fn(usize, usize) -> H::Fr,
+) -> (Vec<H::Fr>, Vec<(usize, usize, H::Fr)>) {
+    // The changes at the current level, one per insertion and in insertion order
+    let mut changes: Vec<(usize, H::Fr)> = leaves
+        .iter()
+        .enumerate()
+        .map(|(i, leaf)| (start + i, *leaf))
+        .collect();
+    let mut updates = Vec::new();
+
+    for level in (1..=depth).rev() {
+        // The nodes of this level changed so far, the other ones keeping their value before the batch
+        let mut current: HashMap<usize, H::Fr> = HashMap::new();
+        let mut parent_changes = Vec::with_capacity(changes.len());
+        for (index, node) in changes {
+            current.insert(index, node);
+            let sibling = current
+                .get(&(index ^ 1))
+                .copied()
+                .unwrap_or_else(|| get_node(level, index ^ 1));
+            let parent = if index & 1 == 0 {
+                H::hash(&[node, sibling])
+            } else {
+                H::hash(&[sibling, node])
+            };
+            parent_changes.push((index >> 1, parent));
+        }
+        updates.extend(
+            current
+                .into_iter()
+                .map(|(index, node)| (level, index, node)),
+        );
+        changes = parent_changes;
+    }
+
+    let roots: Vec<H::Fr> = changes.into_iter().map(|(_, root)| root).collect();
+    if let Some(root) = roots.last() {
+        updates.push((0, 0, *root));
+    }
+    (roots, updates)
+}
+
 ////////////////////////////////////////////////////////////
 /// Tests
 ////////////////////////////////////////////////////////////
@@ -695,4 +1009,126 @@ mod test {
                 .unwrap());
         }
     }
+
+    const LEAVES: [[u8; 32]; 3] = [
+        hex!("0000000000000000000000000000000000000000000000000000000000000001"),
+        hex!("0000000000000000000000000000000000000000000000000000000000000002"),
+        hex!("0000000000000000000000000000000000000000000000000000000000000003"),
+    ];
+
+    // Runs the body once with $tree as FullMerkleTree<Keccak256> and once as OptimalMerkleTree<Keccak256>,
+    // for tests of the API implemented by both trees
+    macro_rules! for_each_tree {
+        ($tree:ident => $body:block) => {{
+            {
+                type $tree = FullMerkleTree<Keccak256>;
+                $body
+            }
+            {
+                type $tree = OptimalMerkleTree<Keccak256>;
+                $body
+            }
+        }};
+    }
+
+    #[test]
+    fn test_recompute_root() {
+        for_each_tree!(Tree => {
+            let mut tree = Tree::new(3, [0; 32]);
+            assert_eq!(tree.recompute_root(), tree.root());
+            for (i, leaf) in LEAVES.into_iter().enumerate() {
+                tree.set(i, leaf).unwrap();
+                assert_eq!(tree.recompute_root(), tree.root());
+            }
+        });
+
+        // We corrupt the stored root
+        let corrupted_root =
+            hex!("00000000000000000000000000000000000000000000000000000000000000ff");
+
+        let mut tree = FullMerkleTree::<Keccak256>::from_leaves(3, &LEAVES).unwrap();
+        tree.nodes[0] = corrupted_root;
+        assert_ne!(tree.recompute_root(), tree.root());
+
+        let mut tree = OptimalMerkleTree::<Keccak256>::from_leaves(3, &LEAVES).unwrap();
+        tree.nodes.insert((0, 0), corrupted_root);
+        assert_ne!(tree.recompute_root(), tree.root());
+    }
+
+    #[test]
+    fn test_delete_and_is_occupied() {
+        let leaf = LEAVES[0];
+        let default_leaf = hex!("00000000000000000000000000000000000000000000000000000000000000ff");
+
+        for_each_tree!(Tree => {
+            let mut tree = Tree::new(2, default_leaf);
+            let empty_root = tree.root();
+            assert!(!tree.is_occupied(1).unwrap());
+            tree.set(1, leaf).unwrap();
+            assert!(tree.is_occupied(1).unwrap());
+            let proof = tree.proof(1).unwrap();
+            assert!(tree.verify(&leaf, &proof).unwrap());
+            tree.delete(1).unwrap();
+            assert!(!tree.is_occupied(1).unwrap());
+            assert!(!tree.verify(&leaf, &proof).unwrap());
+            assert_eq!(tree.root(), empty_root);
+            assert!(tree.is_occupied(4).is_err());
+            assert_eq!(tree.default_leaf(), default_leaf);
+            assert_eq!(Tree::default(2).default_leaf(), Keccak256::default_leaf());
+        });
+    }
+
+    #[test]
+    fn test_from_leaves() {
+        for_each_tree!(Tree => {
+            let tree = Tree::from_leaves(2, &LEAVES).unwrap();
+            let mut expected_tree = Tree::default(2);
+            for (i, leaf) in LEAVES.into_iter().enumerate() {
+                expected_tree.set(i, leaf).unwrap();
+            }
+            assert_eq!(tree, expected_tree);
+            assert_eq!(
+                Tree::from_leaves(1, &LEAVES),
+                Err(TreeError::TooManyLeaves {
+                    leaves: 3,
+                    capacity: 2
+                })
+            );
+        });
+    }
+
+    #[test]
+    fn test_set_leaves_tracked() {
+        for_each_tree!(Tree => {
+            let mut tree = Tree::new(2, [0; 32]);
+            let mut expected_tree = tree.clone();
+            let roots = tree.set_leaves_tracked(1, LEAVES).unwrap();
+            assert_eq!(roots.len(), LEAVES.len());
+            for (i, leaf) in LEAVES.into_iter().enumerate() {
+                expected_tree.set(1 + i, leaf).unwrap();
+                assert_eq!(roots[i], expected_tree.root());
+            }
+            assert_eq!(tree, expected_tree);
+            assert!(tree.set_leaves_tracked(2, LEAVES).is_err());
+            assert!(tree.set_leaves_tracked(usize::MAX, LEAVES).is_err());
+            assert_eq!(tree, expected_tree);
+        });
+    }
+
+    #[test]
+    fn test_update_leaf() {
+        for_each_tree!(Tree => {
+            let mut tree = Tree::new(2, [0; 32]);
+            let mut expected_tree = tree.clone();
+            for (index, leaf) in [3, 0, 1].into_iter().zip(LEAVES) {
+                let (root, proof) = tree.update_leaf(index, leaf).unwrap();
+                expected_tree.set(index, leaf).unwrap();
+                assert_eq!(root, expected_tree.root());
+                assert!(proof == expected_tree.proof(index).unwrap());
+                assert!(tree.verify(&leaf, &proof).unwrap());
+            }
+            assert_eq!(tree, expected_tree);
+            assert!(tree.update_leaf(4, LEAVES[0]).is_err());
+        });
+    }
 }